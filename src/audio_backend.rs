@@ -0,0 +1,406 @@
+//! Where the real time engines send their samples to.
+//!
+//! Playing goes through an [AudioBackend], which pulls the samples the engine generates at its
+//! own pace. [CpalBackend] plays them through a sound card, while [NullBackend] and [FileBackend]
+//! need no audio device at all: they pull the samples on a simulated clock, either discarding
+//! them or writing them to a wav file. As they never miss a sample, the playback of the headless
+//! backends is deterministic, which makes them suitable for tests.
+//! # Example
+//! ```no_run
+//! use lion_synth::audio_backend::FileBackend;
+//! use lion_synth::RealTimeEngine;
+//!
+//! let engine = RealTimeEngine::from_yaml("fm.yaml", 44100).unwrap();
+//! let mut backend = FileBackend::new("exports/fm_live.wav");
+//! engine.play_with_backend(2000, &mut backend).unwrap();
+//! ```
+
+use crate::back_end::{open_output_stream, OutputConfig, OutputError};
+use crate::wav_export::{ExportError, WavEncoder, WavExport};
+use cpal::traits::StreamTrait;
+use cpal::Stream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Default amount of frames pulled at once by the headless backends.
+pub const DEFAULT_BLOCK_SIZE: usize = 512;
+
+/// Gives the next sample generated by the engine, or `None` if it is not ready yet.
+pub type SampleSource = Box<dyn FnMut() -> Option<f32> + Send>;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum BackendError {
+    #[error("The backend is already playing")]
+    AlreadyStarted,
+    #[error(transparent)]
+    Output(#[from] OutputError),
+    #[error("Could not start the output stream: {0}")]
+    PlayStream(#[from] cpal::PlayStreamError),
+    #[error(transparent)]
+    Export(#[from] ExportError),
+    #[error("The playback thread stopped unexpectedly")]
+    ThreadPanicked,
+}
+
+/// Something the samples of a real time engine are played through.
+///
+/// The engine calls [start](AudioBackend::start) once it is ready, then keeps generating samples
+/// for the source until the end of the playback, when it calls [stop](AudioBackend::stop). The
+/// samples are mono, backends with more channels copy them to every channel.
+pub trait AudioBackend {
+    /// Starts pulling samples from the source, which delivers them at the given sample rate.
+    fn start(&mut self, sample_rate: i32, source: SampleSource) -> Result<(), BackendError>;
+    /// Stops pulling samples. Backends which never miss a sample pull the ones left first.
+    fn stop(&mut self) -> Result<(), BackendError>;
+}
+
+/// Plays the samples through a sound card. See [OutputConfig] for choosing it.
+#[derive(Default)]
+pub struct CpalBackend {
+    output: OutputConfig,
+    stream: Option<Stream>,
+}
+
+impl CpalBackend {
+    pub fn new(output: OutputConfig) -> Self {
+        Self {
+            output,
+            stream: None,
+        }
+    }
+}
+
+impl AudioBackend for CpalBackend {
+    fn start(&mut self, sample_rate: i32, mut source: SampleSource) -> Result<(), BackendError> {
+        if self.stream.is_some() {
+            return Err(BackendError::AlreadyStarted);
+        }
+
+        // Unwrap or silence
+        let stream =
+            open_output_stream(&self.output, sample_rate, move || source().unwrap_or(0.0))?;
+        stream.play()?;
+        self.stream = Some(stream);
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), BackendError> {
+        // Dropping the stream closes it, whatever is left in the source is not played
+        self.stream.take();
+        Ok(())
+    }
+}
+
+/// Pulls the samples and discards them. By default the samples are pulled as fast as the engine
+/// delivers them, [with_real_time](NullBackend::with_real_time) paces them like a sound card.
+/// The [frames](NullBackend::frames) pulled tell the time elapsed in the simulated clock.
+#[derive(Default)]
+pub struct NullBackend {
+    puller: Puller,
+}
+
+impl NullBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the amount of frames pulled at once, as a sound card would ask for.
+    pub fn with_block_size(mut self, frames: usize) -> Self {
+        self.puller.block_size = frames.max(1);
+        self
+    }
+
+    /// Waits the duration of every block before pulling the next one, so the samples are pulled
+    /// at the pace of a sound card.
+    pub fn with_real_time(mut self, real_time: bool) -> Self {
+        self.puller.real_time = real_time;
+        self
+    }
+
+    /// Amount of frames pulled since the start of the playback.
+    pub fn frames(&self) -> u64 {
+        self.puller.frames()
+    }
+
+    /// Time elapsed in the simulated clock since the start of the playback.
+    pub fn elapsed(&self) -> Duration {
+        self.puller.elapsed()
+    }
+}
+
+impl AudioBackend for NullBackend {
+    fn start(&mut self, sample_rate: i32, source: SampleSource) -> Result<(), BackendError> {
+        self.puller.start(sample_rate, source, Sink::Discard)
+    }
+
+    fn stop(&mut self) -> Result<(), BackendError> {
+        self.puller.stop()
+    }
+}
+
+/// Pulls the samples like the [NullBackend], writing whatever would have been played to a wav
+/// file, mono and 16 bits unless told otherwise.
+pub struct FileBackend {
+    path: PathBuf,
+    export: WavExport,
+    puller: Puller,
+}
+
+impl FileBackend {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            export: WavExport::default(),
+            puller: Puller::default(),
+        }
+    }
+
+    /// Sets how the file is written. As the samples are written while playing, the normalisation
+    /// is ignored.
+    pub fn with_export(mut self, export: WavExport) -> Self {
+        self.export = export;
+        self
+    }
+
+    /// Sets the amount of frames pulled at once, as a sound card would ask for.
+    pub fn with_block_size(mut self, frames: usize) -> Self {
+        self.puller.block_size = frames.max(1);
+        self
+    }
+
+    /// Waits the duration of every block before pulling the next one, so the samples are pulled
+    /// at the pace of a sound card.
+    pub fn with_real_time(mut self, real_time: bool) -> Self {
+        self.puller.real_time = real_time;
+        self
+    }
+
+    /// Amount of frames written since the start of the playback.
+    pub fn frames(&self) -> u64 {
+        self.puller.frames()
+    }
+}
+
+impl AudioBackend for FileBackend {
+    fn start(&mut self, sample_rate: i32, source: SampleSource) -> Result<(), BackendError> {
+        if self.puller.is_running() {
+            return Err(BackendError::AlreadyStarted);
+        }
+
+        let encoder = self.export.encoder(&self.path, sample_rate, 1.0)?;
+        self.puller.start(sample_rate, source, Sink::Wav(encoder))
+    }
+
+    fn stop(&mut self) -> Result<(), BackendError> {
+        self.puller.stop()
+    }
+}
+
+/// Where the headless backends send the samples they pull.
+enum Sink {
+    Discard,
+    Wav(WavEncoder),
+}
+
+impl Sink {
+    fn write(&mut self, sample: f32) -> Result<(), BackendError> {
+        if let Sink::Wav(encoder) = self {
+            encoder.write_mono(&[sample])?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), BackendError> {
+        if let Sink::Wav(encoder) = self {
+            encoder.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+/// The simulated sound card of the headless backends: a thread pulling blocks of samples, waiting
+/// for the engine whenever it is late instead of playing silence.
+struct Puller {
+    block_size: usize,
+    real_time: bool,
+    sample_rate: i32,
+    frames: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), BackendError>>>,
+}
+
+impl Default for Puller {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            real_time: false,
+            sample_rate: 0,
+            frames: Arc::new(AtomicU64::new(0)),
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl Puller {
+    fn is_running(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Acquire)
+    }
+
+    fn elapsed(&self) -> Duration {
+        if self.sample_rate <= 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate as f64)
+    }
+
+    fn start(
+        &mut self,
+        sample_rate: i32,
+        mut source: SampleSource,
+        mut sink: Sink,
+    ) -> Result<(), BackendError> {
+        if self.is_running() {
+            return Err(BackendError::AlreadyStarted);
+        }
+
+        self.sample_rate = sample_rate;
+        self.frames.store(0, Ordering::Release);
+        self.stop.store(false, Ordering::Release);
+
+        let block_size = self.block_size;
+        let period = match self.real_time {
+            true => Some(Duration::from_secs_f64(
+                block_size as f64 / sample_rate as f64,
+            )),
+            false => None,
+        };
+        let frames = Arc::clone(&self.frames);
+        let stop = Arc::clone(&self.stop);
+
+        self.thread = Some(thread::spawn(move || {
+            'playing: loop {
+                for _ in 0..block_size {
+                    let sample = loop {
+                        match source() {
+                            Some(sample) => break sample,
+                            // Only stops once the source is drained
+                            None if stop.load(Ordering::Acquire) => break 'playing,
+                            None => thread::yield_now(),
+                        }
+                    };
+                    sink.write(sample)?;
+                    frames.fetch_add(1, Ordering::AcqRel);
+                }
+                if let Some(period) = period {
+                    thread::sleep(period);
+                }
+            }
+            sink.finish()
+        }));
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), BackendError> {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+
+        self.stop.store(true, Ordering::Release);
+        thread.join().map_err(|_| BackendError::ThreadPanicked)?
+    }
+}
+
+impl Drop for Puller {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout_yaml::{Layout, RealTimeEngine};
+
+    const LAYOUT: &str = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: oscillator
+      os-out: true
+      config:
+        frequency: 440.0
+";
+
+    fn engine() -> RealTimeEngine {
+        RealTimeEngine::from_layout(&Layout::from_document(LAYOUT), 44100, &Default::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_null_backend() {
+        let mut backend = NullBackend::new().with_block_size(64);
+        engine().play_with_backend(100, &mut backend).unwrap();
+
+        // Every sample generated is pulled, even the ones of an incomplete block
+        assert_eq!(backend.frames(), 4410);
+        assert_eq!(backend.elapsed(), Duration::from_millis(100));
+
+        // Stopping twice does nothing
+        backend.stop().unwrap();
+    }
+
+    #[test]
+    fn test_already_started() {
+        let mut backend = NullBackend::new();
+        backend.start(44100, Box::new(|| None)).unwrap();
+        assert!(matches!(
+            backend.start(44100, Box::new(|| None)),
+            Err(BackendError::AlreadyStarted)
+        ));
+        backend.stop().unwrap();
+    }
+
+    #[test]
+    fn test_file_backend() {
+        let path =
+            std::env::temp_dir().join(format!("lion_synth_backend_{}.wav", std::process::id()));
+        let mut backend = FileBackend::new(&path).with_block_size(100);
+        engine().play_with_backend(50, &mut backend).unwrap();
+        assert_eq!(backend.frames(), 2205);
+
+        // The file holds what the engine generates, tick after tick
+        let mut expected = engine();
+        expected.get_mut_coordinator().reset();
+        expected.get_mut_coordinator().get_mut_transport().start();
+        let expected: Vec<f32> = (0..2205).map(|_| expected.next_sample()).collect();
+        let expected_path = path.with_extension("expected.wav");
+        crate::write_wav(&expected, &expected_path, 44100).unwrap();
+
+        let read = |path: &PathBuf| {
+            let mut reader = hound::WavReader::open(path).unwrap();
+            assert_eq!(reader.spec().sample_rate, 44100);
+            reader
+                .samples::<i16>()
+                .map(Result::unwrap)
+                .collect::<Vec<i16>>()
+        };
+        assert_eq!(read(&path), read(&expected_path));
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&expected_path).unwrap();
+    }
+}
@@ -1,246 +1,466 @@
 // This files contains some custom stuff for initializing the back-end
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-#[cfg(debug_assertions)]
-use cpal::SupportedOutputConfigs;
 use cpal::{
-    Device, FromSample, Sample, SampleFormat, SampleRate, StreamConfig, SupportedStreamConfig,
-    SupportedStreamConfigRange,
+    BufferSize, Device, FromSample, Host, Sample, SampleFormat, SampleRate, SizedSample, Stream,
+    StreamConfig, SupportedBufferSize, SupportedStreamConfigRange,
 };
 use simplelog::__private::paris::Logger;
 use simplelog::{info, warn};
-use std::fs;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
 use std::thread::sleep;
 use std::time::Duration;
 
-/// Looks up for a supported config with a specific sample format.
-///
-/// # Arguments
-/// * `device` - a `Device` from which to get the **supported configurations**.
-/// * `sample_format` - (optional) a `SampleFormat` with the **preferred format** for each **sample**.
-/// * `sample_rate` - (optional) a `SampleRate`. If not set it will default to the max (not recommended).
-/// * `channel_amt` - (optional) the maximum amount of channels to use. Mono or Stereo is recommended.
-///
-/// # Return
-/// Returns the first `SupportedStreamConfig` fulfilling the requirements from the arguments.
-
-pub fn get_preferred_config(
-    device: &Device,
-    sample_format: Option<SampleFormat>,
-    sample_rate: Option<SampleRate>,
-    channel_amt: Option<Channels>,
-) -> SupportedStreamConfig {
-    let config = query_config(device, channel_amt, sample_format, sample_rate);
+use thiserror::Error;
+
+use crate::wav_export::{ExportError, WavExport};
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OutputError {
+    #[error("No audio host named '{0}' is available")]
+    HostUnavailable(String),
+    #[error("No output device found for '{0}'")]
+    DeviceNotFound(OutputDevice),
+    #[error("Device '{device}' does not support {requested}. Supported: {supported}")]
+    UnsupportedConfig {
+        device: String,
+        requested: String,
+        supported: String,
+    },
+    #[error("The signal runs at {signal} Hz but the output was set to {output} Hz")]
+    SampleRateMismatch { signal: u32, output: u32 },
+    #[error("Could not list the output devices: {0}")]
+    Devices(#[from] cpal::DevicesError),
+    #[error("Could not query the configurations of the device: {0}")]
+    Configs(#[from] cpal::SupportedStreamConfigsError),
+    #[error("Could not open the output stream: {0}")]
+    BuildStream(#[from] cpal::BuildStreamError),
+    #[error("Could not start the output stream: {0}")]
+    PlayStream(#[from] cpal::PlayStreamError),
+}
 
-    if cfg!(debug_assertions) {
-        info!(
-            "<b>PREFERRED CONFIG for <red>{}</>",
-            device.name().expect("Couldn't read device name")
-        );
-        info!(" |_ channels: {}", config.channels());
-        info!(" |_ sample_rate: {}", config.sample_rate().0);
-        info!(" |_ buffer size: {:?}", config.buffer_size());
-        info!(" |_ sample format: {:?}\n", config.sample_format());
+/// The output device to play through, either the default one of the host or one of the devices
+/// given by [list_output_devices]. Parsing a number gives an index, anything else a name.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OutputDevice {
+    #[default]
+    Default,
+    Name(String),
+    Index(usize),
+}
+
+impl FromStr for OutputDevice {
+    type Err = std::convert::Infallible;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Ok(match text.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) if text == "default" => Self::Default,
+            Err(_) => Self::Name(text.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for OutputDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::Name(name) => write!(f, "{}", name),
+            Self::Index(index) => write!(f, "#{}", index),
+        }
     }
+}
 
-    config
+/// Format of the samples handed to the output device. Samples are generated as [f32] and
+/// converted when the device takes integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputSampleFormat {
+    F32,
+    I16,
+    U16,
 }
 
-#[cfg(debug_assertions)]
-#[allow(unused)]
-fn print_all_configs(device: &Device) {
-    let supported_configs_range: SupportedOutputConfigs = device
-        .supported_output_configs()
-        .expect("error while querying configs");
+impl OutputSampleFormat {
+    /// Formats tried, in order, when none is requested.
+    const PREFERRED: [OutputSampleFormat; 3] = [Self::F32, Self::I16, Self::U16];
 
-    if cfg!(debug_assertions) {
-        for (ix, config) in supported_configs_range.enumerate() {
-            println!("{}: {:?}", ix, config);
+    fn to_cpal(self) -> SampleFormat {
+        match self {
+            Self::F32 => SampleFormat::F32,
+            Self::I16 => SampleFormat::I16,
+            Self::U16 => SampleFormat::U16,
         }
-        println!();
+    }
+
+    fn from_cpal(format: SampleFormat) -> Option<Self> {
+        Self::PREFERRED
+            .into_iter()
+            .find(|candidate| candidate.to_cpal() == format)
     }
 }
 
-/// Query every configuration meeting certain conditions.
-/// # Arguments
-/// * `device` - a `cpal::Device` from which get the configuration
-/// * `channel_amt` - amount of channels we want available. Will get from the amount onwards.
-/// * `sample_format` - the format in which data is going to be handled (cpal::SampleFormat)
-///
-/// # Returns
-/// A vector containing every cpal::SupportedStreamConfigRange matching the requirements
-pub fn query_configurations(
-    device: &Device,
-    channel_amt: Option<Channels>,
-    sample_format: Option<SampleFormat>,
-) -> Vec<SupportedStreamConfigRange> {
-    if cfg!(debug_assertions) {
-        info!(
-            "<b>QUERYING <red>{:?} device</><b> UNDER</>",
-            device.name().unwrap()
-        );
-        info!("  |_ channel amount: {:?}", channel_amt);
-        info!("  |_ sample format: {:?}\n", sample_format);
+impl fmt::Display for OutputSampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_cpal())
     }
-    let supported_configs = device
-        .supported_output_configs()
-        .expect("error while querying configs")
-        // Check the sample format
-        .filter(|config| match &sample_format {
-            None => true,
-            Some(a) => config.sample_format() == (*a),
-        })
-        // Check the channel amount
-        .filter(|config| match &channel_amt {
-            None => true,
-            Some(a) => (*a).get_amt() >= config.channels() as u8,
-        })
-        // to vector
-        .collect::<Vec<SupportedStreamConfigRange>>();
+}
 
-    // RESULT PRINTS
-    if cfg!(debug_assertions) {
-        info!("<b>CONFIGURATION MATCH LIST</>");
-        let configs = supported_configs.clone();
-        for item in configs {
-            info!("  |_ {:?}", item);
+/// How to open the output of the real time playback: the host and device to use, and the
+/// configuration of the stream. Anything not set is left to the device, except the channel
+/// count, which is stereo by default. The mono signal of the synthesizer is copied to every
+/// channel.
+/// # Example
+/// ```no_run
+/// # use lion_synth::{OutputConfig, OutputDevice, OutputSampleFormat};
+/// let output = OutputConfig::new()
+///     .with_device(OutputDevice::Name("USB Audio".to_string()))
+///     .with_buffer_size(256)
+///     .with_sample_format(OutputSampleFormat::I16);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputConfig {
+    host: Option<String>,
+    device: OutputDevice,
+    channels: u16,
+    sample_rate: Option<u32>,
+    buffer_size: Option<u32>,
+    sample_format: Option<OutputSampleFormat>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            device: OutputDevice::Default,
+            channels: 2,
+            sample_rate: None,
+            buffer_size: None,
+            sample_format: None,
         }
-        println!();
     }
-
-    supported_configs
 }
 
-/// Queries the first configuration found meeting certain conditions.
-/// # Arguments
-/// * `device` - a `cpal::Device` from which get the configuration
-/// * `channel_amt` - amount of channels we want available. Will default to the lowest possible one
-/// * `sample_format` - the format in which data is going to be handled (cpal::SampleFormat)
-///
-/// # Returns
-/// A cpal::SupportedStreamConfigRange matching the requirements.
-pub fn query_config(
-    device: &Device,
-    channel_amt: Option<Channels>,
-    sample_format: Option<SampleFormat>,
-    sample_rate: Option<SampleRate>,
-) -> SupportedStreamConfig {
-    println!();
-    let mut supported_configs = query_configurations(device, channel_amt, sample_format);
-
-    let range = supported_configs
-        .pop()
-        .expect("No possible configuration could be found. Try widening the search.");
-
-    match sample_rate {
-        None => range.with_max_sample_rate(),
-        Some(x) => range.with_sample_rate(x),
+impl OutputConfig {
+    /// The default device of the default host, in stereo.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the audio host by name, such as `ALSA` or `JACK`. See [host_names].
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    pub fn with_device(mut self, device: OutputDevice) -> Self {
+        self.device = device;
+        self
+    }
+
+    pub fn with_channels(mut self, channels: u16) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Sets the sample rate of the stream. Signals generated at a different rate are rejected
+    /// instead of being played at the wrong speed.
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
     }
+
+    /// Sets a fixed buffer size, in frames. Smaller buffers lower the latency, at the risk of
+    /// dropouts.
+    pub fn with_buffer_size(mut self, frames: u32) -> Self {
+        self.buffer_size = Some(frames);
+        self
+    }
+
+    pub fn with_sample_format(mut self, sample_format: OutputSampleFormat) -> Self {
+        self.sample_format = Some(sample_format);
+        self
+    }
+
+    pub fn get_host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    pub fn get_device(&self) -> &OutputDevice {
+        &self.device
+    }
+
+    pub fn get_channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn get_sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    pub fn get_buffer_size(&self) -> Option<u32> {
+        self.buffer_size
+    }
+
+    pub fn get_sample_format(&self) -> Option<OutputSampleFormat> {
+        self.sample_format
+    }
+
+    /// The sample rate to play a signal generated at `signal_rate`.
+    fn check_sample_rate(&self, signal_rate: i32) -> Result<u32, OutputError> {
+        let signal = signal_rate as u32;
+        match self.sample_rate {
+            Some(output) if output != signal => {
+                Err(OutputError::SampleRateMismatch { signal, output })
+            }
+            _ => Ok(signal),
+        }
+    }
+
+    /// Picks the first configuration supported by the device matching the requested one. The
+    /// sample formats are tried from the most to the least precise unless one was requested.
+    fn select(
+        &self,
+        device: &str,
+        supported: &[SupportedStreamConfigRange],
+        sample_rate: u32,
+    ) -> Result<(StreamConfig, OutputSampleFormat), OutputError> {
+        let formats = match self.sample_format {
+            Some(format) => vec![format],
+            None => OutputSampleFormat::PREFERRED.to_vec(),
+        };
+
+        for format in formats {
+            let range = supported.iter().find(|range| {
+                range.sample_format() == format.to_cpal()
+                    && range.channels() == self.channels
+                    && range.min_sample_rate().0 <= sample_rate
+                    && sample_rate <= range.max_sample_rate().0
+            });
+            let range = match range {
+                Some(range) => range,
+                None => continue,
+            };
+
+            let buffer_size = match (self.buffer_size, range.buffer_size()) {
+                (None, _) => BufferSize::Default,
+                (Some(frames), SupportedBufferSize::Range { min, max })
+                    if frames < *min || frames > *max =>
+                {
+                    return Err(OutputError::UnsupportedConfig {
+                        device: device.to_string(),
+                        requested: format!("a buffer of {} frames", frames),
+                        supported: format!("{} to {} frames", min, max),
+                    });
+                }
+                (Some(frames), _) => BufferSize::Fixed(frames),
+            };
+
+            let config = StreamConfig {
+                channels: self.channels,
+                sample_rate: SampleRate(sample_rate),
+                buffer_size,
+            };
+            return Ok((config, format));
+        }
+
+        let format = match self.sample_format {
+            Some(format) => format.to_string(),
+            None => String::from("any sample format"),
+        };
+        Err(OutputError::UnsupportedConfig {
+            device: device.to_string(),
+            requested: format!(
+                "{} channels at {} Hz in {}",
+                self.channels, sample_rate, format
+            ),
+            supported: supported
+                .iter()
+                .map(|range| {
+                    format!(
+                        "{} channels at {}-{} Hz in {}",
+                        range.channels(),
+                        range.min_sample_rate().0,
+                        range.max_sample_rate().0,
+                        range.sample_format()
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(", "),
+        })
+    }
+}
+
+/// An output device, as listed by [list_output_devices].
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputDeviceInfo {
+    /// Position of the device in the list, which can be used to [select](OutputDevice::Index) it.
+    pub index: usize,
+    pub name: String,
+    pub is_default: bool,
+    /// Maximum amount of channels among the supported configurations.
+    pub max_channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    /// Sample formats the device takes, among the ones the synthesizer can deliver.
+    pub sample_formats: Vec<OutputSampleFormat>,
 }
 
-/// An enumeration for specifying an amount of channels and easily differentiate the most common cases (mono and stereo).
-#[derive(Debug)]
-#[allow(dead_code)]
-pub enum Channels {
-    /// A single channel
-    Mono,
-    /// Two channels
-    Stereo,
-    /// Any given amount of channels
-    Multi(u8),
+/// Names of the audio hosts available in this platform, such as `ALSA` or `JACK`.
+pub fn host_names() -> Vec<String> {
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| id.name().to_string())
+        .collect()
 }
 
-impl Channels {
-    /// Translates the `enum` to a value for ease.
-    /// # Example
-    /// ```
-    /// let x: u8 = Channels::Stereo.get_amt(); // returns 2
-    /// ```
-    pub fn get_amt(&self) -> u8 {
-        match *self {
-            Self::Mono => 1,
-            Self::Stereo => 2,
-            Self::Multi(x) => x,
+/// Lists the output devices of a host, the default one if `None`.
+pub fn list_output_devices(host: Option<&str>) -> Result<Vec<OutputDeviceInfo>, OutputError> {
+    let host = get_host(host)?;
+    let default = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+
+    let mut devices = Vec::new();
+    for (index, device) in host.output_devices()?.enumerate() {
+        let name = match device.name() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        // Some devices cannot be queried while busy
+        let supported: Vec<SupportedStreamConfigRange> = match device.supported_output_configs() {
+            Ok(configs) => configs.collect(),
+            Err(_) => Vec::new(),
+        };
+
+        let mut sample_formats: Vec<OutputSampleFormat> = Vec::new();
+        for format in supported
+            .iter()
+            .filter_map(|range| OutputSampleFormat::from_cpal(range.sample_format()))
+        {
+            if !sample_formats.contains(&format) {
+                sample_formats.push(format);
+            }
         }
+
+        devices.push(OutputDeviceInfo {
+            index,
+            is_default: default.as_ref() == Some(&name),
+            name,
+            max_channels: supported.iter().map(|r| r.channels()).max().unwrap_or(0),
+            min_sample_rate: supported
+                .iter()
+                .map(|r| r.min_sample_rate().0)
+                .min()
+                .unwrap_or(0),
+            max_sample_rate: supported
+                .iter()
+                .map(|r| r.max_sample_rate().0)
+                .max()
+                .unwrap_or(0),
+            sample_formats,
+        });
     }
+
+    Ok(devices)
+}
+
+fn get_host(name: Option<&str>) -> Result<Host, OutputError> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(cpal::default_host()),
+    };
+
+    cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .and_then(|id| cpal::host_from_id(id).ok())
+        .ok_or_else(|| OutputError::HostUnavailable(name.to_string()))
 }
 
-pub fn output_wav(buffer: Vec<f32>, filename: &str, sample_rate: i32) {
-    let spec = hound::WavSpec {
-        channels: 1,
-        sample_rate: sample_rate as u32,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
+fn get_device(host: &Host, device: &OutputDevice) -> Result<Device, OutputError> {
+    let found = match device {
+        OutputDevice::Default => host.default_output_device(),
+        OutputDevice::Name(name) => host
+            .output_devices()?
+            .find(|candidate| candidate.name().ok().as_ref() == Some(name)),
+        OutputDevice::Index(index) => host.output_devices()?.nth(*index),
     };
 
+    found.ok_or_else(|| OutputError::DeviceNotFound(device.clone()))
+}
+
+/// Writes a buffer to a mono, 16 bits wav file of the `exports` folder, created if missing.
+pub fn output_wav(buffer: Vec<f32>, filename: &str, sample_rate: i32) -> Result<(), ExportError> {
+    let export = WavExport::default();
+
     info!("<b>Running <magenta>hound</> <b>to generate a wav file.</>");
-    info!("  <b>|_ Channels: <cyan>{}</>", spec.channels);
-    info!("  <b>|_ Bits per sample: <cyan>{}</>", spec.bits_per_sample);
+    info!("  <b>|_ Channels: <cyan>{}</>", export.get_channels());
     info!(
-        "  <b>|_ Sample format: {}</>",
-        match spec.sample_format {
-            hound::SampleFormat::Int => "<yellow>int",
-            hound::SampleFormat::Float => "<cyan>float",
-        }
+        "  <b>|_ Sample format: <yellow>{:?}</>",
+        export.get_format()
     );
 
     let subdir = "exports".to_string();
     info!("  <b>|_ Export directory: <green>.{}/</>", subdir);
     info!("  <b>|_ File name: <green>{}</>", filename);
 
-    fs::create_dir_all(&subdir).unwrap();
     let filename = subdir + "/" + filename;
+    export.write(&buffer, Path::new(&filename), sample_rate)?;
 
-    let mut test_writer = hound::WavWriter::create(filename, spec).unwrap();
-    let amplitude = i16::MAX as f32;
-    for sample in buffer {
-        test_writer
-            .write_sample((amplitude * sample) as i16)
-            .unwrap();
-    }
+    Ok(())
+}
+
+/// Writes a buffer to a mono, 16 bits wav file at the given path. Unlike [output_wav], the path
+/// is used as is. See [WavExport] for other formats.
+pub fn write_wav(buffer: &[f32], path: &Path, sample_rate: i32) -> Result<(), ExportError> {
+    WavExport::default().write(buffer, path, sample_rate)?;
+    Ok(())
+}
 
-    test_writer.finalize().unwrap();
+/// Names of every output device available in the default host. The default device, if any, is
+/// returned as well. See [list_output_devices] for the details of every device.
+pub fn output_device_names() -> Result<(Vec<String>, Option<String>), anyhow::Error> {
+    let devices = list_output_devices(None)?;
+    let default = devices
+        .iter()
+        .find(|device| device.is_default)
+        .map(|device| device.name.clone());
+
+    Ok((
+        devices.into_iter().map(|device| device.name).collect(),
+        default,
+    ))
 }
 
+/// Plays a buffer through the default output device.
 pub fn play_buffer(
-    mut buffer: Vec<f32>,
+    buffer: Vec<f32>,
     signal_duration: i32,
     sample_rate: i32,
 ) -> Result<(), anyhow::Error> {
-    let mut logger = Logger::new();
-
-    let host = cpal::default_host();
-
-    // get default device
-    let device: Device = host
-        .default_output_device()
-        .expect("no default output device available. Please check if one is selected");
-
-    // load config
-    let supported_config = get_preferred_config(
-        &device,
-        Some(SampleFormat::F32),
-        Some(SampleRate(sample_rate as u32)),
-        Some(Channels::Stereo),
-    );
-
-    // open stream
-    let config: StreamConfig = supported_config.into();
+    play_buffer_with_output(
+        buffer,
+        signal_duration,
+        sample_rate,
+        &OutputConfig::default(),
+    )
+}
 
-    let channels = config.channels as usize;
+/// Does the same as [play_buffer] through the given output.
+pub fn play_buffer_with_output(
+    buffer: Vec<f32>,
+    signal_duration: i32,
+    sample_rate: i32,
+    output: &OutputConfig,
+) -> Result<(), anyhow::Error> {
+    let mut logger = Logger::new();
 
     // If there is no more values in the buffer, silence
-    let mut next_value = move || buffer.pop().unwrap_or(0.0);
-    let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
-
-    let stream = device.build_output_stream(
-        &config,
-        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            write_data(data, channels, &mut next_value)
-        },
-        err_fn,
-        None,
-    )?;
+    let mut samples = buffer.into_iter();
+    let stream = open_output_stream(output, sample_rate, move || samples.next().unwrap_or(0.0))?;
 
     info!("<b>Signal duration: <u>{} milliseconds</>", signal_duration);
     warn!("<yellow><warn></> <b>The end of the buffer may be filled with <blue>silence</><b>.</>");
@@ -255,6 +475,62 @@ pub fn play_buffer(
     Ok(())
 }
 
+/// Opens a stream on the given output, playing the samples returned by `next_value` from the
+/// audio thread. The stream is paused until [played](fn@StreamTrait::play).
+pub(crate) fn open_output_stream(
+    output: &OutputConfig,
+    sample_rate: i32,
+    next_value: impl FnMut() -> f32 + Send + 'static,
+) -> Result<Stream, OutputError> {
+    let sample_rate = output.check_sample_rate(sample_rate)?;
+    let host = get_host(output.get_host())?;
+    let device = get_device(&host, output.get_device())?;
+    let name = device
+        .name()
+        .unwrap_or_else(|_| output.get_device().to_string());
+
+    let supported: Vec<SupportedStreamConfigRange> = device.supported_output_configs()?.collect();
+    let (config, format) = output.select(&name, &supported, sample_rate)?;
+
+    if cfg!(debug_assertions) {
+        info!("<b>OUTPUT CONFIG for <red>{}</>", name);
+        info!(" |_ host: {}", host.id().name());
+        info!(" |_ channels: {}", config.channels);
+        info!(" |_ sample_rate: {}", config.sample_rate.0);
+        info!(" |_ buffer size: {:?}", config.buffer_size);
+        info!(" |_ sample format: {}\n", format);
+    }
+
+    match format {
+        OutputSampleFormat::F32 => build_stream::<f32>(&device, &config, next_value),
+        OutputSampleFormat::I16 => build_stream::<i16>(&device, &config, next_value),
+        OutputSampleFormat::U16 => build_stream::<u16>(&device, &config, next_value),
+    }
+}
+
+fn build_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut next_value: impl FnMut() -> f32 + Send + 'static,
+) -> Result<Stream, OutputError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            write_data(data, channels, &mut next_value)
+        },
+        err_fn,
+        None,
+    )?;
+
+    Ok(stream)
+}
+
 /// This function fills the data in batches. Is called by the cpal when it considers timely.
 pub fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> f32)
 where
@@ -267,3 +543,98 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(channels: u16, format: SampleFormat) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(8000),
+            SampleRate(48000),
+            SupportedBufferSize::Range { min: 64, max: 4096 },
+            format,
+        )
+    }
+
+    #[test]
+    fn test_output_device_from_str() {
+        assert_eq!("default".parse(), Ok(OutputDevice::Default));
+        assert_eq!("2".parse(), Ok(OutputDevice::Index(2)));
+        assert_eq!(
+            "USB Audio".parse(),
+            Ok(OutputDevice::Name("USB Audio".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_select_config() {
+        let supported = vec![
+            range(2, SampleFormat::U16),
+            range(2, SampleFormat::I16),
+            range(1, SampleFormat::F32),
+        ];
+
+        // F32 is only available in mono, I16 is preferred to U16
+        let (config, format) = OutputConfig::new()
+            .select("test", &supported, 44100)
+            .unwrap();
+        assert_eq!(format, OutputSampleFormat::I16);
+        assert_eq!(config.channels, 2);
+        assert_eq!(config.sample_rate, SampleRate(44100));
+        assert_eq!(config.buffer_size, BufferSize::Default);
+
+        let (config, format) = OutputConfig::new()
+            .with_channels(1)
+            .with_buffer_size(256)
+            .select("test", &supported, 44100)
+            .unwrap();
+        assert_eq!(format, OutputSampleFormat::F32);
+        assert_eq!(config.buffer_size, BufferSize::Fixed(256));
+
+        let (_, format) = OutputConfig::new()
+            .with_sample_format(OutputSampleFormat::U16)
+            .select("test", &supported, 44100)
+            .unwrap();
+        assert_eq!(format, OutputSampleFormat::U16);
+
+        for output in [
+            OutputConfig::new().with_channels(6),
+            OutputConfig::new().with_buffer_size(16),
+            OutputConfig::new()
+                .with_channels(1)
+                .with_sample_format(OutputSampleFormat::I16),
+        ] {
+            assert!(matches!(
+                output.select("test", &supported, 44100),
+                Err(OutputError::UnsupportedConfig { .. })
+            ));
+        }
+        assert!(OutputConfig::new()
+            .select("test", &supported, 96000)
+            .is_err());
+    }
+
+    #[test]
+    fn test_check_sample_rate() {
+        assert_eq!(OutputConfig::new().check_sample_rate(22050).unwrap(), 22050);
+        assert!(matches!(
+            OutputConfig::new()
+                .with_sample_rate(48000)
+                .check_sample_rate(44100),
+            Err(OutputError::SampleRateMismatch {
+                signal: 44100,
+                output: 48000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_write_data() {
+        let mut samples = vec![0.5, -1.0].into_iter();
+        let mut data = [0i16; 4];
+        write_data(&mut data, 2, &mut || samples.next().unwrap_or(0.0));
+        assert_eq!(data, [16384, 16384, -32768, -32768]);
+    }
+}
@@ -1,16 +1,21 @@
+use crate::document::Value;
 use crate::module::{Module, Parameter};
 use std::f32::consts::PI;
 
 // MODULES
 pub struct PassTrough {}
 
+/// A 440 Hz sine wave, with no parameters at all.
 pub struct OscDebug {
-    sample_rate: f32,
+    /// The position within the cycle, from 0 to 1.
+    position: f64,
+    /// The sample rate given when prepared.
+    sample_rate: f64,
 }
 
 // IMPLEMENTATIONS
 impl Module for PassTrough {
-    fn behavior(&self, in_sample: f32, _time: f32) -> f32 {
+    fn behavior(&mut self, in_sample: f32, _time: f64) -> f32 {
         in_sample // clean data
     }
 
@@ -22,15 +27,40 @@ impl Module for PassTrough {
         None
     }
 
+    fn get_layout_config(&self) -> Option<(String, Value)> {
+        Some((String::from("pass_through"), Value::Null))
+    }
+
     fn get_name(&self) -> String {
         "PassThrough".to_string()
     }
 }
 
 impl Module for OscDebug {
-    fn behavior(&self, _: f32, time: f32) -> f32 {
-        let freq: f32 = 440.0;
-        (time * freq * 2.0 * PI).sin()
+    fn behavior(&mut self, _: f32, _time: f64) -> f32 {
+        let freq: f64 = 440.0;
+        // Only the position within the cycle is kept, so precision is not lost over time
+        let value = (self.position as f32 * 2.0 * PI).sin();
+        self.position = (self.position + freq / self.sample_rate).fract();
+        value
+    }
+
+    fn prepare(&mut self, sample_rate: i32, _max_block: usize) {
+        self.sample_rate = sample_rate as f64;
+    }
+
+    fn reset(&mut self) {
+        self.position = 0.0;
+    }
+
+    fn get_state(&self) -> Value {
+        Value::Real(self.position)
+    }
+
+    fn set_state(&mut self, state: &Value) {
+        if let Some(position) = state.as_f64() {
+            self.position = position.fract();
+        }
     }
 
     fn get_parameters(&self) -> Option<Vec<&Parameter>> {
@@ -41,22 +71,40 @@ impl Module for OscDebug {
         None
     }
 
+    fn get_layout_config(&self) -> Option<(String, Value)> {
+        Some((String::from("osc_debug"), Value::Null))
+    }
+
     fn get_name(&self) -> String {
         "Debug oscillator".to_string()
     }
 }
 
 // CONSTRUCTORS
+impl Default for PassTrough {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PassTrough {
     pub fn new() -> Self {
         Self {}
     }
 }
 
+impl Default for OscDebug {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl OscDebug {
-    pub fn new(sample_rate: i32) -> Self {
+    pub fn new() -> Self {
         Self {
-            sample_rate: sample_rate as f32,
+            position: 0.0,
+            // Until prepared
+            sample_rate: 44100.0,
         }
     }
 }
@@ -65,11 +113,11 @@ impl OscDebug {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::SAMPLE_RATE;
+    const SAMPLE_RATE: i32 = 44100;
 
     #[test]
     fn test_debug_osc() {
-        let mut tested_module = OscDebug::new(SAMPLE_RATE);
+        let mut tested_module = OscDebug::new();
         let mut buffer: Vec<f32> = vec![0.0; 10];
 
         tested_module.fill_buffer(&mut buffer, SAMPLE_RATE, vec![]);
@@ -82,7 +130,7 @@ mod test {
             0.24813786,
             0.30833942,
             0.3673296,
-            0.42487666,
+            0.4248767,
             0.48075455,
             0.53474367,
         ];
@@ -93,7 +141,7 @@ mod test {
     #[test]
     fn test_pass_through() {
         let mut tested_module = PassTrough::new();
-        let mut osc = OscDebug::new(SAMPLE_RATE);
+        let mut osc = OscDebug::new();
         let mut original_buffer: Vec<f32> = vec![0.0; 20];
 
         // MODIFY THE BUFFER
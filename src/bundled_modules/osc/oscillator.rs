@@ -1,7 +1,9 @@
 use crate::bundled_modules::osc::oscillator_math::{OscillatorMath, WaveShape};
-use crate::module::{Module, Parameter, ParameterBuilder};
-use crate::SAMPLE_RATE;
+use crate::document::Value;
+use crate::module::{note_to_frequency, Event, EventKind, Module, Parameter, ParameterBuilder};
+use crate::module_registry::{config_node, name_node, real_node};
 use simplelog::{error, info};
+use std::collections::HashMap;
 use std::f32::consts::PI;
 
 // TODO: add wave shape to doc
@@ -31,14 +33,21 @@ use std::f32::consts::PI;
 /// # Behaviour
 /// The generation of a signal follows a simple formula:
 ///
-/// `x = A * sin(f * t + φ)`
+/// `x = A * sin(2π * p + φ)`
 ///
-/// Where `x` is the value at `t` time,
+/// Where `x` is the value of the sample,
 /// `A` is the maximum amplitude of the wave,
 /// `φ` the phase and
-/// `f` the frequency.
+/// `p` the position within the cycle.
 ///
-/// `t` the time given by a coordinator entity.
+/// The position moves forward `f / sample rate` every sample, `f` being the frequency, so the
+/// wave goes on smoothly when the frequency changes. It starts from zero when the oscillator is
+/// [reset](fn@Module::reset).
+///
+/// # Events
+/// The oscillator can be played with [note events](enum@Event). A *note on* sets the frequency
+/// to the one of the note and the amplitude to its velocity. A *note off* of the last note
+/// played silences the oscillator.
 pub struct Oscillator {
     /// The maximum amplitude of the wave. Translates to volume (gain). A value greater than one will result in clipping.
     amplitude: Parameter,
@@ -52,11 +61,23 @@ pub struct Oscillator {
     pulse_width: Parameter,
     /// Name of the module (debugging)
     name: String,
+    /// The note being played, if the oscillator is driven by events.
+    note: Option<u8>,
+    /// The position within the cycle, from 0 to 1.
+    position: f64,
+    /// The sample rate given when prepared.
+    sample_rate: f64,
 }
 
+/// Sample rate of the oscillators until they are [prepared](fn@Module::prepare).
+const DEFAULT_SAMPLE_RATE: f64 = 44100.0;
+
 impl Module for Oscillator {
-    fn behavior(&self, _in_data: f32, time: f32) -> f32 {
-        let mut value = ((time * self.get_frequency() * 2.0 * PI) + self.get_phase());
+    fn behavior(&mut self, _in_data: f32, _time: f64) -> f32 {
+        // The position is kept in f64 and wrapped every cycle, so the precision of the wave does
+        // not degrade over long sessions.
+        let mut value = (self.position as f32 * 2.0 * PI) + self.get_phase();
+        self.position = (self.position + self.get_frequency() as f64 / self.sample_rate).fract();
 
         value = match self.get_wave() {
             WaveShape::Saw => value.saw(),
@@ -85,13 +106,75 @@ impl Module for Oscillator {
         ])
     }
 
+    fn prepare(&mut self, sample_rate: i32, _max_block: usize) {
+        self.sample_rate = sample_rate as f64;
+    }
+
+    fn reset(&mut self) {
+        self.note = None;
+        self.position = 0.0;
+    }
+
+    fn get_state(&self) -> Value {
+        Value::Real(self.position)
+    }
+
+    fn set_state(&mut self, state: &Value) {
+        if let Some(position) = state.as_f64() {
+            self.position = position.fract();
+        }
+    }
+
+    fn get_event_inputs(&self) -> Vec<EventKind> {
+        vec![EventKind::Note, EventKind::ParameterChange]
+    }
+
+    fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::NoteOn { note, velocity } => {
+                self.set_frequency(note_to_frequency(*note));
+                self.set_amplitude(*velocity);
+                self.note = Some(*note);
+            }
+            Event::NoteOff { note } if self.note == Some(*note) => {
+                self.set_amplitude(0.0);
+                self.note = None;
+            }
+            Event::ParameterChange { tag, value } => {
+                self.update_parameters(HashMap::from([(tag.clone(), *value)]));
+            }
+            _ => {}
+        }
+    }
+
+    fn get_layout_config(&self) -> Option<(String, Value)> {
+        let (wave, pulse_width) = match self.get_wave() {
+            WaveShape::Sine => ("sine", None),
+            WaveShape::Triangle => ("triangle", None),
+            WaveShape::Saw => ("saw", None),
+            WaveShape::Square => ("square", None),
+            WaveShape::Pulse(width) => ("pulse", Some(real_node(*width))),
+        };
+
+        let config = config_node(vec![
+            ("name", name_node(&self.name, " Oscillator")),
+            ("amplitude", Some(real_node(self.get_amplitude()))),
+            ("frequency", Some(real_node(self.get_frequency()))),
+            ("phase", Some(real_node(self.get_phase()))),
+            ("wave", Some(Value::String(wave.to_string()))),
+            ("pwd", pulse_width),
+        ]);
+
+        Some((String::from("oscillator"), config))
+    }
+
     fn get_name(&self) -> String {
         self.name.to_string()
     }
 }
 
 /// Some shortcut methods for the parameters. Look at the implementation for reference.
-/// ```rust
+/// ```ignore
 /// pub fn set_amplitude(&mut self, amp: f32) {
 ///     self.get_parameter_mutable("amplitude").unwrap().set(amp);
 /// }
@@ -146,6 +229,7 @@ impl Oscillator {
 /// The [OscillatorBuilder] is the proper way of generating an [Oscillator].
 /// # Usage
 /// ```rust
+/// # use lion_synth::bundled_modules::OscillatorBuilder;
 /// let mut oscillator = OscillatorBuilder::new().build().unwrap(); // Default oscillator
 ///
 /// let osc = OscillatorBuilder::new() // With most values
@@ -164,6 +248,12 @@ pub struct OscillatorBuilder {
     name: Option<String>,
 }
 
+impl Default for OscillatorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl OscillatorBuilder {
     /// Sets the defaults for the oscillator (no parameters).
     pub fn new() -> Self {
@@ -274,31 +364,30 @@ impl OscillatorBuilder {
 
         Ok(Oscillator {
             name,
+            note: None,
+            position: 0.0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
             amplitude: ParameterBuilder::new("amplitude".to_string())
                 .with_default(amplitude)
-                .build()
-                .expect("Invalid amplitude value"),
+                .build()?,
 
             frequency: ParameterBuilder::new("frequency".to_string())
                 .with_max(22000.0)
                 .with_min(10.0)
                 .with_default(frequency)
-                .build()
-                .expect("Invalid frequency value"),
+                .build()?,
 
             phase: ParameterBuilder::new("phase".to_string())
                 .with_max(PI * 2.0)
                 .with_default(phase)
-                .build()
-                .expect("Invalid phase value"),
+                .build()?,
             wave_shape: wave,
 
             pulse_width: ParameterBuilder::new("pulse width".to_string())
                 .with_max(2.0 * PI)
                 .with_min(0.0)
                 .with_default(pulse_width)
-                .build()
-                .expect("Invalid pulse width"),
+                .build()?,
         })
     }
 }
@@ -309,7 +398,7 @@ mod oscillator_builder_tests {
     use super::OscillatorBuilder;
     use crate::bundled_modules::Oscillator;
     use crate::module::Clock;
-    use crate::SAMPLE_RATE;
+    const SAMPLE_RATE: i32 = 44100;
     use simplelog::__private::paris::Logger;
     use std::f32::consts::PI;
 
@@ -512,4 +601,86 @@ mod oscillator_tests {
         let value = (&osc).get_phase();
         assert_eq!(PI, value);
     }
+
+    #[test]
+    fn test_long_session() {
+        use crate::module::Module;
+
+        let mut osc = OscillatorBuilder::new()
+            .with_frequency(441.0)
+            .build()
+            .unwrap();
+        osc.prepare(44100, 1);
+        let start: Vec<f32> = (0..100).map(|_| osc.get_sample(0.0, 0.0)).collect();
+
+        // A whole number of cycles later
+        for _ in 100..60 * 44100 {
+            osc.get_sample(0.0, 0.0);
+        }
+        for expected in start {
+            let sample = osc.get_sample(0.0, 0.0);
+            assert!((sample - expected).abs() < 1e-4, "Precision lost over time");
+        }
+    }
+
+    #[test]
+    fn test_frequency_change() {
+        use crate::module::Module;
+
+        let mut osc = OscillatorBuilder::new().build().unwrap();
+        osc.prepare(44100, 1);
+
+        // Ten minutes in, the time must not matter
+        let time = |sample: u64| 600.7 + sample as f64 / 44100.0;
+        // The most a sine wave moves in a sample, at the highest frequency played
+        let step = 2.0 * PI * 440.37 / 44100.0;
+
+        let mut previous = osc.get_sample(0.0, time(0));
+        for sample in 1..1000 {
+            if sample == 500 {
+                osc.set_frequency(440.37);
+            }
+            let value = osc.get_sample(0.0, time(sample));
+            assert!(
+                (value - previous).abs() <= step,
+                "Phase jump at sample {}",
+                sample
+            );
+            previous = value;
+        }
+
+        // Starts over once reset
+        osc.reset();
+        assert_eq!(osc.get_sample(0.0, time(1000)), 0.0);
+    }
+
+    #[test]
+    fn test_note_events() {
+        use crate::module::{Event, Module};
+
+        let mut osc = OscillatorBuilder::new().build().unwrap();
+
+        osc.handle_event(&Event::NoteOn {
+            note: 57,
+            velocity: 0.5,
+        });
+        assert_eq!(osc.get_frequency(), 220.0);
+        assert_eq!(osc.get_amplitude(), 0.5);
+
+        osc.handle_event(&Event::NoteOff { note: 60 });
+        assert_eq!(
+            osc.get_amplitude(),
+            0.5,
+            "Only the last note can be released"
+        );
+
+        osc.handle_event(&Event::NoteOff { note: 57 });
+        assert_eq!(osc.get_amplitude(), 0.0);
+
+        osc.handle_event(&Event::ParameterChange {
+            tag: "frequency".to_string(),
+            value: 330.0,
+        });
+        assert_eq!(osc.get_frequency(), 330.0);
+    }
 }
@@ -1,5 +1,7 @@
 use super::*;
+use crate::document::Value;
 use crate::module::{Module, Parameter, ParameterBuilder};
+use crate::module_registry::{config_node, name_node, real_node};
 /// Sums the data from the input module and the auxiliary parameter.
 /// # Input data
 /// Input values come from auxiliaries and will get clipped to [1,-1] before adjusting
@@ -31,13 +33,18 @@ impl Sum2In {
 }
 
 impl Module for Sum2In {
-    fn behavior(&self, in_data: f32, _time: f32) -> f32 {
+    fn behavior(&mut self, in_data: f32, _time: f64) -> f32 {
         let in_1 = in_data * self.in1_gain.get_value();
         let in_2 = self.get_in2() * self.in2_gain.get_value();
 
         (in_1 + in_2) * self.out_gain.get_value()
     }
 
+    /// Forgets the last value received in the second input.
+    fn reset(&mut self) {
+        self.second_input.reset();
+    }
+
     fn get_parameters(&self) -> Option<Vec<&Parameter>> {
         Some(vec![
             &self.second_input,
@@ -56,6 +63,18 @@ impl Module for Sum2In {
         ])
     }
 
+    fn get_layout_config(&self) -> Option<(String, Value)> {
+        let config = config_node(vec![
+            ("name", name_node(&self.name, " Sum 2in")),
+            ("input-amount", Some(Value::Integer(2))),
+            ("out-gain", Some(real_node(self.out_gain.get_value()))),
+            ("in-1", Some(real_node(self.in1_gain.get_value()))),
+            ("in-2", Some(real_node(self.in2_gain.get_value()))),
+        ]);
+
+        Some((String::from("sum"), config))
+    }
+
     fn get_name(&self) -> String {
         self.name.clone()
     }
@@ -71,6 +90,12 @@ pub struct Sum2InBuilder {
     out_gain: Option<f32>,
 }
 
+impl Default for Sum2InBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Sum2InBuilder {
     pub fn new() -> Self {
         Self {
@@ -129,29 +154,25 @@ impl Sum2InBuilder {
             second_input: ParameterBuilder::new("in2".to_string())
                 .with_min(AUDIO_RANGE_BOT)
                 .with_max(AUDIO_RANGE_TOP)
-                .build()
-                .unwrap(),
+                .build()?,
 
             out_gain: ParameterBuilder::new("out_gain".to_string())
                 .with_max(OVER_GAIN)
                 .with_default(out_gain)
                 .with_min(MIN_GAIN)
-                .build()
-                .unwrap(),
+                .build()?,
 
             in1_gain: ParameterBuilder::new("in_1_gain".to_string())
                 .with_max(OVER_GAIN)
                 .with_default(in_1_gain)
                 .with_min(MIN_GAIN)
-                .build()
-                .unwrap(),
+                .build()?,
 
             in2_gain: ParameterBuilder::new("in_2_gain".to_string())
                 .with_max(OVER_GAIN)
                 .with_default(in_2_gain)
                 .with_min(MIN_GAIN)
-                .build()
-                .unwrap(),
+                .build()?,
         })
     }
 }
@@ -164,7 +185,7 @@ mod test {
         use super::*;
         use crate::bundled_modules::debug::OscDebug;
         use crate::module::{AuxDataHolder, AuxInputBuilder};
-        use crate::SAMPLE_RATE;
+        const SAMPLE_RATE: i32 = 44100;
         use log::info;
 
         fn get_sum_module() -> Sum2In {
@@ -175,8 +196,8 @@ mod test {
         fn sum_module() {
             const BUFFER_SIZE: usize = 10;
 
-            let mut in1_osc = OscDebug::new(SAMPLE_RATE);
-            let mut in2_osc = OscDebug::new(SAMPLE_RATE);
+            let mut in1_osc = OscDebug::new();
+            let mut in2_osc = OscDebug::new();
             let mut sum = get_sum_module();
 
             let mut buffer1 = vec![0.0f32; BUFFER_SIZE];
@@ -1,5 +1,7 @@
 use super::*;
+use crate::document::Value;
 use crate::module::{Module, Parameter, ParameterBuilder};
+use crate::module_registry::{config_node, name_node, real_node};
 
 pub struct Sum3In {
     name: String,
@@ -36,7 +38,7 @@ impl Sum3In {
 }
 
 impl Module for Sum3In {
-    fn behavior(&self, in_data: f32, _time: f32) -> f32 {
+    fn behavior(&mut self, in_data: f32, _time: f64) -> f32 {
         let in_1 = in_data * self.in1_gain.get_value();
         let in_2 = self.get_in2() * self.in2_gain.get_value();
         let in_3 = self.get_in3() * self.in3_gain.get_value();
@@ -44,6 +46,12 @@ impl Module for Sum3In {
         (in_1 + in_2 + in_3) * self.out_gain.get_value()
     }
 
+    /// Forgets the last values received in the second and third inputs.
+    fn reset(&mut self) {
+        self.second_input.reset();
+        self.third_input.reset();
+    }
+
     fn get_parameters(&self) -> Option<Vec<&Parameter>> {
         Some(vec![
             &self.second_input,
@@ -66,6 +74,19 @@ impl Module for Sum3In {
         ])
     }
 
+    fn get_layout_config(&self) -> Option<(String, Value)> {
+        let config = config_node(vec![
+            ("name", name_node(&self.name, " Sum 3in")),
+            ("input-amount", Some(Value::Integer(3))),
+            ("out-gain", Some(real_node(self.out_gain.get_value()))),
+            ("in-1", Some(real_node(self.in1_gain.get_value()))),
+            ("in-2", Some(real_node(self.in2_gain.get_value()))),
+            ("in-3", Some(real_node(self.in3_gain.get_value()))),
+        ]);
+
+        Some((String::from("sum"), config))
+    }
+
     fn get_name(&self) -> String {
         self.name.clone()
     }
@@ -83,6 +104,12 @@ pub struct Sum3InBuilder {
     out_gain: Option<f32>,
 }
 
+impl Default for Sum3InBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Sum3InBuilder {
     pub fn new() -> Self {
         Self {
@@ -151,42 +178,36 @@ impl Sum3InBuilder {
             second_input: ParameterBuilder::new("in2".to_string())
                 .with_min(AUDIO_RANGE_BOT)
                 .with_max(AUDIO_RANGE_TOP)
-                .build()
-                .unwrap(),
+                .build()?,
 
             third_input: ParameterBuilder::new("in3".to_string())
                 .with_min(AUDIO_RANGE_BOT)
                 .with_max(AUDIO_RANGE_TOP)
-                .build()
-                .unwrap(),
+                .build()?,
 
             out_gain: ParameterBuilder::new("out_gain".to_string())
                 .with_max(OVER_GAIN)
                 .with_default(out_gain)
                 .with_min(MIN_GAIN)
-                .build()
-                .unwrap(),
+                .build()?,
 
             in1_gain: ParameterBuilder::new("in_1_gain".to_string())
                 .with_max(OVER_GAIN)
                 .with_default(in_1_gain)
                 .with_min(MIN_GAIN)
-                .build()
-                .unwrap(),
+                .build()?,
 
             in2_gain: ParameterBuilder::new("in_2_gain".to_string())
                 .with_max(OVER_GAIN)
                 .with_default(in_2_gain)
                 .with_min(MIN_GAIN)
-                .build()
-                .unwrap(),
+                .build()?,
 
             in3_gain: ParameterBuilder::new("in_3_gain".to_string())
                 .with_max(OVER_GAIN)
                 .with_default(in_3_gain)
                 .with_min(MIN_GAIN)
-                .build()
-                .unwrap(),
+                .build()?,
         })
     }
 }
@@ -196,15 +217,15 @@ mod test {
     use super::*;
     use crate::bundled_modules::debug_modules::*;
     use crate::module::{AuxDataHolder, AuxInputBuilder};
-    use crate::SAMPLE_RATE;
+    const SAMPLE_RATE: i32 = 44100;
 
     #[test]
     fn sum3_module() {
         const BUFFER_SIZE: usize = 10;
 
-        let mut in1_osc = OscDebug::new(SAMPLE_RATE);
-        let mut in2_osc = OscDebug::new(SAMPLE_RATE);
-        let mut in3_osc = OscDebug::new(SAMPLE_RATE);
+        let mut in1_osc = OscDebug::new();
+        let mut in2_osc = OscDebug::new();
+        let mut in3_osc = OscDebug::new();
         let mut sum = Sum3InBuilder::new().build().unwrap();
 
         let mut buffer1 = vec![0.0f32; BUFFER_SIZE];
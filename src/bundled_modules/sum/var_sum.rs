@@ -11,7 +11,9 @@
 //! system.
 
 use super::*;
+use crate::document::Value;
 use crate::module::{Module, Parameter, ParameterBuilder};
+use crate::module_registry::{config_node, name_node, real_node};
 use simplelog::info;
 
 /// The [VarSum] will let you create a sum module with any amount of modules.
@@ -26,7 +28,7 @@ pub struct VarSum {
 }
 
 impl Module for VarSum {
-    fn behavior(&self, in_data: f32, _time: f32) -> f32 {
+    fn behavior(&mut self, in_data: f32, _time: f64) -> f32 {
         let mut result = in_data;
 
         for in_value in self.inputs.iter() {
@@ -36,6 +38,11 @@ impl Module for VarSum {
         result * self.out_gain.get_value()
     }
 
+    /// Forgets the last values received in the inputs.
+    fn reset(&mut self) {
+        self.inputs.iter_mut().for_each(|input| input.reset());
+    }
+
     fn get_parameters(&self) -> Option<Vec<&Parameter>> {
         let mut parameters: Vec<&Parameter> = Vec::new();
 
@@ -52,6 +59,17 @@ impl Module for VarSum {
         Some(parameters)
     }
 
+    fn get_layout_config(&self) -> Option<(String, Value)> {
+        let suffix = format!(" {}Sum", self.in_count);
+        let config = config_node(vec![
+            ("name", name_node(&self.name, &suffix)),
+            ("input-amount", Some(Value::Integer(self.in_count as i64))),
+            ("out-gain", Some(real_node(self.out_gain.get_value()))),
+        ]);
+
+        Some((String::from("sum"), config))
+    }
+
     fn get_name(&self) -> String {
         self.name.clone()
     }
@@ -63,6 +81,12 @@ pub struct VarSumBuilder {
     out_gain: Option<f32>,
 }
 
+impl Default for VarSumBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl VarSumBuilder {
     pub fn new() -> Self {
         Self {
@@ -110,8 +134,7 @@ impl VarSumBuilder {
                 .with_min(AUDIO_RANGE_BOT)
                 .with_default(AUDIO_RANGE_BOT)
                 .with_max(AUDIO_RANGE_TOP)
-                .build()
-                .unwrap();
+                .build()?;
             inputs.push(param);
         }
 
@@ -123,8 +146,7 @@ impl VarSumBuilder {
                 .with_max(OVER_GAIN)
                 .with_default(out_gain)
                 .with_min(MIN_GAIN)
-                .build()
-                .unwrap(),
+                .build()?,
         })
     }
 }
@@ -0,0 +1,550 @@
+//! The document model layouts are read into, independent of the format they are written in.
+//!
+//! Layouts can be written in YAML, JSON or TOML. Whatever the format, the text is parsed into a
+//! tree of [Value]s, which is what the loader, the [module factories](crate::ModuleFactory) and
+//! the [migrations](crate::migration) work with, so every format is validated the same way. The
+//! format is usually [chosen by the extension](fn@Format::from_path) of the file.
+//!
+//! The same layout in the three formats:
+//! ```text
+//! # YAML                  // JSON                                 # TOML
+//! version: 0.5            {                                       version = 0.5
+//! layout:                   "version": 0.5,                       [[layout]]
+//!   - module:               "layout": [                           [layout.module]
+//!       id: 0                 { "module": {                       id = 0
+//!       type: oscillator        "id": 0,                          type = "oscillator"
+//!       os-out: true            "type": "oscillator",             os-out = true
+//!       config:                 "os-out": true,                   [layout.module.config]
+//!         frequency: 440.0      "config": { "frequency": 440.0 }  frequency = 440.0
+//!                             } }
+//!                           ]
+//!                         }
+//! ```
+
+use crate::layout_yaml::{Location, YamlParsingError};
+use std::fmt;
+use std::ops::Index;
+use std::path::Path;
+use std::str::FromStr;
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+/// Languages a layout can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl Format {
+    /// The format of a file, given by its extension: `.yaml`/`.yml`, `.json` or `.toml`.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        extension.parse().ok()
+    }
+
+    /// Reads a document written in this format.
+    /// # Expected errors
+    /// * [Syntax](YamlParsingError::Syntax) errors, with the location where they were found.
+    /// * [EmptyDocument](YamlParsingError::EmptyDocument) if there is nothing to read.
+    pub fn parse(&self, text: &str) -> Result<Value, YamlParsingError> {
+        match self {
+            Self::Yaml => {
+                let mut docs =
+                    YamlLoader::load_from_str(text).map_err(|err| YamlParsingError::Syntax {
+                        message: err.to_string(),
+                        location: Location::from(err.marker()),
+                    })?;
+                if docs.is_empty() {
+                    return Err(YamlParsingError::EmptyDocument);
+                }
+                Ok(Value::from(docs.remove(0)))
+            }
+            Self::Json => {
+                if text.trim().is_empty() {
+                    return Err(YamlParsingError::EmptyDocument);
+                }
+                let doc: serde_json::Value =
+                    serde_json::from_str(text).map_err(|err| YamlParsingError::Syntax {
+                        message: err.to_string(),
+                        location: Location {
+                            line: err.line(),
+                            column: err.column(),
+                        },
+                    })?;
+                Ok(Value::from(doc))
+            }
+            Self::Toml => {
+                let doc = text
+                    .parse::<toml::Table>()
+                    .map_err(|err| YamlParsingError::Syntax {
+                        message: err.message().to_string(),
+                        location: location_of(text, err.span().map_or(0, |span| span.start)),
+                    })?;
+                if doc.is_empty() {
+                    return Err(YamlParsingError::EmptyDocument);
+                }
+                Ok(Value::from(toml::Value::Table(doc)))
+            }
+        }
+    }
+
+    /// Writes a document in this format. TOML has no null values, so null fields are left out.
+    pub fn emit(&self, doc: &Value) -> Result<String, YamlParsingError> {
+        let emit_error = |err: &dyn fmt::Debug| YamlParsingError::Emit(format!("{:?}", err));
+
+        match self {
+            Self::Yaml => {
+                let mut out = String::new();
+                YamlEmitter::new(&mut out)
+                    .dump(&Yaml::from(doc))
+                    .map_err(|err| emit_error(&err))?;
+                out.push('\n');
+                Ok(out)
+            }
+            Self::Json => {
+                let mut out = serde_json::to_string_pretty(&serde_json::Value::from(doc))
+                    .map_err(|err| emit_error(&err))?;
+                out.push('\n');
+                Ok(out)
+            }
+            Self::Toml => match toml_value(doc) {
+                Some(toml::Value::Table(table)) => {
+                    toml::to_string(&table).map_err(|err| emit_error(&err))
+                }
+                _ => Err(YamlParsingError::Emit(String::from(
+                    "TOML documents must be tables",
+                ))),
+            },
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Ok(Self::Yaml),
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            _ => Err(format!("'{}' is not a known layout format", text)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Yaml => write!(f, "yaml"),
+            Self::Json => write!(f, "json"),
+            Self::Toml => write!(f, "toml"),
+        }
+    }
+}
+
+/// Line and column of a byte offset of the text.
+fn location_of(text: &str, offset: usize) -> Location {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    Location {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+/// A node of a document.
+///
+/// Like in YAML, reading a field or an element that does not exist gives
+/// [Missing](Value::Missing) instead of panicking, so nested fields can be read at once:
+/// ```
+/// # use lion_synth::document::{Format, Value};
+/// let doc = Format::Yaml.parse("config:\n  frequency: 440.0").unwrap();
+/// assert_eq!(doc["config"]["frequency"].as_f64(), Some(440.0));
+/// assert!(doc["config"]["phase"].is_missing());
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Real(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(Map),
+    /// A node that is not in the document, or could not be represented.
+    #[default]
+    Missing,
+}
+
+static MISSING: Value = Value::Missing;
+
+impl Value {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The value of a number. Integers are widened, as JSON and TOML writers often drop the
+    /// fraction of round reals: `440` reads the same as `440.0`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Real(value) => Some(*value),
+            Self::Integer(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&Vec<Value>> {
+        match self {
+            Self::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&Map> {
+        match self {
+            Self::Map(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Self::Null)
+    }
+
+    pub fn is_missing(&self) -> bool {
+        matches!(self, Self::Missing)
+    }
+}
+
+impl Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        match self {
+            Self::Map(map) => map.get(key).unwrap_or(&MISSING),
+            _ => &MISSING,
+        }
+    }
+}
+
+impl Index<usize> for Value {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        match self {
+            Self::List(list) => list.get(index).unwrap_or(&MISSING),
+            _ => &MISSING,
+        }
+    }
+}
+
+/// The fields of a [Value::Map], in the order they were written.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Map {
+    entries: Vec<(String, Value)>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.entries
+            .iter_mut()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Sets a field, returning its previous value. New fields are added at the end.
+    pub fn insert(&mut self, key: impl Into<String>, value: Value) -> Option<Value> {
+        let key = key.into();
+        match self.get_mut(&key) {
+            Some(old) => Some(std::mem::replace(old, value)),
+            None => {
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let index = self.entries.iter().position(|(name, _)| name == key)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Value)> {
+        self.entries.iter_mut().map(|(name, value)| (&*name, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(name, _)| name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K: Into<String>> FromIterator<(K, Value)> for Map {
+    fn from_iter<I: IntoIterator<Item = (K, Value)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+// CONVERSIONS
+
+impl From<Yaml> for Value {
+    fn from(yaml: Yaml) -> Self {
+        match yaml {
+            Yaml::Null => Self::Null,
+            Yaml::Boolean(value) => Self::Boolean(value),
+            Yaml::Integer(value) => Self::Integer(value),
+            Yaml::Real(_) => yaml.as_f64().map_or(Self::Missing, Self::Real),
+            Yaml::String(value) => Self::String(value),
+            Yaml::Array(list) => Self::List(list.into_iter().map(Self::from).collect()),
+            Yaml::Hash(hash) => Self::Map(
+                hash.into_iter()
+                    .map(|(key, value)| (yaml_key(key), Self::from(value)))
+                    .collect(),
+            ),
+            Yaml::Alias(_) | Yaml::BadValue => Self::Missing,
+        }
+    }
+}
+
+/// Keys are always read as strings, so `1: a` and `"1": a` are the same field.
+fn yaml_key(key: Yaml) -> String {
+    match key {
+        Yaml::String(key) | Yaml::Real(key) => key,
+        Yaml::Integer(key) => key.to_string(),
+        Yaml::Boolean(key) => key.to_string(),
+        _ => String::from("~"),
+    }
+}
+
+impl From<&Value> for Yaml {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Yaml::Null,
+            Value::Boolean(value) => Yaml::Boolean(*value),
+            Value::Integer(value) => Yaml::Integer(*value),
+            Value::Real(value) => Yaml::Real(format!("{:?}", value)),
+            Value::String(value) => Yaml::String(value.clone()),
+            Value::List(list) => Yaml::Array(list.iter().map(Yaml::from).collect()),
+            Value::Map(map) => {
+                let mut hash = Hash::new();
+                for (key, value) in map.iter() {
+                    hash.insert(Yaml::String(key.clone()), Yaml::from(value));
+                }
+                Yaml::Hash(hash)
+            }
+            Value::Missing => Yaml::BadValue,
+        }
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        match json {
+            serde_json::Value::Null => Self::Null,
+            serde_json::Value::Bool(value) => Self::Boolean(value),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(value) => Self::Integer(value),
+                None => number.as_f64().map_or(Self::Missing, Self::Real),
+            },
+            serde_json::Value::String(value) => Self::String(value),
+            serde_json::Value::Array(list) => {
+                Self::List(list.into_iter().map(Self::from).collect())
+            }
+            serde_json::Value::Object(object) => Self::Map(
+                object
+                    .into_iter()
+                    .map(|(key, value)| (key, Self::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<&Value> for serde_json::Value {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null | Value::Missing => serde_json::Value::Null,
+            Value::Boolean(value) => serde_json::Value::Bool(*value),
+            Value::Integer(value) => serde_json::Value::from(*value),
+            Value::Real(value) => serde_json::Value::from(*value),
+            Value::String(value) => serde_json::Value::String(value.clone()),
+            Value::List(list) => {
+                serde_json::Value::Array(list.iter().map(serde_json::Value::from).collect())
+            }
+            Value::Map(map) => serde_json::Value::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), serde_json::Value::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<toml::Value> for Value {
+    fn from(toml: toml::Value) -> Self {
+        match toml {
+            toml::Value::Boolean(value) => Self::Boolean(value),
+            toml::Value::Integer(value) => Self::Integer(value),
+            toml::Value::Float(value) => Self::Real(value),
+            toml::Value::String(value) => Self::String(value),
+            toml::Value::Datetime(value) => Self::String(value.to_string()),
+            toml::Value::Array(list) => Self::List(list.into_iter().map(Self::from).collect()),
+            toml::Value::Table(table) => Self::Map(
+                table
+                    .into_iter()
+                    .map(|(key, value)| (key, Self::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// TOML has no null values: null nodes give `None`, and are left out of lists and tables.
+fn toml_value(value: &Value) -> Option<toml::Value> {
+    match value {
+        Value::Null | Value::Missing => None,
+        Value::Boolean(value) => Some(toml::Value::Boolean(*value)),
+        Value::Integer(value) => Some(toml::Value::Integer(*value)),
+        Value::Real(value) => Some(toml::Value::Float(*value)),
+        Value::String(value) => Some(toml::Value::String(value.clone())),
+        Value::List(list) => Some(toml::Value::Array(
+            list.iter().filter_map(toml_value).collect(),
+        )),
+        Value::Map(map) => Some(toml::Value::Table(
+            map.iter()
+                .filter_map(|(key, value)| Some((key.clone(), toml_value(value)?)))
+                .collect(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: oscillator
+      os-out: true
+      config:
+        name: \"1\"
+        frequency: 440.0
+";
+
+    #[test]
+    fn test_formats() {
+        let doc = Format::Yaml.parse(YAML).unwrap();
+        assert_eq!(doc["layout"][0]["module"]["id"].as_i64(), Some(0));
+        assert_eq!(
+            doc["layout"][0]["module"]["config"]["name"].as_str(),
+            Some("1")
+        );
+        assert!(doc["layout"][1]["module"].is_missing());
+
+        for format in [Format::Yaml, Format::Json, Format::Toml] {
+            let text = format.emit(&doc).unwrap();
+            assert_eq!(format.parse(&text).unwrap(), doc, "{} round trip", format);
+        }
+
+        let json = Format::Json.emit(&doc).unwrap();
+        assert!(json.starts_with("{\n  \"version\": 0.5,\n  \"layout\""));
+        let toml = Format::Toml.emit(&doc).unwrap();
+        assert!(toml.contains("[layout.module.config]"));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let location = |format: Format, text: &str| match format.parse(text) {
+            Err(YamlParsingError::Syntax { location, .. }) => location,
+            result => panic!("Expected a syntax error, got {:?}", result),
+        };
+
+        assert_eq!(
+            location(Format::Yaml, "layout:\n  - a\n b: c"),
+            Location { line: 3, column: 3 }
+        );
+        assert_eq!(
+            location(Format::Json, "{\n  \"layout\": [,]\n}"),
+            Location {
+                line: 2,
+                column: 14
+            }
+        );
+        assert_eq!(
+            location(Format::Toml, "version = 0.5\nlayout = ["),
+            Location {
+                line: 2,
+                column: 11
+            }
+        );
+
+        for format in [Format::Yaml, Format::Json, Format::Toml] {
+            assert!(matches!(
+                format.parse(""),
+                Err(YamlParsingError::EmptyDocument)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(Format::from_path("a/b.yml"), Some(Format::Yaml));
+        assert_eq!(Format::from_path("a/b.JSON"), Some(Format::Json));
+        assert_eq!(Format::from_path("b.toml"), Some(Format::Toml));
+        assert_eq!(Format::from_path("b.txt"), None);
+        assert_eq!(Format::from_path("b"), None);
+    }
+}
@@ -0,0 +1,417 @@
+//! Variables and arithmetic expressions in layouts.
+//!
+//! A layout may declare numeric variables under `vars`, and use them in the config of its modules
+//! (and in the range of its auxiliaries) through expressions:
+//! ```yaml
+//! vars:
+//!   root: 523.25
+//!   detune: 20c                         # 20 cents, as a frequency ratio
+//!   fifth: "${root} * 2^(7/12)"         # variables may use the previous ones
+//!
+//! layout:
+//!   - module:
+//!       ...
+//!       config:
+//!         frequency: "${fifth} * ${detune}"
+//! ```
+//! Expressions support `+`, `-`, `*`, `/`, `^` (power) and parentheses. Numbers may carry a unit
+//! turning them into a frequency ratio: `c` for cents and `st` for semitones, so `7st` is a fifth
+//! and `-1200c` an octave down.
+//!
+//! Config values are only read as expressions if they use a variable or are a number with a
+//! unit, so plain strings (such as names) are left untouched. Expressions always result in a
+//! float.
+
+use crate::document::Value;
+use crate::layout_yaml::YamlParsingError;
+use std::collections::HashMap;
+
+/// Reads the `vars` of a document, in order, so each variable may use the previous ones.
+pub(crate) fn read_vars(doc: &Value) -> Result<HashMap<String, f64>, YamlParsingError> {
+    let mut vars = HashMap::new();
+
+    let definitions = match &doc["vars"] {
+        Value::Map(definitions) => definitions,
+        Value::Missing => return Ok(vars),
+        _ => {
+            return Err(YamlParsingError::in_field(
+                None,
+                String::from("vars"),
+                YamlParsingError::WrongFormat {
+                    field_name: String::from("vars"),
+                    supported_format: String::from("map of numbers and expressions"),
+                },
+            ))
+        }
+    };
+
+    for (name, value) in definitions.iter() {
+        let name = name.to_string();
+        let field = format!("vars.{}", name);
+
+        let value = match value {
+            Value::Real(x) => Some(*x),
+            Value::Integer(x) => Some(*x as f64),
+            Value::String(expression) => Some(
+                evaluate(expression, &vars)
+                    .map_err(|err| YamlParsingError::in_field(None, field.clone(), err))?,
+            ),
+            _ => None,
+        };
+        match value {
+            Some(value) => vars.insert(name, value),
+            None => {
+                return Err(YamlParsingError::in_field(
+                    None,
+                    field,
+                    YamlParsingError::WrongFormat {
+                        field_name: name,
+                        supported_format: String::from("f64, i64, expression"),
+                    },
+                ))
+            }
+        };
+    }
+
+    Ok(vars)
+}
+
+/// Evaluates the expressions found in the config and the auxiliaries of a module, replacing
+/// them by their result.
+pub(crate) fn substitute(
+    module: &mut Value,
+    path: &str,
+    vars: &HashMap<String, f64>,
+) -> Result<(), YamlParsingError> {
+    let module_id = module["id"].as_i64();
+    let module = match module {
+        Value::Map(module) => module,
+        _ => return Ok(()),
+    };
+
+    if let Some(config) = module.get_mut("config") {
+        substitute_node(config, &format!("{}.config", path), module_id, vars)?;
+    }
+
+    if let Some(Value::List(auxiliaries)) = module.get_mut("auxiliaries") {
+        for (index, aux) in auxiliaries.iter_mut().enumerate() {
+            if let Value::Map(aux) = aux {
+                if let Some(Value::Map(aux)) = aux.get_mut("aux") {
+                    for field in ["max", "min"] {
+                        if let Some(value) = aux.get_mut(field) {
+                            let path = format!("{}.auxiliaries[{}].aux.{}", path, index, field);
+                            substitute_node(value, &path, module_id, vars)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn substitute_node(
+    node: &mut Value,
+    path: &str,
+    module_id: Option<i64>,
+    vars: &HashMap<String, f64>,
+) -> Result<(), YamlParsingError> {
+    match node {
+        Value::String(text) if is_expression(text) => {
+            let value = evaluate(text, vars)
+                .map_err(|err| YamlParsingError::in_field(module_id, path.to_string(), err))?;
+            *node = Value::Real(value);
+        }
+        Value::Map(map) => {
+            for (name, value) in map.iter_mut() {
+                let path = format!("{}.{}", path, name);
+                substitute_node(value, &path, module_id, vars)?;
+            }
+        }
+        Value::List(list) => {
+            for (index, value) in list.iter_mut().enumerate() {
+                substitute_node(value, &format!("{}[{}]", path, index), module_id, vars)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Whether a string of the config is an expression: it uses a variable or it is a number with a
+/// unit.
+fn is_expression(text: &str) -> bool {
+    if text.contains("${") {
+        return true;
+    }
+
+    let text = text.trim();
+    let number = text
+        .strip_suffix("st")
+        .or_else(|| text.strip_suffix('c'))
+        .unwrap_or_default();
+    number.parse::<f64>().is_ok()
+}
+
+/// Evaluates an expression, using the given variables.
+pub(crate) fn evaluate(
+    expression: &str,
+    vars: &HashMap<String, f64>,
+) -> Result<f64, YamlParsingError> {
+    let mut parser = ExpressionParser {
+        expression,
+        chars: expression.char_indices().peekable(),
+        vars,
+    };
+
+    let value = parser.parse_sum()?;
+    parser.skip_spaces();
+    if let Some(&(_, c)) = parser.chars.peek() {
+        return Err(parser.fail(format!("unexpected '{}'", c)));
+    }
+    if !value.is_finite() {
+        return Err(parser.fail(String::from("the result is not a finite number")));
+    }
+
+    Ok(value)
+}
+
+/// A recursive descent parser, evaluating the expression as it is read.
+struct ExpressionParser<'a> {
+    expression: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    vars: &'a HashMap<String, f64>,
+}
+
+impl ExpressionParser<'_> {
+    /// `sum := product (('+' | '-') product)*`
+    fn parse_sum(&mut self) -> Result<f64, YamlParsingError> {
+        let mut value = self.parse_product()?;
+
+        loop {
+            match self.peek() {
+                Some('+') => {
+                    self.chars.next();
+                    value += self.parse_product()?;
+                }
+                Some('-') => {
+                    self.chars.next();
+                    value -= self.parse_product()?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// `product := unary (('*' | '/') unary)*`
+    fn parse_product(&mut self) -> Result<f64, YamlParsingError> {
+        let mut value = self.parse_unary()?;
+
+        loop {
+            match self.peek() {
+                Some('*') => {
+                    self.chars.next();
+                    value *= self.parse_unary()?;
+                }
+                Some('/') => {
+                    self.chars.next();
+                    value /= self.parse_unary()?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// `unary := '-' unary | power`
+    fn parse_unary(&mut self) -> Result<f64, YamlParsingError> {
+        if self.peek() == Some('-') {
+            self.chars.next();
+
+            // A unit applies to the signed number, so `-1200c` is an octave down
+            let mut negative = true;
+            let value = self.parse_power(&mut negative)?;
+            return Ok(if negative { -value } else { value });
+        }
+
+        self.parse_power(&mut false)
+    }
+
+    /// `power := atom ('^' unary)?`, so it is right associative and `-2^2` is `-4`.
+    fn parse_power(&mut self, negative: &mut bool) -> Result<f64, YamlParsingError> {
+        let base = self.parse_atom(negative)?;
+
+        if self.peek() == Some('^') {
+            self.chars.next();
+            return Ok(base.powf(self.parse_unary()?));
+        }
+
+        Ok(base)
+    }
+
+    /// `atom := number unit? | '${' name '}' | '(' sum ')'`
+    /// A number with a unit takes the sign in front of it, clearing `negative`.
+    fn parse_atom(&mut self, negative: &mut bool) -> Result<f64, YamlParsingError> {
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let value = self.parse_sum()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(value)
+                    }
+                    _ => Err(self.fail(String::from("missing ')'"))),
+                }
+            }
+            Some('$') => {
+                self.chars.next();
+                if self.chars.next().map(|(_, c)| c) != Some('{') {
+                    return Err(self.fail(String::from("variables are written as ${name}")));
+                }
+
+                let name: String = self.take_while(|c| c != '}');
+                if self.chars.next().is_none() {
+                    return Err(self.fail(String::from("missing '}'")));
+                }
+
+                self.vars
+                    .get(name.trim())
+                    .copied()
+                    .ok_or_else(|| YamlParsingError::UndefinedVariable(name.trim().to_string()))
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number: String = self.take_while(|c| c.is_ascii_digit() || c == '.');
+                let mut value = number
+                    .parse::<f64>()
+                    .map_err(|_| self.fail(format!("'{}' is not a number", number)))?;
+
+                let unit: String = self.take_while(|c| c.is_ascii_alphabetic());
+                if !unit.is_empty() && *negative {
+                    *negative = false;
+                    value = -value;
+                }
+                match unit.as_str() {
+                    "" => Ok(value),
+                    "c" => Ok(2f64.powf(value / 1200.0)),
+                    "st" => Ok(2f64.powf(value / 12.0)),
+                    unit => Err(self.fail(format!("unknown unit '{}', use 'c' or 'st'", unit))),
+                }
+            }
+            Some(c) => Err(self.fail(format!("unexpected '{}'", c))),
+            None => Err(self.fail(String::from("unexpected end"))),
+        }
+    }
+
+    /// The next character, skipping the spaces before it.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_spaces();
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut text = String::new();
+        while let Some((_, c)) = self.chars.next_if(|(_, c)| predicate(*c)) {
+            text.push(c);
+        }
+        text
+    }
+
+    fn fail(&self, reason: String) -> YamlParsingError {
+        YamlParsingError::InvalidExpression {
+            expression: self.expression.to_string(),
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Format;
+
+    fn eval(expression: &str) -> f64 {
+        let vars = HashMap::from([(String::from("root"), 440.0)]);
+        evaluate(expression, &vars).unwrap()
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("${root} / 2"), 220.0);
+        assert_eq!(eval("${root} * 12st"), 880.0);
+        assert_eq!(eval("${ root } * -1200c"), 220.0);
+        assert!((eval("-(2) * -3st^4") + 1.0).abs() < 1e-9);
+        assert!((eval("${root} * 2^(7/12)") - 659.255).abs() < 1e-3);
+
+        let vars = HashMap::new();
+        assert!(matches!(
+            evaluate("${root}", &vars),
+            Err(YamlParsingError::UndefinedVariable(_))
+        ));
+        for wrong in ["1 +", "(1", "2 ** 3", "3x", "1 / 0", "${root"] {
+            assert!(
+                matches!(
+                    evaluate(wrong, &vars),
+                    Err(YamlParsingError::InvalidExpression { .. })
+                ),
+                "'{}' should not be valid",
+                wrong
+            );
+        }
+    }
+
+    #[test]
+    fn test_substitute() {
+        let doc = Format::Yaml
+            .parse(
+                "\
+vars:
+  root: 220
+  fifth: ${root} * 7st
+  detune: 20c
+module:
+  id: 4
+  config:
+    name: C5
+    id-name: \"1\"
+    frequency: ${fifth} * ${detune}
+    phase: 0.5
+    gain: -6st
+  auxiliaries:
+    - aux:
+        max: ${root} * 2
+",
+            )
+            .unwrap();
+
+        let vars = read_vars(&doc).unwrap();
+        assert!((vars["fifth"] - 329.628).abs() < 1e-3);
+
+        let mut module = doc["module"].clone();
+        substitute(&mut module, "layout[0].module", &vars).unwrap();
+        let config = &module["config"];
+        assert_eq!(config["name"].as_str(), Some("C5"));
+        assert_eq!(config["id-name"].as_str(), Some("1"));
+        assert!((config["frequency"].as_f64().unwrap() - 333.458).abs() < 1e-3);
+        assert_eq!(config["phase"].as_f64(), Some(0.5));
+        assert_eq!(config["gain"].as_f64(), Some(2f64.powf(-0.5)));
+        assert_eq!(module["auxiliaries"][0]["aux"]["max"].as_f64(), Some(440.0));
+
+        let mut module = doc["module"].clone();
+        let vars = HashMap::new();
+        let err = substitute(&mut module, "layout[0].module", &vars).unwrap_err();
+        assert!(matches!(err.kind(), YamlParsingError::UndefinedVariable(_)));
+        assert_eq!(err.module_id(), Some(4));
+        assert_eq!(err.field(), Some("layout[0].module.config.frequency"));
+    }
+}
@@ -0,0 +1,537 @@
+//! Plays a layout file in real time, reloading it whenever the file changes.
+//!
+//! The file is checked periodically from a watcher thread, which also builds the new chain, so
+//! the loop feeding the audio device never stops to parse a layout. Once built, the new chain
+//! [takes over](fn@crate::module::CoordinatorEntity::continue_from) the clock, the transport and
+//! the [handles](struct@crate::module::ControlHandle) of the old one, and both are crossfaded.
+//! Modules whose type and config did not change take over the parameter values set while playing
+//! and the [state](fn@crate::module::Module::get_state) of the old ones, such as the position of
+//! the oscillators, so the parts of the patch that did not change keep on sounding the same
+//! through the reload. The modules edited start over.
+//!
+//! Layouts that cannot be loaded are reported and ignored, so a typo while editing never stops
+//! the playback.
+
+use crate::audio_backend::{AudioBackend, CpalBackend};
+use crate::back_end::OutputConfig;
+use crate::document::{Format, Value};
+use crate::layout_yaml::{Layout, RealTimeEngine, YamlParsingError, BATCH_SIZE_RT};
+use crate::module_registry::ModuleRegistry;
+use crate::patch::Patch;
+use crossbeam::channel::{Receiver, Sender};
+use ringbuf::HeapRb;
+use simplelog::{error, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Length of the crossfade between the chain being played and the reloaded one, in milliseconds.
+pub const DEFAULT_CROSSFADE: u32 = 50;
+/// How often the layout file is checked for changes, in milliseconds.
+pub const DEFAULT_POLL_INTERVAL: u64 = 250;
+
+/// Type and config of every module of a patch, telling which modules a reload left untouched.
+type ModuleSignatures = HashMap<i64, (String, Value)>;
+
+/// A chain built from the layout once changed, ready to replace the one being played.
+struct Reload {
+    engine: RealTimeEngine,
+    /// IDs of the modules whose type and config did not change.
+    unchanged: Vec<i64>,
+}
+
+/// Plays a layout file in real time, reloading it whenever it changes. See the
+/// [module](crate::hot_reload) documentation.
+/// # Example
+/// ```no_run
+/// # use lion_synth::hot_reload::HotReloadEngine;
+/// HotReloadEngine::new("patches/bass.yaml", 44100)
+///     .with_crossfade(100)
+///     .play(60_000)
+///     .unwrap();
+/// ```
+pub struct HotReloadEngine {
+    path: PathBuf,
+    format: Option<Format>,
+    registry: ModuleRegistry,
+    sample_rate: i32,
+    crossfade: u32,
+    poll_interval: Duration,
+    output: OutputConfig,
+}
+
+impl HotReloadEngine {
+    pub fn new(path: impl Into<PathBuf>, sample_rate: i32) -> Self {
+        Self {
+            path: path.into(),
+            format: None,
+            registry: ModuleRegistry::default(),
+            sample_rate,
+            crossfade: DEFAULT_CROSSFADE,
+            poll_interval: Duration::from_millis(DEFAULT_POLL_INTERVAL),
+            output: OutputConfig::default(),
+        }
+    }
+
+    /// Sets the registry the modules are created by, so the layout may use custom module types.
+    pub fn with_registry(mut self, registry: ModuleRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Sets the format of the layout, overriding the one given by the extension of the file.
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Sets the length of the crossfade, in milliseconds.
+    pub fn with_crossfade(mut self, milliseconds: u32) -> Self {
+        self.crossfade = milliseconds;
+        self
+    }
+
+    /// Sets how often the layout file is checked for changes.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Sets the output the layout is played through, the default device otherwise.
+    pub fn with_output(mut self, output: OutputConfig) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Plays the layout for the given amount of milliseconds. Only the first load of the layout
+    /// may fail, later ones are reported and skipped.
+    pub fn play(self, signal_duration: i32) -> Result<(), anyhow::Error> {
+        let mut backend = CpalBackend::new(self.output.clone());
+        self.play_with_backend(signal_duration, &mut backend)
+    }
+
+    /// Does the same as [play](HotReloadEngine::play) through any [AudioBackend]. The output set
+    /// with [with_output](HotReloadEngine::with_output) is ignored.
+    pub fn play_with_backend(
+        self,
+        signal_duration: i32,
+        backend: &mut dyn AudioBackend,
+    ) -> Result<(), anyhow::Error> {
+        let mut layout = Layout::from_path(&self.path)?;
+        if let Some(format) = self.format {
+            layout = layout.with_format(format);
+        }
+
+        let loader = Loader {
+            format: layout.get_format(),
+            base_dir: layout.get_base_dir().map(Path::to_path_buf),
+            registry: self.registry,
+            sample_rate: self.sample_rate,
+        };
+        let (mut engine, signatures) = loader.load(&layout)?;
+        let coordinator = engine.get_mut_coordinator();
+        coordinator.display_order();
+        coordinator.reset();
+        coordinator.get_mut_transport().start();
+
+        let ring_buffer: HeapRb<f32> = HeapRb::new(BATCH_SIZE_RT);
+        let (mut producer, mut consumer) = ring_buffer.split();
+        backend.start(self.sample_rate, Box::new(move || consumer.pop()))?;
+
+        let (reload_sender, reload_receiver) = crossbeam::channel::bounded(1);
+        let (retired_sender, retired_receiver) = crossbeam::channel::unbounded();
+        let stop = Arc::new(AtomicBool::new(false));
+        let watcher = Watcher {
+            path: self.path.clone(),
+            poll_interval: self.poll_interval,
+            document: layout.get_document().to_string(),
+            signatures,
+            loader,
+            reloads: reload_sender,
+            retired: retired_receiver,
+            stop: Arc::clone(&stop),
+        };
+        let watcher = thread::spawn(move || watcher.run());
+
+        let fade_length = (self.crossfade as i64 * self.sample_rate as i64 / 1000) as usize;
+        let mut switch = ChainSwitch::new(engine, fade_length);
+
+        let mut logger = simplelog::__private::paris::Logger::new();
+        info!("<b>Signal duration: <u>{} milliseconds</>", signal_duration);
+        info!(
+            "<b>Watching <blue>{}</><b> for changes.</>",
+            self.path.display()
+        );
+        logger.loading("<blue><info></><b> Playing sound</>");
+
+        let mut count = 0;
+        while count < (signal_duration as f32 * self.sample_rate as f32 / 1000.0) as i32 {
+            if producer.is_full() {
+                continue;
+            }
+            if !switch.is_fading() {
+                if let Ok(reload) = reload_receiver.try_recv() {
+                    switch.replace(reload);
+                }
+            }
+
+            let _ = producer.push(switch.next_sample());
+            // Dropping a chain frees memory, which is left to the watcher thread
+            if let Some(retired) = switch.take_retired() {
+                let _ = retired_sender.send(retired);
+            }
+            count += 1;
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        drop(reload_receiver);
+        if watcher.join().is_err() {
+            error!("<b>The layout <red>watcher</><b> stopped unexpectedly.</>");
+        }
+        backend.stop()?;
+        switch.release();
+        logger.done();
+
+        Ok(())
+    }
+}
+
+/// Everything needed to build a chain from the layout, moved to the watcher thread.
+struct Loader {
+    format: Format,
+    base_dir: Option<PathBuf>,
+    registry: ModuleRegistry,
+    sample_rate: i32,
+}
+
+impl Loader {
+    fn load(
+        &self,
+        layout: &Layout,
+    ) -> Result<(RealTimeEngine, ModuleSignatures), YamlParsingError> {
+        let patch = layout.to_patch(&self.registry)?;
+        let signatures = module_signatures(&patch);
+
+        Ok((
+            RealTimeEngine::from_patch(patch, self.sample_rate)?,
+            signatures,
+        ))
+    }
+
+    fn load_document(
+        &self,
+        document: &str,
+    ) -> Result<(RealTimeEngine, ModuleSignatures), YamlParsingError> {
+        let mut layout = Layout::from_document(document).with_format(self.format);
+        if let Some(dir) = &self.base_dir {
+            layout = layout.with_base_dir(dir);
+        }
+
+        self.load(&layout)
+    }
+}
+
+/// Checks the layout file for changes, building a new chain every time it does.
+struct Watcher {
+    path: PathBuf,
+    poll_interval: Duration,
+    /// The document the chain being played was built from.
+    document: String,
+    signatures: ModuleSignatures,
+    loader: Loader,
+    reloads: Sender<Reload>,
+    /// Chains already replaced, to be dropped here.
+    retired: Receiver<RealTimeEngine>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    fn run(mut self) {
+        while !self.stop.load(Ordering::Relaxed) {
+            thread::sleep(self.poll_interval);
+            self.retired.try_iter().for_each(drop);
+
+            if let Some(reload) = self.check() {
+                // Waits until the chain is taken, failing once the playback is over
+                if self.reloads.send(reload).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Reads the layout file, returning a new chain if it changed and could be loaded.
+    fn check(&mut self) -> Option<Reload> {
+        // The file may be missing for a moment while an editor saves it
+        let document = fs::read_to_string(&self.path).ok()?;
+        if document == self.document {
+            return None;
+        }
+        self.document = document;
+
+        info!("<b>Reloading <blue>{}</><b>.</>", self.path.display());
+        match self.loader.load_document(&self.document) {
+            Ok((engine, signatures)) => {
+                let unchanged = signatures
+                    .iter()
+                    .filter(|(id, signature)| self.signatures.get(id) == Some(signature))
+                    .map(|(id, _)| *id)
+                    .collect();
+                self.signatures = signatures;
+
+                Some(Reload { engine, unchanged })
+            }
+            Err(err) => {
+                error!(
+                    "<b>Could not reload <red>{}</><b>: {}</>",
+                    self.path.display(),
+                    err
+                );
+                warn!("  |_ the current chain keeps playing");
+                None
+            }
+        }
+    }
+}
+
+/// Type and config of every module of the patch able to describe itself.
+fn module_signatures(patch: &Patch) -> ModuleSignatures {
+    patch
+        .get_ids()
+        .into_iter()
+        .filter_map(|id| {
+            let module = patch.get_module(id)?;
+            module.get_layout_config().map(|signature| (id, signature))
+        })
+        .collect()
+}
+
+/// Plays a chain, crossfading it with the one replacing it on every reload.
+struct ChainSwitch {
+    current: RealTimeEngine,
+    incoming: Option<RealTimeEngine>,
+    retired: Option<RealTimeEngine>,
+    fade_position: usize,
+    fade_length: usize,
+}
+
+impl ChainSwitch {
+    fn new(engine: RealTimeEngine, fade_length: usize) -> Self {
+        Self {
+            current: engine,
+            incoming: None,
+            retired: None,
+            fade_position: 0,
+            fade_length,
+        }
+    }
+
+    fn is_fading(&self) -> bool {
+        self.incoming.is_some()
+    }
+
+    /// Starts fading in a reloaded chain. Must not be called while fading.
+    fn replace(&mut self, reload: Reload) {
+        let mut engine = reload.engine;
+        engine.continue_from(&mut self.current, &reload.unchanged);
+
+        self.incoming = Some(engine);
+        self.fade_position = 0;
+        if self.fade_length == 0 {
+            self.finish_fade();
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let old = self.current.next_sample();
+        let incoming = match self.incoming.as_mut() {
+            Some(incoming) => incoming,
+            None => return old,
+        };
+
+        // Most of the patch usually remains in phase, as the modules not edited take over the
+        // state of the previous ones, so a linear fade keeps the level steady.
+        self.fade_position += 1;
+        let gain = self.fade_position as f32 / self.fade_length as f32;
+        let sample = old * (1.0 - gain) + incoming.next_sample() * gain;
+
+        if self.fade_position >= self.fade_length {
+            self.finish_fade();
+        }
+        sample
+    }
+
+    fn finish_fade(&mut self) {
+        if let Some(incoming) = self.incoming.take() {
+            let mut previous = std::mem::replace(&mut self.current, incoming);
+            previous.get_mut_coordinator().release();
+            self.retired = Some(previous);
+        }
+    }
+
+    /// The chain replaced by the last reload, once the crossfade is over.
+    fn take_retired(&mut self) -> Option<RealTimeEngine> {
+        self.retired.take()
+    }
+
+    fn release(&mut self) {
+        self.finish_fade();
+        self.current.get_mut_coordinator().release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: &str = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: pass_through
+      os-out: true
+      input-from: 1
+  - module:
+      id: 1
+      type: oscillator
+      config:
+        frequency: 440.0
+";
+
+    fn engine(yaml: &str) -> (RealTimeEngine, ModuleSignatures) {
+        let loader = Loader {
+            format: Format::Yaml,
+            base_dir: None,
+            registry: ModuleRegistry::default(),
+            sample_rate: 44100,
+        };
+
+        loader.load_document(yaml).unwrap()
+    }
+
+    fn reload(yaml: &str, unchanged: Vec<i64>) -> Reload {
+        Reload {
+            engine: engine(yaml).0,
+            unchanged,
+        }
+    }
+
+    #[test]
+    fn test_same_layout() {
+        let mut reference = engine(LAYOUT).0;
+        let mut switch = ChainSwitch::new(engine(LAYOUT).0, 100);
+
+        for _ in 0..500 {
+            assert_eq!(switch.next_sample(), reference.next_sample());
+        }
+
+        switch.replace(reload(LAYOUT, vec![0, 1]));
+        for sample in 0..1000 {
+            let expected = reference.next_sample();
+            assert!((switch.next_sample() - expected).abs() < 1e-6);
+            assert_eq!(switch.is_fading(), sample < 99);
+        }
+        assert!(switch.take_retired().is_some());
+        assert!(switch.take_retired().is_none());
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let changed = LAYOUT.replace("440.0", "220.0");
+        let mut switch = ChainSwitch::new(engine(LAYOUT).0, 441);
+
+        let mut previous = 0.0;
+        for _ in 0..1000 {
+            previous = switch.next_sample();
+        }
+
+        // The oscillator edited starts over
+        let mut reference = engine(&changed).0;
+
+        // A sine wave at 440 Hz never moves more than 0.07 between samples
+        switch.replace(reload(&changed, vec![0]));
+        for _ in 0..441 {
+            let sample = switch.next_sample();
+            assert!((sample - previous).abs() < 0.07);
+            previous = sample;
+            reference.next_sample();
+        }
+
+        assert!(!switch.is_fading());
+        for _ in 0..1000 {
+            assert_eq!(switch.next_sample(), reference.next_sample());
+        }
+    }
+
+    #[test]
+    fn test_keep_parameters() {
+        let mut switch = ChainSwitch::new(engine(LAYOUT).0, 0);
+        let handle = switch.current.control_handle();
+        handle.set_parameter(1, "amplitude", 0.5).unwrap();
+        switch.next_sample();
+
+        // Unchanged modules keep the values set while playing, and the handle still works
+        switch.replace(reload(LAYOUT, vec![0, 1]));
+        assert!(!switch.is_fading());
+        switch.next_sample();
+        assert_eq!(handle.get_parameter(1, "amplitude"), Some(0.5));
+
+        handle.set_parameter(1, "frequency", 330.0).unwrap();
+        switch.next_sample();
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(330.0));
+
+        let changed = LAYOUT.replace("440.0", "220.0");
+        switch.replace(reload(&changed, vec![0]));
+        switch.next_sample();
+        assert_eq!(handle.get_parameter(1, "amplitude"), Some(1.0));
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(220.0));
+    }
+
+    #[test]
+    fn test_watcher() {
+        let path =
+            std::env::temp_dir().join(format!("lion_synth_watch_{}.yaml", std::process::id()));
+        fs::write(&path, LAYOUT).unwrap();
+
+        let (_, signatures) = engine(LAYOUT);
+        let (reloads, reload_receiver) = crossbeam::channel::bounded(1);
+        let (_, retired) = crossbeam::channel::unbounded();
+        let mut watcher = Watcher {
+            path: path.clone(),
+            poll_interval: Duration::from_millis(1),
+            document: LAYOUT.to_string(),
+            signatures,
+            loader: Loader {
+                format: Format::Yaml,
+                base_dir: None,
+                registry: ModuleRegistry::default(),
+                sample_rate: 44100,
+            },
+            reloads,
+            retired,
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        assert!(watcher.check().is_none());
+
+        // Broken layouts are skipped
+        fs::write(&path, LAYOUT.replace("input-from: 1", "input-from: 7")).unwrap();
+        assert!(watcher.check().is_none());
+
+        fs::write(&path, LAYOUT.replace("440.0", "220.0")).unwrap();
+        let reload = watcher.check().unwrap();
+        assert_eq!(reload.unchanged, vec![0]);
+        assert!(watcher.check().is_none());
+
+        // The thread gives up once the playback is over
+        fs::write(&path, LAYOUT).unwrap();
+        drop(reload_receiver);
+        thread::spawn(move || watcher.run()).join().unwrap();
+
+        fs::remove_file(path).unwrap();
+    }
+}
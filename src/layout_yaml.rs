@@ -1,29 +1,32 @@
-use crate::back_end::{get_preferred_config, write_data, Channels};
-use crate::bundled_modules::debug::*;
-use crate::bundled_modules::prelude::Sum3InBuilder;
-use crate::bundled_modules::WaveShape;
-use crate::bundled_modules::*;
+use crate::audio_backend::{AudioBackend, CpalBackend};
+use crate::back_end::OutputConfig;
+use crate::document::{Format, Value};
+use crate::expression::{read_vars, substitute};
+use crate::migration::{migrate, MigrationNote};
 use crate::module::{
-    AuxDataHolder, AuxInputBuilder, AuxiliaryInput, CoordinatorEntity, GeneratorModuleWrapper,
-    LinkerModuleWrapper, Module, ModuleProducer, ModuleWrapper,
+    advise_custom_fill, AuxDataHolder, AuxInputBuilder, AuxiliaryInput, ChainLinks, ControlHandle,
+    CoordinatorEntity, GeneratorModuleWrapper, LinkerModuleWrapper, Module, ModuleConsumer,
+    ModuleProducer, ModuleWrapper, Routing, TimeSignature, Transport,
 };
-use crate::SAMPLE_RATE;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, SampleFormat, SampleRate, StreamConfig};
+use crate::module_registry::{resolve_path, FactoryContext, ModuleRegistry};
+use crate::patch::{AuxRoute, ChainCell, Patch};
+use crate::sub_patch::{flatten, FlatModule};
 use ringbuf::HeapRb;
 use simplelog::{error, info, warn};
-use std::collections::{HashMap, LinkedList};
-use std::f32::consts::PI;
+use std::collections::{HashMap, HashSet, LinkedList};
+use std::fmt;
 use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::thread::sleep;
 use std::time::Duration;
-use yaml_rust::{Yaml, YamlLoader};
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
 
 // TODO test size. Different signal durations may be affected playback
-const BATCH_SIZE_RT: usize = 1000;
-const YAML_VERSION: &str = "0.5";
+pub(crate) const BATCH_SIZE_RT: usize = 1000;
+pub(crate) const YAML_VERSION: &str = "0.5";
 
-use crate::layout_yaml::YamlParsingError::UnknownType;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -56,117 +59,673 @@ pub enum YamlParsingError {
     #[error("'{0}' type not known.")]
     UnknownType(String),
 
+    #[error("{0} is not a valid tempo. It must be greater than zero.")]
+    InvalidTempo(f64),
+
+    #[error("Could not read '{path}': {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Invalid YAML syntax: {message}")]
+    Syntax { message: String, location: Location },
+    #[error("The document is empty.")]
+    EmptyDocument,
+    #[error("Missing 'layout' list of modules.")]
+    MissingLayout,
+    #[error("Modules {first} and {second} are both linked to the Operating System. There can only be one.")]
+    DuplicatedOpSysOutput { first: i64, second: i64 },
+    #[error("Module {0} not found in the layout.")]
+    ModuleNotFound(i64),
+    #[error("Module {0} feeds more than one module or is part of a cycle.")]
+    ReusedModule(i64),
+    #[error("The module could not be built: {0}")]
+    Builder(String),
+    #[error("The module has no parameter '{0}'.")]
+    UnknownParameter(String),
+    #[error("Module {0} is not connected to the Operating System output.")]
+    UnreachableModule(i64),
+    #[error("Module {0} can not be saved to a layout.")]
+    NotSerializable(i64),
+    #[error("Could not write the layout: {0}")]
+    Emit(String),
+    #[error("Layout upgraded from version {} to {}: {}", .0.from, .0.to, .0.change)]
+    Migrated(MigrationNote),
+    #[error("Sub-patch '{name}' is not valid: {reason}")]
+    InvalidSubPatch { name: String, reason: String },
+    #[error("Variable '{0}' is not defined.")]
+    UndefinedVariable(String),
+    #[error("Invalid expression '{expression}': {reason}")]
+    InvalidExpression { expression: String, reason: String },
+    #[error("Only {routed} of the {expected} extra inputs are routed to an auxiliary.")]
+    UnroutedInputs { expected: i64, routed: usize },
+
     // SUM MODULE
     #[error("{0} is not a valid amount of inputs.")]
     InvalidInputAmount(i64),
+
+    /// Wraps any other error with the place of the layout where it was found.
+    #[error("{source}{}", describe_context(*.module_id, .field, .location))]
+    Context {
+        module_id: Option<i64>,
+        field: Option<String>,
+        location: Option<Location>,
+        source: Box<YamlParsingError>,
+    },
+}
+
+impl YamlParsingError {
+    /// Wraps an error with the path of the field it was found in. The location is filled in
+    /// later, from the document the path belongs to.
+    pub(crate) fn in_field(
+        module_id: Option<i64>,
+        path: impl Into<String>,
+        error: YamlParsingError,
+    ) -> YamlParsingError {
+        Self::Context {
+            module_id,
+            field: Some(path.into()),
+            location: None,
+            source: Box::new(error),
+        }
+    }
+
+    /// The error itself, without the [context](YamlParsingError::Context) of where it happened.
+    pub fn kind(&self) -> &YamlParsingError {
+        match self {
+            Self::Context { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+
+    /// ID of the module where the error was found, if it belongs to any.
+    pub fn module_id(&self) -> Option<i64> {
+        match self {
+            Self::Context { module_id, .. } => *module_id,
+            _ => None,
+        }
+    }
+
+    /// Path of the field where the error was found, such as `layout[2].module.config.frequency`.
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            Self::Context { field, .. } => field.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Position of the error in the document, if known.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Self::Context { location, .. } => *location,
+            Self::Syntax { location, .. } => Some(*location),
+            _ => None,
+        }
+    }
+
+    /// The field of the `config` node the error refers to, if any.
+    fn config_field(&self) -> Option<&str> {
+        match self.kind() {
+            Self::MissingField(name) => Some(name),
+            Self::WrongFormat { field_name, .. } | Self::InvalidValue { field_name, .. } => {
+                Some(field_name)
+            }
+            Self::InvalidInputAmount(_) => Some("input-amount"),
+            _ => None,
+        }
+    }
+}
+
+fn describe_context(
+    module_id: Option<i64>,
+    field: &Option<String>,
+    location: &Option<Location>,
+) -> String {
+    let mut context = Vec::new();
+
+    if let Some(module_id) = module_id {
+        context.push(format!("module {}", module_id));
+    }
+    if let Some(field) = field {
+        context.push(format!("field '{}'", field));
+    }
+    if let Some(location) = location {
+        context.push(location.to_string());
+    }
+
+    if context.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", context.join(", "))
+    }
 }
 
-struct ChainCell {
-    from_module: Option<i64>,
-    module: Box<dyn Module>,
-    auxiliaries: Vec<AuxInfo>,
+/// A position in a layout document. Both line and column start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
 }
 
-struct AuxInfo {
-    from_module: i64,
-    linked_with: String,
-    max: Option<f32>,
-    min: Option<f32>,
+impl From<&Marker> for Location {
+    fn from(marker: &Marker) -> Self {
+        Self {
+            line: marker.line(),
+            column: marker.col() + 1,
+        }
+    }
 }
 
-fn load_yaml(
-    file: &str,
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// How serious a [Diagnostic] is. Layouts with errors can not be loaded, while warnings point to
+/// something that probably does not behave as intended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found while [validating](fn@Layout::validate) a layout. The error carries the
+/// [context](YamlParsingError::Context) of where it was found, whenever possible.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: YamlParsingError,
+}
+
+impl Diagnostic {
+    fn error(error: YamlParsingError) -> Self {
+        Self {
+            severity: Severity::Error,
+            error,
+        }
+    }
+
+    fn warning(error: YamlParsingError) -> Self {
+        Self {
+            severity: Severity::Warning,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.error),
+            Severity::Warning => write!(f, "warning: {}", self.error),
+        }
+    }
+}
+
+/// Every problem found in a layout, in the order they appear in the document.
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ValidationReport {
+    /// A layout is valid if it has no errors, even if it has warnings.
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Warning)
+    }
+}
+
+impl From<YamlParsingError> for ValidationReport {
+    /// A report for a layout that could not even be read.
+    fn from(error: YamlParsingError) -> Self {
+        Self {
+            diagnostics: vec![Diagnostic::error(error)],
+        }
+    }
+}
+
+/// Location of every node of a document, indexed by its path (`layout[0].module.id`). The
+/// [Value] tree does not keep track of where its nodes come from, so the document is parsed a
+/// second time for this purpose.
+///
+/// JSON documents are valid YAML, so both are located by the YAML parser. TOML documents are not
+/// located, so their errors only carry the path of the field.
+#[derive(Default)]
+struct Positions {
+    locations: HashMap<String, Location>,
+    stack: Vec<PositionFrame>,
+}
+
+enum PositionFrame {
+    Sequence {
+        path: String,
+        index: usize,
+    },
+    Mapping {
+        path: String,
+        key: Option<(String, Marker)>,
+    },
+}
+
+impl Positions {
+    fn from_source(source: &str, format: Format) -> Self {
+        let mut positions = Self::default();
+        if format != Format::Toml {
+            // Syntax errors have already been reported when loading the document
+            let _ = Parser::new(source.chars()).load(&mut positions, false);
+        }
+        positions
+    }
+
+    /// Location of a path. If the path is not present, such as a missing field, the location of
+    /// the closest parent is returned.
+    fn find(&self, path: &str) -> Option<Location> {
+        let mut path = path;
+        loop {
+            if let Some(location) = self.locations.get(path) {
+                return Some(*location);
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+
+    /// Wraps an error with the place where it was found.
+    fn error(
+        &self,
+        module_id: Option<i64>,
+        path: &str,
+        error: YamlParsingError,
+    ) -> YamlParsingError {
+        YamlParsingError::Context {
+            module_id,
+            field: Some(path.to_string()),
+            location: self.find(path),
+            source: Box::new(error),
+        }
+    }
+
+    /// Fills in the location of an error already carrying the path of its field.
+    fn locate(&self, error: YamlParsingError) -> YamlParsingError {
+        match error {
+            YamlParsingError::Context {
+                module_id,
+                field: Some(field),
+                location: None,
+                source,
+            } => YamlParsingError::Context {
+                module_id,
+                location: self.find(&field),
+                field: Some(field),
+                source,
+            },
+            error => error,
+        }
+    }
+
+    /// Path of the next node and where it starts. The values of a mapping are located at their key.
+    fn next_node(&self, marker: Marker) -> (String, Location) {
+        match self.stack.last() {
+            None => (String::new(), Location::from(&marker)),
+            Some(PositionFrame::Sequence { path, index }) => {
+                (format!("{}[{}]", path, index), Location::from(&marker))
+            }
+            Some(PositionFrame::Mapping { path, key }) => {
+                let (key, marker) = match key {
+                    Some((key, key_marker)) => (key.as_str(), key_marker),
+                    None => ("", &marker),
+                };
+                let path = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                (path, Location::from(marker))
+            }
+        }
+    }
+
+    /// Moves to the next element of the current sequence or mapping.
+    fn advance(&mut self) {
+        match self.stack.last_mut() {
+            Some(PositionFrame::Sequence { index, .. }) => *index += 1,
+            Some(PositionFrame::Mapping { key, .. }) => *key = None,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for Positions {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        match event {
+            Event::Scalar(value, ..) => {
+                if let Some(PositionFrame::Mapping {
+                    key: key @ None, ..
+                }) = self.stack.last_mut()
+                {
+                    *key = Some((value, marker));
+                    return;
+                }
+                let (path, location) = self.next_node(marker);
+                self.locations.insert(path, location);
+                self.advance();
+            }
+            Event::Alias(_) => {
+                let (path, location) = self.next_node(marker);
+                self.locations.insert(path, location);
+                self.advance();
+            }
+            Event::SequenceStart(_) => {
+                let (path, location) = self.next_node(marker);
+                self.locations.insert(path.clone(), location);
+                self.stack.push(PositionFrame::Sequence { path, index: 0 });
+            }
+            Event::MappingStart(_) => {
+                let (path, location) = self.next_node(marker);
+                self.locations.insert(path.clone(), location);
+                self.stack.push(PositionFrame::Mapping { path, key: None });
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.stack.pop();
+                self.advance();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Global settings of a layout, not related to any module.
+#[derive(Default)]
+struct LayoutSettings {
+    tempo: Option<f32>,
+    time_signature: Option<TimeSignature>,
+}
+
+/// Builds the module chain from the text of a layout, failing on the first error found.
+fn parse_yaml(
+    text: &str,
+    format: Format,
+    base_dir: Option<&Path>,
+    registry: &ModuleRegistry,
     first_module_index: &mut i64,
+    settings: &mut LayoutSettings,
 ) -> Result<HashMap<i64, ChainCell>, YamlParsingError> {
+    let mut diagnostics = Vec::new();
+    let module_chain = collect_layout(
+        text,
+        format,
+        base_dir,
+        registry,
+        first_module_index,
+        settings,
+        &mut diagnostics,
+    );
+
+    match diagnostics
+        .into_iter()
+        .find(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        Some(diagnostic) => Err(diagnostic.error),
+        None => Ok(module_chain),
+    }
+}
+
+/// Connections of a module, used to check the structure of the layout once every module has
+/// been read.
+struct ModuleLinks {
+    id: i64,
+    path: String,
+    input_from: Option<i64>,
+    /// ID of the module feeding each auxiliary, with the path of the auxiliary.
+    aux_from: Vec<(i64, String)>,
+}
+
+/// Builds the module chain from the text of a layout. Instead of stopping at the first problem,
+/// every module is checked and each problem found is added to `diagnostics`. The chain returned
+/// is only complete if no error has been reported.
+fn collect_layout(
+    text: &str,
+    format: Format,
+    base_dir: Option<&Path>,
+    registry: &ModuleRegistry,
+    first_module_index: &mut i64,
+    settings: &mut LayoutSettings,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> HashMap<i64, ChainCell> {
     use YamlParsingError::*;
 
+    let mut module_chain: HashMap<i64, ChainCell> = HashMap::new();
     let mut first_module: Option<i64> = None;
-    let path = format!("layouts/{}", file);
-    info!("<b>Loading data from <red>{}</><b>.</>", path);
-    let yaml = &fs::read_to_string(path).unwrap();
-
-    let doc = YamlLoader::load_from_str(yaml).unwrap();
-    let doc = &doc[0];
-
-    let version = &doc["version"];
-    let version = match version {
-        Yaml::Real(_) => version.as_f64().map(|x| x.to_string()),
-        Yaml::String(_) => version.as_str().map(|x| x.to_string()),
-        Yaml::BadValue => return Err(MissingVersionNumber(YAML_VERSION.to_string())),
+
+    let mut doc = match format.parse(text) {
+        Ok(doc) => doc,
+        Err(err) => {
+            if let Syntax { .. } = err {
+                error!("<b>Invalid <red>{} syntax</><b>.</>", format);
+            }
+            diagnostics.push(Diagnostic::error(err));
+            return module_chain;
+        }
+    };
+    let positions = Positions::from_source(text, format);
+
+    match migrate(&mut doc) {
+        Ok(notes) => {
+            for note in notes {
+                let field = note.field.clone();
+                diagnostics.push(Diagnostic::warning(positions.error(
+                    note.module_id,
+                    &field,
+                    Migrated(note),
+                )));
+            }
+        }
+        Err(err) => {
+            let err = match err {
+                MissingVersionNumber(_) => err,
+                err => {
+                    if let VersionMismatch { .. } = err {
+                        error!("<b>Please use the <red>latest YAML</> <b>version.</>");
+                    }
+                    positions.error(None, "version", err)
+                }
+            };
+            diagnostics.push(Diagnostic::error(err));
+            return module_chain;
+        }
+    }
+    info!(
+        "<b>Using <magenta>YAML parsing</> <b>version: <b><cyan>{}</>",
+        YAML_VERSION
+    );
+
+    // TRANSPORT
+    settings.tempo = match &doc["tempo"] {
+        Value::Real(_) | Value::Integer(_) => {
+            let tempo = doc["tempo"].as_f64().unwrap_or(f64::NAN);
+
+            if tempo > 0.0 && tempo.is_finite() {
+                info!("<b>Tempo: <cyan>{} BPM</>", tempo);
+                Some(tempo as f32)
+            } else {
+                error!("<b>Invalid <red>tempo</><b>.</>");
+                diagnostics.push(Diagnostic::error(positions.error(
+                    None,
+                    "tempo",
+                    InvalidTempo(tempo),
+                )));
+                None
+            }
+        }
+        Value::Missing => None,
         _ => {
-            return Err(WrongFormat {
-                field_name: String::from("version"),
-                supported_format: String::from("f64, str"),
-            })
+            diagnostics.push(Diagnostic::error(positions.error(
+                None,
+                "tempo",
+                WrongFormat {
+                    field_name: String::from("tempo"),
+                    supported_format: String::from("f64, i64"),
+                },
+            )));
+            None
         }
     };
 
-    let version = version.unwrap();
-    if version != YAML_VERSION {
-        error!("<b>Please use the <red>latest YAML</> <b>version.</>");
-        return Err(VersionMismatch {
-            using: version.to_string(),
-            latest: YAML_VERSION.to_string(),
-        });
-    } else {
-        info!(
-            "<b>Using <magenta>YAML parsing</> <b>version: <b><cyan>{}</>",
-            version
-        );
-    }
+    settings.time_signature = match &doc["time-signature"] {
+        Value::Missing => None,
+        yaml => {
+            let signature = yaml.as_str().and_then(parse_time_signature);
+
+            if signature.is_none() {
+                error!("<b>Invalid <red>time signature</><b>.</>");
+                diagnostics.push(Diagnostic::error(positions.error(
+                    None,
+                    "time-signature",
+                    WrongFormat {
+                        field_name: String::from("time-signature"),
+                        supported_format: String::from("str (beats/unit, such as 3/4)"),
+                    },
+                )));
+            }
+
+            signature
+        }
+    };
 
     info!("<b>Creating module chain.</>");
-    let mut module_chain: HashMap<i64, ChainCell> = HashMap::new();
+    let mut module_links: Vec<ModuleLinks> = Vec::new();
+
+    let layout = match &doc["layout"] {
+        Value::List(layout) => layout,
+        Value::Missing => {
+            error!("<b>Missing <red>layout</><b>.</>");
+            diagnostics.push(Diagnostic::error(MissingLayout));
+            return module_chain;
+        }
+        _ => {
+            diagnostics.push(Diagnostic::error(positions.error(
+                None,
+                "layout",
+                WrongFormat {
+                    field_name: String::from("layout"),
+                    supported_format: String::from("list of modules"),
+                },
+            )));
+            return module_chain;
+        }
+    };
+
+    let mut layout = match flatten(&doc, layout, base_dir) {
+        Ok(layout) => layout,
+        Err(err) => {
+            error!("<b>Invalid <red>sub-patch</><b>.</>");
+            diagnostics.push(Diagnostic::error(positions.locate(err)));
+            return module_chain;
+        }
+    };
+
+    // EXPRESSIONS
+    let vars = match read_vars(&doc) {
+        Ok(vars) => vars,
+        Err(err) => {
+            error!("<b>Invalid <red>variables</><b>.</>");
+            diagnostics.push(Diagnostic::error(positions.locate(err)));
+            return module_chain;
+        }
+    };
+    let mut valid_expressions = true;
+    for FlatModule { path, module, .. } in &mut layout {
+        if let Err(err) = substitute(module, path, &vars) {
+            error!("<b>Invalid <red>expression</><b>.</>");
+            diagnostics.push(Diagnostic::error(positions.locate(err)));
+            valid_expressions = false;
+        }
+    }
+    if !valid_expressions {
+        return module_chain;
+    }
+
+    for FlatModule {
+        path,
+        module,
+        forwarded,
+    } in &layout
+    {
+        let module_path = path.clone();
+        let field = |name: &str| format!("{}.{}", module_path, name);
 
-    let layout = &doc["layout"];
-    // TODO add error for missing layout
-    for module in layout.clone().into_iter() {
-        let module = &module["module"];
         let module_type = &module["type"];
         let module_id = &module["id"];
 
         // ID CHECKS
         let module_id = match module_id {
-            Yaml::Integer(x) => *x,
-            Yaml::BadValue => {
+            Value::Integer(x) => *x,
+            Value::Missing => {
                 error!("<b>Missing module <red>ID</><b>.</>");
-
-                return Err(MissingField(String::from("ID")));
+                diagnostics.push(Diagnostic::error(positions.error(
+                    None,
+                    &field("id"),
+                    MissingID,
+                )));
+                continue;
             }
             _ => {
                 error!("<b>Module ID could not be <red>parsed</><b>.</>");
-                return Err(WrongFormat {
-                    field_name: String::from("id"),
-                    supported_format: String::from("i64"),
-                });
+                diagnostics.push(Diagnostic::error(positions.error(
+                    None,
+                    &field("id"),
+                    WrongFormat {
+                        field_name: String::from("id"),
+                        supported_format: String::from("i64"),
+                    },
+                )));
+                continue;
             }
         };
 
         info!("> Processing <cyan>module {}</>", module_id);
 
-        if module_chain.contains_key(&module_id) {
+        if module_links.iter().any(|links| links.id == module_id) {
             error!("<b>Found a <red>duplicated ID</> <b>value.</>");
-            return Err(DuplicatedID(module_id));
+            diagnostics.push(Diagnostic::error(positions.error(
+                Some(module_id),
+                &field("id"),
+                DuplicatedID(module_id),
+            )));
+            continue;
         }
 
         // FIRST BOOL
-        if let Some(value) = module["os-out"].as_bool() {
-            if value {
-                if first_module.is_some() {
+        if let Some(true) = module["os-out"].as_bool() {
+            match first_module {
+                Some(first) => {
                     error!("<b>Two modules have been defined as <red>Operative System output</><b>. There can only be <cyan>one at a time</><b>.</>");
-                    panic!("Two modules have been defined as Operative System output. There can only be one. Please check the logs more information.");
+                    diagnostics.push(Diagnostic::error(positions.error(
+                        Some(module_id),
+                        &field("os-out"),
+                        DuplicatedOpSysOutput {
+                            first,
+                            second: module_id,
+                        },
+                    )));
                 }
-                first_module = Some(module_id);
+                None => first_module = Some(module_id),
             }
         }
 
         // TYPE
-        if module_type.as_str().is_none() {
-            error!("<b>Module <red>type</> <b>not specified.</>");
-            return Err(MissingField(String::from("type")));
-        }
-
-        let module_type = module_type.as_str().unwrap();
         let config = &module["config"];
         let name = config["name"].as_str();
 
@@ -174,172 +733,168 @@ fn load_yaml(
             info!("  |_ name: {}", name);
         }
 
-        let generated_module: Box<dyn Module> = match module_type {
-            "oscillator" => {
-                if !config.is_null() {
-                    let sample_rate = config["sample_rate"].as_i64();
-                    let amp = config["amplitude"].as_f64();
-                    let freq = config["frequency"].as_f64();
-                    let phase = config["phase"].as_f64();
-                    let pwd = config["pwd"].as_f64();
-
-                    let wave = match config["wave"].as_str() {
-                        None => None,
-                        Some(str) => match str {
-                            "sin" | "sine" => Some(WaveShape::Sine),
-                            "tri" | "triangle" => Some(WaveShape::Triangle),
-                            "saw" => Some(WaveShape::Saw),
-                            "sqr" | "square" => Some(WaveShape::Square),
-                            "pulse" => {
-                                let width: f32 = match pwd {
-                                    Some(x) => x as f32,
-                                    None => PI,
-                                };
-                                Some(WaveShape::Pulse(width))
-                            }
-                            &_ => None,
-                        },
-                    };
-
-                    Box::new(
-                        OscillatorBuilder::with_all_yaml_fmt(name, amp, freq, phase, wave, pwd)
-                            .build()
-                            .unwrap(),
-                    )
-                } else {
-                    info!("No configuration found for oscillator");
-                    Box::new(OscillatorBuilder::new().build().unwrap())
-                }
-            }
-
-            "sum" => {
-                let input_amount = config["input-amount"].as_i64();
-
-                if input_amount.is_none() {
-                    error!(
-                        "<b>Invalid format or no <red>input amount</> <b>provided for sum module. ID: {}.</>",
-                        module_id
-                    );
-                    return Err(MissingField(String::from("input-amount")));
-                }
-
-                let input_amount = input_amount.unwrap();
-
-                let out_gain = &config["out-gain"];
-                let in_1_gain = &config["in-1"];
-                let in_2_gain = &config["in-2"];
-                let in_3_gain = &config["in-3"];
-
-                let items: Vec<Option<f64>> = [out_gain, in_1_gain, in_2_gain, in_3_gain]
-                    .into_iter()
-                    .map(|yaml| match yaml {
-                        Yaml::Real(_) => yaml.as_f64(),
-                        Yaml::Integer(_) => yaml.as_i64().map(|x| x as f64),
-                        _ => None,
-                    })
-                    .collect();
-                let (out_gain, in_1_gain, in_2_gain, in_3_gain) =
-                    (items[0], items[1], items[2], items[3]);
-
-                if input_amount <= 1 {
-                    error!("<b><redInvalid amount</> <b>of inputs declared</>");
-                    error!("  |_ id: {}", module_id);
-                    return Err(InvalidInputAmount(input_amount));
-                } else if input_amount == 2 {
-                    Box::new(
-                        Sum2InBuilder::with_all_yaml(name, out_gain, in_1_gain, in_2_gain)
-                            .build()
-                            .unwrap(),
-                    )
-                } else if input_amount == 3 {
-                    Box::new(
-                        Sum3InBuilder::with_all_yaml(
-                            name, out_gain, in_1_gain, in_2_gain, in_3_gain,
-                        )
-                        .build()
-                        .unwrap(),
-                    )
-                } else {
-                    if in_1_gain.is_some() || in_2_gain.is_some() || in_3_gain.is_some() {
-                        warn!("<b>For sum modules with a size greater than 3 is <yellow>not possible to specify the input gain</> <b>for each input. Instead, you have to specify it in the module itself.</>");
-                        warn!("  * found in module with id: {}", module_id);
+        let generated_module = match module_type.as_str() {
+            Some(module_type) => {
+                info!("  |_ type: {}", module_type);
+
+                let context = FactoryContext {
+                    module_id,
+                    base_dir,
+                };
+                match registry.create(module_type, config, &context) {
+                    Ok(module) => Some(module),
+                    Err(err) => {
+                        let path = match err.config_field() {
+                            Some(name) => format!("{}.{}", field("config"), name),
+                            None if matches!(err, UnknownType(_)) => field("type"),
+                            None => field("config"),
+                        };
+                        diagnostics.push(Diagnostic::error(positions.error(
+                            Some(module_id),
+                            &path,
+                            err,
+                        )));
+                        None
                     }
-
-                    Box::new(
-                        VarSumBuilder::with_all_yaml(name, input_amount, out_gain)
-                            .build()
-                            .unwrap(),
-                    )
                 }
             }
-            "osc_debug" => Box::new(OscDebug::new(SAMPLE_RATE)),
-            "pass_through" => Box::new(PassTrough::new()),
-
-            _ => {
-                error!("<b>Module type <red>not known</><b>. ID: {}.</>", module_id);
-                return Err(UnknownType(module_type.to_string()));
+            None => {
+                error!("<b>Module <red>type</> <b>not specified.</>");
+                diagnostics.push(Diagnostic::error(positions.error(
+                    Some(module_id),
+                    &field("type"),
+                    MissingField(String::from("type")),
+                )));
+                None
             }
         };
 
-        info!("  |_ type: {}", module_type);
-
         // ADD AUXILIARIES
-        let mut auxiliaries: Vec<AuxInfo> = Vec::new();
+        let mut auxiliaries: Vec<AuxRoute> = Vec::new();
+        let mut aux_from: Vec<(i64, String)> = Vec::new();
         info!("  |_ looking for auxiliaries");
 
-        let mut aux_count = 0;
-        for aux in module["auxiliaries"].clone().into_iter() {
-            aux_count += 1;
+        let aux_list = match &module["auxiliaries"] {
+            Value::List(list) => list.as_slice(),
+            _ => &[],
+        };
+
+        for (aux_index, aux) in aux_list.iter().enumerate() {
+            // Handed over by a sub-patch instance, reported where it was written
+            let forwarded = forwarded.get(&aux_index);
+            let aux_path = match forwarded {
+                Some(forwarded) => forwarded.path.clone(),
+                None => format!("{}.auxiliaries[{}].aux", module_path, aux_index),
+            };
+            let aux_field = |name: &str| format!("{}.{}", aux_path, name);
             let aux = &aux["aux"];
 
             let from_id = &aux["from-id"];
-            let id_from = match from_id {
-                Yaml::Real(_) => from_id.as_f64().map(|x| x as i64),
-                Yaml::Integer(_) => from_id.as_i64(),
+            let from_id = match from_id {
+                Value::Real(x) => Some(*x as i64),
+                Value::Integer(x) => Some(*x),
 
-                Yaml::BadValue => {
+                Value::Missing => {
                     warn!("<b>Missing <yellow>from-id</> <b>value.</>");
                     error!(
                         "<b>from-id parameter is <red>compulsory</> <b>for auxiliary inputs to know where the data goes to.</>"
                     );
-                    return Err(MissingAuxFromId);
+                    diagnostics.push(Diagnostic::error(positions.error(
+                        Some(module_id),
+                        &aux_field("from-id"),
+                        MissingAuxFromId,
+                    )));
+                    continue;
                 }
 
-                _ => {
+                _ => None,
+            };
+
+            let from_id = match from_id {
+                Some(from_id) => from_id,
+                None => {
                     error!("<b>Invalid format for <red>from-id</> <b>value.</>");
-                    return Err(WrongFormat {
-                        field_name: String::from("from-id"),
-                        supported_format: String::from("i64"),
-                    });
+                    diagnostics.push(Diagnostic::error(positions.error(
+                        Some(module_id),
+                        &aux_field("from-id"),
+                        WrongFormat {
+                            field_name: String::from("from-id"),
+                            supported_format: String::from("i64"),
+                        },
+                    )));
+                    continue;
                 }
             };
+            aux_from.push((from_id, aux_field("from-id")));
 
             let tag = &aux["linked-with"];
             let tag = match tag {
-                Yaml::Real(_) => tag.as_f64().map(|x| x.to_string()),
-                Yaml::Integer(_) => tag.as_i64().map(|x| x.to_string()),
-                Yaml::Boolean(_) => tag.as_bool().map(|x| x.to_string()),
-                Yaml::String(_) => tag.as_str().map(|x| x.to_string()),
-                Yaml::BadValue => {
+                Value::Real(x) => x.to_string(),
+                Value::Integer(x) => x.to_string(),
+                Value::Boolean(x) => x.to_string(),
+                Value::String(x) => x.to_string(),
+                Value::Missing => {
                     warn!("<b>Missing <yellow>linked-with</> <b>value.</>");
                     error!(
                     "<b>linked-with parameter is <red>compulsory</> <b>for auxiliary inputs to know to which parameter maps to.</>"
                 );
-                    return Err(MissingAuxTag);
+                    diagnostics.push(Diagnostic::error(positions.error(
+                        Some(module_id),
+                        &aux_field("linked-with"),
+                        MissingAuxTag,
+                    )));
+                    continue;
                 }
                 _ => {
                     error!("<b>Invalid format for <red>linked-with</> <b>value.</>");
-                    return Err(WrongFormat {
-                        field_name: String::from("linked-with"),
-                        supported_format: String::from("str"),
-                    });
+                    diagnostics.push(Diagnostic::error(positions.error(
+                        Some(module_id),
+                        &aux_field("linked-with"),
+                        WrongFormat {
+                            field_name: String::from("linked-with"),
+                            supported_format: String::from("str"),
+                        },
+                    )));
+                    continue;
                 }
             };
 
+            if let Some(module) = &generated_module {
+                match (module.get_parameter(&tag), forwarded) {
+                    (Some(_), _) => {}
+                    // The output of a sub-patch only takes the auxiliaries it has a parameter for
+                    (None, Some(forwarded)) => {
+                        error!("<b>Sub-patch <red>input</> <b>not found.</>");
+                        error!("  |_ name: {}", tag);
+                        diagnostics.push(Diagnostic::error(positions.error(
+                            Some(module_id),
+                            &aux_field("linked-with"),
+                            InvalidSubPatch {
+                                name: forwarded.sub_patch.clone(),
+                                reason: format!(
+                                    "'{}' is neither one of its inputs nor a parameter of its output",
+                                    tag
+                                ),
+                            },
+                        )));
+                        continue;
+                    }
+                    (None, None) => {
+                        warn!("<b>Parameter tag <yellow>not found</><b>.</>");
+                        warn!("  |_ name: {}", tag);
+                        diagnostics.push(Diagnostic::warning(positions.error(
+                            Some(module_id),
+                            &aux_field("linked-with"),
+                            UnknownParameter(tag.clone()),
+                        )));
+                    }
+                }
+            }
+
             let max = match &aux["max"] {
-                Yaml::Real(_) => aux["max"].as_f64().map(|x| x as f32),
-                Yaml::Integer(_) => aux["max"].as_i64().map(|x| x as f32),
-                Yaml::BadValue => None, // not found
+                Value::Real(x) => Some(*x as f32),
+                Value::Integer(x) => Some(*x as f32),
+                Value::Missing => None, // not found
                 _ => {
                     warn!("<b>Invalid format for <yellow>max</> <b>value.</>");
                     None
@@ -347,24 +902,18 @@ fn load_yaml(
             };
 
             let min = match &aux["min"] {
-                Yaml::Real(_) => aux["min"].as_f64().map(|x| x as f32),
-                Yaml::Integer(_) => aux["min"].as_i64().map(|x| x as f32),
-                Yaml::BadValue => None, // not found
+                Value::Real(x) => Some(*x as f32),
+                Value::Integer(x) => Some(*x as f32),
+                Value::Missing => None, // not found
                 _ => {
                     warn!("<b>Invalid format for <yellow>min</> <b>value.</>");
                     None
                 }
             };
 
-            let tag = tag.expect(
-                "An auxiliary is not specifying 'linked-with' field. Please check the logs for more information.",
-            );
-
-            let from_id = id_from.expect("An auxiliary is missing the 'from-id' field. Please check the logs for more information.");
-
             info!("    |_ routing {} to module #{}", tag, from_id);
 
-            auxiliaries.push(AuxInfo {
+            auxiliaries.push(AuxRoute {
                 from_module: from_id,
                 linked_with: tag,
                 max,
@@ -373,124 +922,552 @@ fn load_yaml(
         }
 
         if let Some(input_amount) = config["input-amount"].as_i64() {
-            if aux_count < input_amount - 1 {
+            if (aux_list.len() as i64) < input_amount - 1 {
                 // input amount specified - amount of directly routed inputs (one, currently)
-                warn!("<b>A {} module has been detected not to have every input routed to an <yellow>auxiliary</><b>.</>", module_type);
+                warn!("<b>A module has been detected not to have every input routed to an <yellow>auxiliary</><b>.</>");
                 warn!("  |_ id: {}", module_id);
-                warn!("  |_ aux count: {}", aux_count);
+                warn!("  |_ aux count: {}", aux_list.len());
                 warn!("  |_ input amt: {}", input_amount);
+                diagnostics.push(Diagnostic::warning(positions.error(
+                    Some(module_id),
+                    &field("auxiliaries"),
+                    UnroutedInputs {
+                        expected: input_amount - 1,
+                        routed: aux_list.len(),
+                    },
+                )));
             }
         }
 
-        module_chain.insert(
-            module_id,
-            ChainCell {
-                from_module: (&module["input-from"]).as_i64(),
-                module: generated_module,
-                auxiliaries,
-            },
+        let input_from = module["input-from"].as_i64();
+        if let Some(module) = generated_module {
+            module_chain.insert(
+                module_id,
+                ChainCell {
+                    from_module: input_from,
+                    module,
+                    auxiliaries,
+                },
+            );
+        }
+        module_links.push(ModuleLinks {
+            id: module_id,
+            path: module_path,
+            input_from,
+            aux_from,
+        });
+    }
+
+    check_links(&module_links, first_module, &positions, diagnostics);
+
+    match first_module {
+        Some(first_module) => {
+            *first_module_index = first_module;
+            info!("First module's index: {}", first_module_index);
+        }
+        None => {
+            error!("<b>No module linked to <red>Operating System</><b>. Add field 'os-out: true' to the last element in the chain.</>");
+            diagnostics.push(Diagnostic::error(MissingOpSysOutput));
+        }
+    }
+
+    module_chain
+}
+
+/// Checks every module referenced exists, and that each module is used exactly once by the chain
+/// ending in the Operating System output.
+fn check_links(
+    module_links: &[ModuleLinks],
+    first_module: Option<i64>,
+    positions: &Positions,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    use YamlParsingError::*;
+
+    let by_id: HashMap<i64, &ModuleLinks> =
+        module_links.iter().map(|links| (links.id, links)).collect();
+
+    // REFERENCES
+    for links in module_links {
+        let references = links
+            .input_from
+            .map(|from| (from, format!("{}.input-from", links.path)))
+            .into_iter()
+            .chain(links.aux_from.iter().cloned());
+
+        for (from, path) in references {
+            if !by_id.contains_key(&from) {
+                error!(
+                    "<b>Module {} takes data from a <red>missing module</><b>.</>",
+                    links.id
+                );
+                diagnostics.push(Diagnostic::error(positions.error(
+                    Some(links.id),
+                    &path,
+                    ModuleNotFound(from),
+                )));
+            }
+        }
+    }
+
+    // REACHABILITY
+    let first_module = match first_module {
+        Some(first_module) if by_id.contains_key(&first_module) => first_module,
+        _ => return,
+    };
+
+    let mut visited: Vec<i64> = vec![first_module];
+    let mut pending: Vec<i64> = vec![first_module];
+
+    while let Some(id) = pending.pop() {
+        let links = by_id[&id];
+        let references = links
+            .input_from
+            .map(|from| (from, format!("{}.input-from", links.path)))
+            .into_iter()
+            .chain(links.aux_from.iter().cloned());
+
+        for (from, path) in references {
+            if !by_id.contains_key(&from) {
+                continue; // Already reported
+            }
+            if visited.contains(&from) {
+                error!("<b>Module {} is <red>used more than once</><b>.</>", from);
+                diagnostics.push(Diagnostic::error(positions.error(
+                    Some(id),
+                    &path,
+                    ReusedModule(from),
+                )));
+                continue;
+            }
+            visited.push(from);
+            pending.push(from);
+        }
+    }
+
+    for links in module_links {
+        if !visited.contains(&links.id) {
+            warn!(
+                "<b>Module {} is <yellow>not connected</> <b>to the output.</>",
+                links.id
+            );
+            diagnostics.push(Diagnostic::warning(positions.error(
+                Some(links.id),
+                &links.path,
+                UnreachableModule(links.id),
+            )));
+        }
+    }
+}
+
+/// Parses a time signature written as `beats/unit`, such as `3/4` or `6/8`.
+fn parse_time_signature(text: &str) -> Option<TimeSignature> {
+    let (beats, unit) = text.split_once('/')?;
+    let beats_per_bar = beats.trim().parse::<u32>().ok()?;
+    let beat_unit = unit.trim().parse::<u32>().ok()?;
+
+    if beats_per_bar == 0 || !beat_unit.is_power_of_two() {
+        return None;
+    }
+
+    Some(TimeSignature {
+        beats_per_bar,
+        beat_unit,
+    })
+}
+
+/// A layout document, together with the folder its relative paths are resolved from, such as
+/// the ones of the samples or the sub-patches used by its modules.
+///
+/// A layout may be read from a file, from any [Read] implementation or straight from a string,
+/// and written in any of the supported [formats](Format). Files are read in the format given by
+/// their extension, while anything else is read as YAML unless [told
+/// otherwise](fn@Layout::with_format).
+/// # Example
+/// ```no_run
+/// # use lion_synth::{Format, Layout};
+/// let layout = Layout::from_path("patches/bass.yaml").unwrap();
+/// let buffer = layout.render(44100, 44100).unwrap();
+///
+/// let layout = Layout::from_document("version: 0.5\nlayout: []").with_base_dir("patches");
+/// let report = layout.validate(&Default::default());
+///
+/// let layout = Layout::from_document(r#"{ "version": 0.5, "layout": [] }"#).with_format(Format::Json);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Layout {
+    document: String,
+    format: Format,
+    base_dir: Option<PathBuf>,
+}
+
+impl Layout {
+    /// Reads a layout file, in the format given by its extension (YAML if it is not a known
+    /// one). Relative paths in the layout are resolved from the folder of the file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, YamlParsingError> {
+        let path = path.as_ref();
+        info!("<b>Loading data from <red>{}</><b>.</>", path.display());
+
+        let document = fs::read_to_string(path).map_err(|source| {
+            error!("<b>Could not read <red>{}</><b>.</>", path.display());
+            YamlParsingError::Io {
+                path: path.display().to_string(),
+                source,
+            }
+        })?;
+
+        Ok(Self {
+            document,
+            format: Format::from_path(path).unwrap_or_default(),
+            base_dir: path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map(Path::to_path_buf),
+        })
+    }
+
+    /// A layout held in memory. Relative paths in the layout are resolved from the working
+    /// directory, unless a [base directory](fn@Layout::with_base_dir) is set.
+    pub fn from_document(document: impl Into<String>) -> Self {
+        Self {
+            document: document.into(),
+            format: Format::default(),
+            base_dir: None,
+        }
+    }
+
+    /// Reads a layout until the end of the reader. Relative paths in the layout are resolved from
+    /// the working directory, unless a [base directory](fn@Layout::with_base_dir) is set.
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, YamlParsingError> {
+        let mut document = String::new();
+        reader
+            .read_to_string(&mut document)
+            .map_err(|source| YamlParsingError::Io {
+                path: String::from("<reader>"),
+                source,
+            })?;
+
+        Ok(Self::from_document(document))
+    }
+
+    /// Sets the folder relative paths in the layout are resolved from.
+    pub fn with_base_dir(mut self, base_dir: impl Into<PathBuf>) -> Self {
+        self.base_dir = Some(base_dir.into());
+        self
+    }
+
+    /// Sets the format the layout is written in.
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn get_document(&self) -> &str {
+        &self.document
+    }
+
+    pub fn get_format(&self) -> Format {
+        self.format
+    }
+
+    pub fn get_base_dir(&self) -> Option<&Path> {
+        self.base_dir.as_deref()
+    }
+
+    /// Resolves a path found in the layout. Absolute paths are left untouched.
+    pub fn resolve_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        resolve_path(self.get_base_dir(), path.as_ref())
+    }
+
+    /// Loads the layout without rendering nor playing it, returning the first error found, if any.
+    pub fn check(&self, registry: &ModuleRegistry) -> Result<(), YamlParsingError> {
+        self.to_patch(registry).map(|_| ())
+    }
+
+    /// Checks the layout without rendering nor playing it. Unlike [`check`](fn@Layout::check), it
+    /// does not stop at the first problem: every module is checked and the report contains all
+    /// the errors and warnings found, such as references to missing modules, modules not
+    /// connected to the output or auxiliaries linked to parameters that do not exist.
+    pub fn validate(&self, registry: &ModuleRegistry) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        collect_layout(
+            &self.document,
+            self.format,
+            self.get_base_dir(),
+            registry,
+            &mut 0,
+            &mut LayoutSettings::default(),
+            &mut report.diagnostics,
         );
+
+        report
     }
 
-    if first_module.is_none() {
-        error!("<b>No module linked to <red>Operating System</><b>. Add field 'os-out: true' to the last element in the chain.</>");
-        return Err(MissingOpSysOutput);
+    /// Renders the layout offline, returning the first `buffer_length` samples of its output.
+    pub fn render(
+        &self,
+        buffer_length: usize,
+        sample_rate: i32,
+    ) -> Result<Vec<f32>, YamlParsingError> {
+        self.render_with_registry(buffer_length, sample_rate, &ModuleRegistry::default())
     }
 
-    *first_module_index = first_module.unwrap();
-    info!("First module's index: {}", first_module_index);
+    /// Does the same as [`render`](fn@Layout::render) but the modules are created by the given
+    /// [ModuleRegistry], so the layout may use custom module types.
+    pub fn render_with_registry(
+        &self,
+        buffer_length: usize,
+        sample_rate: i32,
+        registry: &ModuleRegistry,
+    ) -> Result<Vec<f32>, YamlParsingError> {
+        self.to_patch(registry)?.render(buffer_length, sample_rate)
+    }
 
-    Ok(module_chain)
+    /// Builds every module of the layout, returning the first error found, if any. The
+    /// [Patch] can be changed and saved again as a layout.
+    pub fn to_patch(&self, registry: &ModuleRegistry) -> Result<Patch, YamlParsingError> {
+        let mut output = 0i64;
+        let mut settings = LayoutSettings::default();
+        let modules = parse_yaml(
+            &self.document,
+            self.format,
+            self.get_base_dir(),
+            registry,
+            &mut output,
+            &mut settings,
+        )?;
+
+        Ok(Patch {
+            modules,
+            output: Some(output),
+            tempo: settings.tempo,
+            time_signature: settings.time_signature,
+        })
+    }
 }
 
-pub fn buffer_from_yaml(file: &str, buffer_length: usize, sample_rate: i32) -> Vec<f32> {
-    let mut first_module = 0i64;
-    let mut module_chain = load_yaml(file, &mut first_module);
+/// Reads a layout from the `layouts` folder, used by the functions taking a file name.
+fn from_layouts_folder(file: &str) -> Result<Layout, YamlParsingError> {
+    Layout::from_path(Path::new("layouts").join(file))
+}
 
-    info!("<b>Filling buffer:</>\n");
-    fill_buffer(
-        &mut module_chain.unwrap(),
-        first_module,
-        buffer_length,
-        sample_rate,
-    )
+/// Checks a layout from the `layouts` folder. See [`Layout::validate`].
+pub fn validate_layout(file: &str, registry: &ModuleRegistry) -> ValidationReport {
+    match from_layouts_folder(file) {
+        Ok(layout) => layout.validate(registry),
+        Err(err) => ValidationReport::from(err),
+    }
+}
+
+/// Loads a layout from the `layouts` folder without rendering nor playing it, returning the
+/// first error found, if any.
+pub fn check_layout(file: &str, registry: &ModuleRegistry) -> Result<(), YamlParsingError> {
+    from_layouts_folder(file)?.check(registry)
+}
+
+/// Renders a layout from the `layouts` folder offline, returning the first `buffer_length`
+/// samples of its output. Use [Layout] to load it from anywhere else.
+pub fn buffer_from_yaml(
+    file: &str,
+    buffer_length: usize,
+    sample_rate: i32,
+) -> Result<Vec<f32>, YamlParsingError> {
+    from_layouts_folder(file)?.render(buffer_length, sample_rate)
 }
 
+/// Does the same as [buffer_from_yaml] but the modules are created by the given
+/// [ModuleRegistry], so the layout may use custom module types.
+pub fn buffer_from_yaml_with_registry(
+    file: &str,
+    buffer_length: usize,
+    sample_rate: i32,
+    registry: &ModuleRegistry,
+) -> Result<Vec<f32>, YamlParsingError> {
+    from_layouts_folder(file)?.render_with_registry(buffer_length, sample_rate, registry)
+}
+
+/// Plays a layout from the `layouts` folder in real time.
 pub fn play_from_yaml(
     file: &str,
     signal_duration: i32,
     sample_rate: i32,
 ) -> Result<(), anyhow::Error> {
-    let mut first_module = 0i64;
-    let mut module_chain = load_yaml(file, &mut first_module);
-    let mut wrapper_chain: LinkedList<Box<dyn ModuleWrapper>> = LinkedList::new();
-
-    let ring_buffer: HeapRb<f32> = HeapRb::new(BATCH_SIZE_RT);
-    let (prod, mut cpal_consumer) = ring_buffer.split();
-
-    build_wrapper_chain(
-        &mut module_chain.unwrap(),
-        first_module,
-        &mut wrapper_chain,
-        prod,
-    );
+    RealTimeEngine::from_yaml(file, sample_rate)?.play(signal_duration)
+}
 
-    let mut coordinator = CoordinatorEntity::new(sample_rate, wrapper_chain);
-    coordinator.display_order();
-
-    // CPAL CONFIGURATION
-    let mut logger = simplelog::__private::paris::Logger::new();
-    // get default host
-    let host = cpal::default_host();
-
-    // get default device
-    let device: Device = host
-        .default_output_device()
-        .expect("no default output device available. Please check if one is selected");
-
-    // load config
-    let supported_config = get_preferred_config(
-        &device,
-        Some(SampleFormat::F32),
-        Some(SampleRate(SAMPLE_RATE as u32)),
-        Some(Channels::Stereo),
-    );
+/// The real time engine built from a layout. Its parameters can be controlled while playing
+/// through the [ControlHandle] obtained with [`control_handle`](fn@RealTimeEngine::control_handle)
+/// before starting the playback.
+/// # Example
+/// ```no_run
+/// # use lion_synth::RealTimeEngine;
+/// # use std::thread;
+/// let engine = RealTimeEngine::from_yaml("fm.yaml", 44100).unwrap();
+/// let handle = engine.control_handle();
+///
+/// thread::spawn(move || handle.set_parameter(1, "amplitude", 0.5));
+/// engine.play(1000).unwrap();
+/// ```
+pub struct RealTimeEngine {
+    coordinator: CoordinatorEntity,
+    output: ModuleConsumer,
+    sample_rate: i32,
+}
 
-    // open stream
-    let config: StreamConfig = supported_config.into();
-    let channels = config.channels as usize;
+impl RealTimeEngine {
+    /// Builds the engine from a layout of the `layouts` folder.
+    pub fn from_yaml(file: &str, sample_rate: i32) -> Result<Self, YamlParsingError> {
+        Self::from_yaml_with_registry(file, sample_rate, &ModuleRegistry::default())
+    }
 
-    let mut next_value = move || cpal_consumer.pop().unwrap_or(0.0); // Unwrap or silence
+    /// Does the same as [`from_yaml`](fn@RealTimeEngine::from_yaml) but the modules are created
+    /// by the given [ModuleRegistry], so the layout may use custom module types.
+    pub fn from_yaml_with_registry(
+        file: &str,
+        sample_rate: i32,
+        registry: &ModuleRegistry,
+    ) -> Result<Self, YamlParsingError> {
+        Self::from_layout(&from_layouts_folder(file)?, sample_rate, registry)
+    }
 
-    let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+    /// Builds the engine from a [Layout] loaded from anywhere.
+    pub fn from_layout(
+        layout: &Layout,
+        sample_rate: i32,
+        registry: &ModuleRegistry,
+    ) -> Result<Self, YamlParsingError> {
+        Self::from_patch(layout.to_patch(registry)?, sample_rate)
+    }
 
-    // call a function to let cpal output the stream
-    let stream = device.build_output_stream(
-        &config,
-        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            write_data(data, channels, &mut next_value)
-        },
-        err_fn,
-        None,
-    )?;
+    /// Builds the engine from a [Patch]. The modules are moved into the engine.
+    pub fn from_patch(mut patch: Patch, sample_rate: i32) -> Result<Self, YamlParsingError> {
+        let first_module = patch.check_links()?;
+        let mut wrapper_chain: LinkedList<Box<dyn ModuleWrapper>> = LinkedList::new();
+        let routing = Routing {
+            links: patch
+                .modules
+                .iter()
+                .map(|(id, cell)| {
+                    let links = ChainLinks {
+                        from_module: cell.from_module,
+                        auxiliaries: cell.auxiliaries.clone(),
+                    };
+                    (*id, links)
+                })
+                .collect(),
+            output: first_module,
+            ..Default::default()
+        };
 
-    info!("<b>Signal duration: <u>{} milliseconds</>", signal_duration);
-    warn!("<yellow><warn></> <b>The end of the buffer may be filled with <blue>silence</><b>.</>");
-    logger.loading("<blue><info></><b> Playing sound</>");
-    stream.play()?;
+        let ring_buffer: HeapRb<f32> = HeapRb::new(BATCH_SIZE_RT);
+        let (prod, output) = ring_buffer.split();
+
+        build_wrapper_chain(
+            &mut patch.modules,
+            &mut HashSet::new(),
+            first_module,
+            &mut wrapper_chain,
+            prod,
+        )?;
+
+        let mut coordinator = CoordinatorEntity::new(sample_rate, wrapper_chain);
+        coordinator.prepare();
+        patch.configure_transport(coordinator.get_mut_transport())?;
+
+        // Modules not connected to the output may be wired while playing
+        let detached = patch
+            .modules
+            .into_iter()
+            .map(|(id, mut cell)| {
+                cell.module.prepare(sample_rate, 1);
+                (id, cell.module)
+            })
+            .collect();
+        coordinator.set_routing(routing, detached);
+
+        Ok(Self {
+            coordinator,
+            output,
+            sample_rate,
+        })
+    }
 
-    let mut count = 0;
-    while count < (signal_duration as f32 * sample_rate as f32 / 1000.0) as i32 {
-        if !coordinator.is_full() {
-            coordinator.tick();
-            count += 1;
-        }
+    /// Creates a new handle to change the parameters of the modules while playing.
+    pub fn control_handle(&self) -> ControlHandle {
+        self.coordinator.control_handle()
     }
 
-    logger.done();
+    pub fn get_sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
 
-    Ok(())
+    /// Ticks the coordinator once, returning the sample it delivers to the output.
+    pub(crate) fn next_sample(&mut self) -> f32 {
+        self.coordinator.tick();
+        self.output.pop().unwrap_or(0.0)
+    }
+
+    /// Takes over from the engine being played. See [`CoordinatorEntity::continue_from`].
+    pub(crate) fn continue_from(&mut self, previous: &mut RealTimeEngine, unchanged: &[i64]) {
+        self.coordinator
+            .continue_from(&mut previous.coordinator, unchanged);
+    }
+
+    pub(crate) fn get_mut_coordinator(&mut self) -> &mut CoordinatorEntity {
+        &mut self.coordinator
+    }
+
+    /// Plays the engine through the default output device for the given amount of milliseconds.
+    pub fn play(self, signal_duration: i32) -> Result<(), anyhow::Error> {
+        self.play_with_output(signal_duration, &OutputConfig::default())
+    }
 
+    /// Does the same as [play](RealTimeEngine::play) through the given output.
+    pub fn play_with_output(
+        self,
+        signal_duration: i32,
+        output: &OutputConfig,
+    ) -> Result<(), anyhow::Error> {
+        self.play_with_backend(signal_duration, &mut CpalBackend::new(output.clone()))
+    }
+
+    /// Plays the engine for the given amount of milliseconds through any [AudioBackend], such as
+    /// a headless one which needs no audio device.
+    pub fn play_with_backend(
+        self,
+        signal_duration: i32,
+        backend: &mut dyn AudioBackend,
+    ) -> Result<(), anyhow::Error> {
+        let mut coordinator = self.coordinator;
+        let mut output = self.output;
+        let sample_rate = self.sample_rate;
+
+        coordinator.display_order();
+
+        let mut logger = simplelog::__private::paris::Logger::new();
+        info!("<b>Signal duration: <u>{} milliseconds</>", signal_duration);
+        warn!(
+            "<yellow><warn></> <b>The end of the buffer may be filled with <blue>silence</><b>.</>"
+        );
+        logger.loading("<blue><info></><b> Playing sound</>");
+        coordinator.reset();
+        backend.start(sample_rate, Box::new(move || output.pop()))?;
+        coordinator.get_mut_transport().start();
+
+        let mut count = 0;
+        while count < (signal_duration as f32 * sample_rate as f32 / 1000.0) as i32 {
+            if !coordinator.is_full() {
+                coordinator.tick();
+                count += 1;
+            }
+        }
+
+        backend.stop()?;
+        coordinator.release();
+        logger.done();
+
+        Ok(())
+    }
     // TODO add a "to_module" field to the ChainCell struct which is calculated ()
     // during the load_yaml. Is read to generate the producer and consumer pair.
     // the modules without a "from_module" field are considered to be generators
@@ -534,59 +1511,131 @@ pub fn play_from_yaml(
 
 // An optimization with threads would not be possible as a recursive function does not
 // have perspective of the whole structure.
-/// Fill the whole buffer from the module chain structure.
-fn fill_buffer(
+/// Fill the whole buffer from the module chain structure. Every module plays along its own copy
+/// of `transport`.
+pub(crate) fn fill_buffer(
     module_chain: &mut HashMap<i64, ChainCell>,
+    taken: &mut HashSet<i64>,
     current_pos: i64,
     buffer_size: usize,
     sample_rate: i32,
-) -> Vec<f32> {
-    let mut current_module = module_chain.remove(&current_pos).unwrap();
+    transport: &Transport,
+) -> Result<Vec<f32>, YamlParsingError> {
+    let mut current_module = take_cell(module_chain, taken, current_pos)?;
     let next_id = current_module.from_module;
+    current_module.module.prepare(sample_rate, buffer_size);
 
     // AUXILIARIES
     let mut aux_list: Vec<AuxiliaryInput> = Vec::new();
 
     for aux_info in current_module.auxiliaries {
-        let aux_buffer = fill_buffer(module_chain, aux_info.from_module, buffer_size, sample_rate);
+        let aux_buffer = fill_buffer(
+            module_chain,
+            taken,
+            aux_info.from_module,
+            buffer_size,
+            sample_rate,
+            transport,
+        )?;
         let aux = AuxInputBuilder::new(&aux_info.linked_with, AuxDataHolder::Batch(aux_buffer))
             .with_all_yaml(aux_info.max, aux_info.min)
             .build()
-            .unwrap();
+            .map_err(|err| aux_error(current_pos, err))?;
 
         aux_list.push(aux);
     }
 
     // GENERATE OR PROCESS BUFFER
-    return if next_id.is_some() {
+    let buffer = if let Some(next_id) = next_id {
         // LINKER MODULE (PROCESS BUFFER) - RECURSIVE STEP
 
-        let next_id = next_id.unwrap();
-        let mut buffer = fill_buffer(module_chain, next_id, buffer_size, sample_rate);
-        current_module
-            .module
-            .fill_buffer(&mut buffer, sample_rate, aux_list);
+        let mut buffer = fill_buffer(
+            module_chain,
+            taken,
+            next_id,
+            buffer_size,
+            sample_rate,
+            transport,
+        )?;
+        fill_from_start(
+            current_module.module.as_mut(),
+            &mut buffer,
+            sample_rate,
+            aux_list,
+            transport,
+        );
+        current_module.module.release();
         buffer
     } else {
         // GENERATOR MODULE (CREATE BUFFER) - BASE CASE
 
         let mut buffer = vec![0.0f32; buffer_size];
 
-        current_module
-            .module
-            .fill_buffer(&mut buffer, sample_rate, aux_list);
+        fill_from_start(
+            current_module.module.as_mut(),
+            &mut buffer,
+            sample_rate,
+            aux_list,
+            transport,
+        );
+        current_module.module.release();
         buffer
     };
+
+    Ok(buffer)
+}
+
+/// Fills a buffer from the beginning, as [`fill_buffer`](fn@Module::fill_buffer) does, with the
+/// module playing along a copy of `transport`.
+fn fill_from_start(
+    module: &mut dyn Module,
+    buffer: &mut Vec<f32>,
+    sample_rate: i32,
+    auxiliaries: Vec<AuxiliaryInput>,
+    transport: &Transport,
+) {
+    advise_custom_fill();
+    module.reset();
+    module.fill_buffer_with_transport(buffer, 0, sample_rate, auxiliaries, &mut transport.clone());
+}
+
+/// Takes the module at `id` out of `module_chain`, remembering it in `taken`. A module taken
+/// before feeds more than one module, while one never in the chain does not exist.
+pub(crate) fn take_cell(
+    module_chain: &mut HashMap<i64, ChainCell>,
+    taken: &mut HashSet<i64>,
+    id: i64,
+) -> Result<ChainCell, YamlParsingError> {
+    match module_chain.remove(&id) {
+        Some(cell) => {
+            taken.insert(id);
+            Ok(cell)
+        }
+        None if taken.contains(&id) => Err(YamlParsingError::ReusedModule(id)),
+        None => Err(YamlParsingError::ModuleNotFound(id)),
+    }
+}
+
+/// Error for an auxiliary input that could not be built, such as one with a wrong range.
+pub(crate) fn aux_error(module_id: i64, error: String) -> YamlParsingError {
+    YamlParsingError::in_field(
+        Some(module_id),
+        "auxiliaries",
+        YamlParsingError::Builder(error),
+    )
 }
 
-fn build_wrapper_chain(
+/// Wraps the module at `current_pos` and every module feeding it, taking them out of
+/// `module_chain`. Modules are pushed to `wrapper_chain` in the order they must be ticked, the one
+/// at `current_pos` delivering to `producer` last.
+pub(crate) fn build_wrapper_chain(
     module_chain: &mut HashMap<i64, ChainCell>,
+    taken: &mut HashSet<i64>,
     current_pos: i64,
     wrapper_chain: &mut LinkedList<Box<dyn ModuleWrapper>>,
     producer: ModuleProducer,
-) {
-    // If current module has not
-    let current_module = module_chain.remove(&current_pos).unwrap();
+) -> Result<(), YamlParsingError> {
+    let current_module = take_cell(module_chain, taken, current_pos)?;
     let next_id = current_module.from_module;
 
     // AUXILIARIES
@@ -600,27 +1649,777 @@ fn build_wrapper_chain(
         let aux = AuxInputBuilder::new(&aux_info.linked_with, AuxDataHolder::RealTime(cons))
             .with_all_yaml(aux_info.max, aux_info.min)
             .build()
-            .unwrap();
-        build_wrapper_chain(module_chain, aux_id, wrapper_chain, prod);
+            .map_err(|err| aux_error(current_pos, err))?;
+        build_wrapper_chain(module_chain, taken, aux_id, wrapper_chain, prod)?;
 
         aux_list.push(aux);
     }
 
-    if next_id.is_some() {
+    if let Some(next_id) = next_id {
         // LINKER MODULE - RECURSIVE STEP
         let rb: HeapRb<f32> = HeapRb::new(BATCH_SIZE_RT);
         let (prod, cons) = rb.split();
-        let wrapper = LinkerModuleWrapper::new(current_module.module, cons, producer, aux_list);
+        let wrapper = LinkerModuleWrapper::new(current_module.module, cons, producer, aux_list)
+            .with_id(current_pos);
 
         // To ensure that the sample of the previous module is generated first
         // We fist add the AUXILIARY
-        build_wrapper_chain(module_chain, next_id.unwrap(), wrapper_chain, prod);
+        build_wrapper_chain(module_chain, taken, next_id, wrapper_chain, prod)?;
         // and then the current module
         wrapper_chain.push_back(Box::new(wrapper));
     } else {
         // GENERATOR MODULE - BASE CASE
-        let wrapper = GeneratorModuleWrapper::new(current_module.module, producer, aux_list);
+        let wrapper = GeneratorModuleWrapper::new(current_module.module, producer, aux_list)
+            .with_id(current_pos);
 
         wrapper_chain.push_back(Box::new(wrapper));
     }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts how many times the signal goes from negative to positive.
+    fn rising_edges(buffer: &[f32]) -> usize {
+        buffer
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count()
+    }
+
+    fn parse(yaml: &str) -> Result<HashMap<i64, ChainCell>, YamlParsingError> {
+        parse_yaml(
+            yaml,
+            Format::Yaml,
+            None,
+            &ModuleRegistry::default(),
+            &mut 0,
+            &mut LayoutSettings::default(),
+        )
+    }
+
+    #[test]
+    fn test_error_location() {
+        let yaml = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: pass_through
+      os-out: true
+      input-from: 1
+  - module:
+      id: 1
+      type: oscillator
+      config:
+        frequency: 50000.0
+";
+        let err = parse(yaml).err().unwrap();
+
+        assert!(matches!(err.kind(), YamlParsingError::Builder(_)));
+        assert_eq!(err.module_id(), Some(1));
+        assert_eq!(err.field(), Some("layout[1].module.config"));
+        assert_eq!(
+            err.location(),
+            Some(Location {
+                line: 11,
+                column: 7
+            })
+        );
+
+        let err = parse(&yaml.replace("id: 1", "id: one")).err().unwrap();
+        assert!(matches!(err.kind(), YamlParsingError::WrongFormat { .. }));
+        assert_eq!(err.field(), Some("layout[1].module.id"));
+        assert_eq!(err.location(), Some(Location { line: 9, column: 7 }));
+        assert_eq!(
+            err.to_string(),
+            "Format wrong for field 'id'. Supported format: i64 \
+            (field 'layout[1].module.id', line 9, column 7)"
+        );
+
+        // Missing fields point to the module
+        let err = parse("version: 0.5\nlayout:\n  - module:\n      id: 0\n")
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind(), YamlParsingError::MissingField(_)));
+        assert_eq!(err.field(), Some("layout[0].module.type"));
+        assert_eq!(err.location(), Some(Location { line: 3, column: 5 }));
+    }
+
+    #[test]
+    fn test_no_panics() {
+        let err = parse("version: 0.5\nlayout: [").err().unwrap();
+        assert!(matches!(err, YamlParsingError::Syntax { .. }));
+        assert_eq!(err.location().map(|x| x.line), Some(3));
+
+        assert!(matches!(
+            parse("").err(),
+            Some(YamlParsingError::EmptyDocument)
+        ));
+        assert!(matches!(
+            parse("version: 0.5").err(),
+            Some(YamlParsingError::MissingLayout)
+        ));
+
+        let yaml = "\
+version: 0.5
+layout:
+  - module: { id: 0, type: pass_through, os-out: true, input-from: 1 }
+  - module: { id: 1, type: osc_debug, os-out: true }
+";
+        let err = parse(yaml).err().unwrap();
+        assert!(matches!(
+            err.kind(),
+            YamlParsingError::DuplicatedOpSysOutput {
+                first: 0,
+                second: 1
+            }
+        ));
+
+        let yaml = "\
+version: 0.5
+layout:
+  - module: { id: 0, type: pass_through, os-out: true, input-from: 7 }
+";
+        let err = parse(yaml).err().unwrap();
+        assert!(matches!(err.kind(), YamlParsingError::ModuleNotFound(7)));
+        assert_eq!(err.field(), Some("layout[0].module.input-from"));
+
+        assert!(matches!(
+            buffer_from_yaml("missing_layout.yaml", 10, 44100),
+            Err(YamlParsingError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn test_validation() {
+        let yaml = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: sum
+      os-out: true
+      input-from: 1
+      config:
+        input-amount: 3
+      auxiliaries:
+        - aux:
+            linked-with: in9
+            from-id: 2
+  - module:
+      id: 1
+      type: oscillator
+      input-from: 5
+      config:
+        amplitude: 1.5
+  - module:
+      id: 2
+      type: osc_debug
+  - module:
+      id: 3
+      type: osc_debug
+      os-out: true
+      auxiliaries:
+        - aux:
+            linked-with: frequency
+            from-id: 8
+";
+        let mut diagnostics = Vec::new();
+        collect_layout(
+            yaml,
+            Format::Yaml,
+            None,
+            &ModuleRegistry::default(),
+            &mut 0,
+            &mut LayoutSettings::default(),
+            &mut diagnostics,
+        );
+        let report = ValidationReport { diagnostics };
+        let found: Vec<(Severity, Option<i64>, Option<&str>)> = report
+            .diagnostics
+            .iter()
+            .map(|x| (x.severity, x.error.module_id(), x.error.field()))
+            .collect();
+
+        use Severity::*;
+        assert_eq!(
+            found,
+            vec![
+                (
+                    Warning,
+                    Some(0),
+                    Some("layout[0].module.auxiliaries[0].aux.linked-with")
+                ),
+                (Warning, Some(0), Some("layout[0].module.auxiliaries")),
+                (Error, Some(1), Some("layout[1].module.config")),
+                (Error, Some(3), Some("layout[3].module.os-out")),
+                (
+                    Warning,
+                    Some(3),
+                    Some("layout[3].module.auxiliaries[0].aux.linked-with")
+                ),
+                (Error, Some(1), Some("layout[1].module.input-from")),
+                (
+                    Error,
+                    Some(3),
+                    Some("layout[3].module.auxiliaries[0].aux.from-id")
+                ),
+                (Warning, Some(3), Some("layout[3].module")),
+            ]
+        );
+        assert!(!report.is_valid());
+        assert_eq!(report.errors().count(), 4);
+        assert!(matches!(
+            report.diagnostics[5].error.kind(),
+            YamlParsingError::ModuleNotFound(5)
+        ));
+
+        // The loader stops at the first error
+        let err = parse(yaml).err().unwrap();
+        assert!(matches!(err.kind(), YamlParsingError::Builder(_)));
+    }
+
+    #[test]
+    fn test_take_cell() {
+        let mut patch = Layout::from_document(
+            "version: 0.5\nlayout:\n  - module:\n      id: 0\n      type: osc_debug\n      \
+            os-out: true\n",
+        )
+        .to_patch(&ModuleRegistry::default())
+        .unwrap();
+        let mut taken = HashSet::new();
+
+        assert!(take_cell(&mut patch.modules, &mut taken, 0).is_ok());
+        assert!(matches!(
+            take_cell(&mut patch.modules, &mut taken, 0),
+            Err(YamlParsingError::ReusedModule(0))
+        ));
+        assert!(matches!(
+            take_cell(&mut patch.modules, &mut taken, 7),
+            Err(YamlParsingError::ModuleNotFound(7))
+        ));
+    }
+
+    #[test]
+    fn test_reused_module() {
+        let yaml = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: pass_through
+      os-out: true
+      input-from: 1
+      auxiliaries:
+        - aux: { linked-with: gain, from-id: 1 }
+  - module:
+      id: 1
+      type: osc_debug
+";
+        let err = parse(yaml).err().unwrap();
+        assert!(matches!(err.kind(), YamlParsingError::ReusedModule(1)));
+    }
+
+    #[test]
+    fn test_render_pitch() {
+        // A 440 Hz pulse wave rendered for one second at each sample rate
+        for sample_rate in [44100, 48000, 88200, 96000] {
+            let buffer =
+                buffer_from_yaml("pulse_passthrough.yaml", sample_rate as usize, sample_rate)
+                    .unwrap();
+            let edges = rising_edges(&buffer);
+
+            assert!(
+                (439..=440).contains(&edges),
+                "Wrong pitch at {} Hz: {} cycles",
+                sample_rate,
+                edges
+            );
+        }
+    }
+
+    #[test]
+    fn test_layout_sources() {
+        let from_path = Layout::from_path("layouts/pulse_passthrough.yaml").unwrap();
+        assert_eq!(from_path.get_base_dir(), Some(Path::new("layouts")));
+        let expected = from_path.render(1000, 44100).unwrap();
+
+        let from_document = Layout::from_document(from_path.get_document());
+        assert_eq!(from_document.get_base_dir(), None);
+        assert_eq!(from_document.render(1000, 44100).unwrap(), expected);
+
+        let from_reader = Layout::from_reader(from_path.get_document().as_bytes()).unwrap();
+        assert_eq!(from_reader.render(1000, 44100).unwrap(), expected);
+
+        assert!(matches!(
+            Layout::from_path("layouts/missing_layout.yaml"),
+            Err(YamlParsingError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn test_layout_base_dir() {
+        use std::sync::{Arc, Mutex};
+
+        let resolved = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = ModuleRegistry::default();
+        let paths = Arc::clone(&resolved);
+        registry.register("sampler", move |config, context| {
+            let sample = config["sample"].as_str().unwrap_or_default();
+            paths.lock().unwrap().push(context.resolve_path(sample));
+            Ok(Box::new(crate::bundled_modules::debug::PassTrough::new()))
+        });
+
+        let yaml = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: sampler
+      os-out: true
+      config:
+        sample: samples/kick.wav
+";
+        let layout = Layout::from_document(yaml).with_base_dir("patches/drums");
+        assert_eq!(
+            layout.resolve_path("/samples/kick.wav"),
+            PathBuf::from("/samples/kick.wav")
+        );
+        layout.check(&registry).unwrap();
+        Layout::from_document(yaml).check(&registry).unwrap();
+
+        assert_eq!(
+            *resolved.lock().unwrap(),
+            vec![
+                PathBuf::from("patches/drums/samples/kick.wav"),
+                PathBuf::from("samples/kick.wav")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_migration() {
+        let yaml = "\
+version: 0.4
+layout:
+  - module:
+      id: 0
+      type: oscillator
+      os-out: true
+      input-from: -1
+      config:
+        sample_rate: 44100
+        frequency: 220.0
+";
+        let mut diagnostics = Vec::new();
+        collect_layout(
+            yaml,
+            Format::Yaml,
+            None,
+            &ModuleRegistry::default(),
+            &mut 0,
+            &mut LayoutSettings::default(),
+            &mut diagnostics,
+        );
+
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity == Severity::Warning));
+        let warning = &diagnostics[1].error;
+        assert!(matches!(warning.kind(), YamlParsingError::Migrated(_)));
+        assert_eq!(warning.field(), Some("layout[0].module.config.sample_rate"));
+        assert_eq!(warning.location(), Some(Location { line: 9, column: 9 }));
+
+        assert!(Layout::from_document(yaml).render(100, 44100).is_ok());
+    }
+
+    #[test]
+    fn test_sub_patches() {
+        let nested = "\
+version: 0.5
+sub-patches:
+  voice:
+    output: 0
+    parameters:
+      frequency:
+        targets: [1.frequency]
+    layout:
+      - module:
+          id: 0
+          type: pass_through
+          input-from: 1
+      - module:
+          id: 1
+          type: oscillator
+layout:
+  - module:
+      id: 0
+      type: sum
+      os-out: true
+      input-from: 1
+      config:
+        input-amount: 2
+      auxiliaries:
+        - aux:
+            from-id: 2
+            linked-with: in2
+  - module:
+      id: 1
+      type: voice
+      config:
+        frequency: 220.0
+  - module:
+      id: 2
+      type: voice
+";
+        let flat = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: sum
+      os-out: true
+      input-from: 1
+      config:
+        input-amount: 2
+      auxiliaries:
+        - aux:
+            from-id: 2
+            linked-with: in2
+  - module:
+      id: 1
+      type: pass_through
+      input-from: 3
+  - module:
+      id: 3
+      type: oscillator
+      config:
+        frequency: 220.0
+  - module:
+      id: 2
+      type: pass_through
+      input-from: 4
+  - module:
+      id: 4
+      type: oscillator
+";
+        assert_eq!(
+            Layout::from_document(nested).render(1000, 44100).unwrap(),
+            Layout::from_document(flat).render(1000, 44100).unwrap()
+        );
+
+        let layout = Layout::from_path("layouts/poli4phased_sub.yaml").unwrap();
+        let patch = layout.to_patch(&ModuleRegistry::default()).unwrap();
+        assert_eq!(patch.get_ids(), (0..10).collect::<Vec<_>>());
+
+        // What the sub-patch can not take is an error, not silently dropped
+        let invalid = |instance: &str| {
+            let yaml = nested.replace("      type: voice\n      config:", instance);
+            let report = Layout::from_document(yaml).validate(&ModuleRegistry::default());
+            let error = report
+                .errors()
+                .map(|diagnostic| &diagnostic.error)
+                .find(|error| matches!(error.kind(), YamlParsingError::InvalidSubPatch { .. }))
+                .unwrap();
+            error.field().map(String::from)
+        };
+        assert_eq!(
+            invalid("      type: voice\n      input-from: 2\n      config:").as_deref(),
+            Some("layout[1].module.input-from")
+        );
+        let aux =
+            "auxiliaries:\n        - aux:\n            from-id: 2\n            linked-with: detune";
+        assert_eq!(
+            invalid(&format!("      type: voice\n      {}\n      config:", aux)).as_deref(),
+            Some("layout[1].module.auxiliaries[0].aux.linked-with")
+        );
+    }
+
+    #[test]
+    fn test_vars() {
+        let with_vars = "\
+version: 0.5
+vars:
+  root: 220
+  detune: 20c
+layout:
+  - module:
+      id: 0
+      type: oscillator
+      os-out: true
+      config:
+        name: \"2\"
+        frequency: ${root} * 7st * ${detune}
+";
+        let plain = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: oscillator
+      os-out: true
+      config:
+        name: \"2\"
+        frequency: 333.45764463228755
+";
+        assert_eq!(
+            Layout::from_document(with_vars)
+                .render(1000, 44100)
+                .unwrap(),
+            Layout::from_document(plain).render(1000, 44100).unwrap()
+        );
+
+        let report = Layout::from_document(with_vars.replace("${root}", "${tonic}"))
+            .validate(&ModuleRegistry::default());
+        assert_eq!(report.errors().count(), 1);
+        let error = &report.errors().next().unwrap().error;
+        assert!(matches!(
+            error.kind(),
+            YamlParsingError::UndefinedVariable(_)
+        ));
+        assert_eq!(error.field(), Some("layout[0].module.config.frequency"));
+        assert_eq!(
+            error.location(),
+            Some(Location {
+                line: 12,
+                column: 9
+            })
+        );
+    }
+
+    #[test]
+    fn test_formats() {
+        let registry = ModuleRegistry::default();
+        let summary = |report: ValidationReport| {
+            report
+                .diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    (
+                        diagnostic.severity,
+                        diagnostic.error.kind().to_string(),
+                        diagnostic.error.field().map(String::from),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for file in [
+            "fm.yaml",
+            "layout.yaml",
+            "poli4phased.yaml",
+            "poli4phased_sub.yaml",
+        ] {
+            let yaml = Layout::from_path(Path::new("layouts").join(file)).unwrap();
+            let doc = Format::Yaml.parse(yaml.get_document()).unwrap();
+
+            for format in [Format::Json, Format::Toml] {
+                let layout = Layout::from_document(format.emit(&doc).unwrap())
+                    .with_format(format)
+                    .with_base_dir("layouts");
+
+                assert_eq!(
+                    summary(layout.validate(&registry)),
+                    summary(yaml.validate(&registry)),
+                    "{} as {}",
+                    file,
+                    format
+                );
+                assert_eq!(
+                    layout.render(500, 44100).unwrap(),
+                    yaml.render(500, 44100).unwrap(),
+                    "{} as {}",
+                    file,
+                    format
+                );
+            }
+        }
+
+        // Errors are located in JSON documents too
+        let json = r#"{
+  "version": 0.5,
+  "layout": [
+    { "module": { "id": 0, "type": "oscillator", "os-out": true, "input-from": 3 } }
+  ]
+}"#;
+        let report = Layout::from_document(json)
+            .with_format(Format::Json)
+            .validate(&registry);
+        let error = &report.errors().next().unwrap().error;
+        assert!(matches!(error.kind(), YamlParsingError::ModuleNotFound(3)));
+        assert_eq!(error.field(), Some("layout[0].module.input-from"));
+        assert_eq!(
+            error.location(),
+            Some(Location {
+                line: 4,
+                column: 66
+            })
+        );
+
+        let report = Layout::from_document("version = 0.5\nlayout = [")
+            .with_format(Format::Toml)
+            .validate(&registry);
+        assert!(matches!(
+            report.errors().next().unwrap().error,
+            YamlParsingError::Syntax { .. }
+        ));
+    }
+
+    #[test]
+    fn test_graph_edits() {
+        use crate::bundled_modules::OscillatorBuilder;
+        use crate::module::{ControlError, GraphEdit};
+
+        let layout = "\
+version: 0.5
+layout:
+  - module:
+      id: 0
+      type: pass_through
+      os-out: true
+      input-from: 1
+  - module:
+      id: 1
+      type: oscillator
+";
+        let with_lfo = format!(
+            "{}      auxiliaries:\n        - aux:\n            from-id: 2\n            \
+            linked-with: frequency\n            max: 660.0\n            min: 220.0\n  \
+            - module:\n      id: 2\n      type: oscillator\n      config:\n        \
+            frequency: 15.0\n",
+            layout
+        );
+        let engine = |yaml: &str| {
+            RealTimeEngine::from_layout(&Layout::from_document(yaml), 44100, &Default::default())
+                .unwrap()
+        };
+        let lfo = || {
+            let lfo = OscillatorBuilder::new()
+                .with_frequency(15.0)
+                .build()
+                .unwrap();
+            GraphEdit::AddModule {
+                id: 2,
+                module: Box::new(lfo),
+            }
+        };
+
+        // The carrier playing alone at 440 Hz, as oscillators go on from where they are
+        let assert_carrier = |engine: &mut RealTimeEngine| {
+            let step = 2.0 * std::f32::consts::PI * 440.0 / 44100.0;
+            let mut previous = engine.next_sample();
+            let mut peak: f32 = 0.0;
+            for _ in 0..100 {
+                let sample = engine.next_sample();
+                assert!((sample - previous).abs() <= step * 1.001);
+                peak = peak.max(sample.abs());
+                previous = sample;
+            }
+            assert!(peak > 0.9);
+        };
+
+        let mut edited = engine(layout);
+        let mut reference = engine(&with_lfo);
+        let handle = edited.control_handle();
+
+        // Nothing is sent unless every edit can be applied
+        let errors = [
+            (
+                vec![GraphEdit::RemoveModule(1)],
+                ControlError::UnknownModule(1),
+            ),
+            (
+                vec![GraphEdit::SetInput {
+                    id: 1,
+                    from: Some(0),
+                }],
+                ControlError::ReusedModule(0),
+            ),
+            (vec![lfo(), lfo()], ControlError::DuplicatedModule(2)),
+            (
+                vec![GraphEdit::RemoveAuxiliary {
+                    id: 1,
+                    linked_with: String::from("frequency"),
+                }],
+                ControlError::UnknownAuxiliary {
+                    module_id: 1,
+                    tag: String::from("frequency"),
+                },
+            ),
+            (
+                vec![
+                    lfo(),
+                    GraphEdit::AddAuxiliary {
+                        id: 1,
+                        aux: AuxRoute::new(2, "frequency").with_min(2.0),
+                    },
+                ],
+                ControlError::InvalidAuxRange {
+                    module_id: 1,
+                    tag: String::from("frequency"),
+                },
+            ),
+        ];
+        for (edits, error) in errors {
+            assert_eq!(handle.edit_graph(edits), Err(error));
+        }
+
+        let aux = AuxRoute::new(2, "frequency")
+            .with_max(660.0)
+            .with_min(220.0);
+        handle
+            .edit_graph(vec![lfo(), GraphEdit::AddAuxiliary { id: 1, aux }])
+            .unwrap();
+        for _ in 0..1000 {
+            assert_eq!(edited.next_sample(), reference.next_sample());
+        }
+
+        // Detached modules keep their state until wired again. Auxiliaries leave their last value
+        // in the parameter.
+        handle.set_parameter(2, "frequency", 20.0).unwrap();
+        handle.set_parameter(1, "frequency", 440.0).unwrap();
+        handle
+            .edit_graph(vec![GraphEdit::RemoveAuxiliary {
+                id: 1,
+                linked_with: String::from("frequency"),
+            }])
+            .unwrap();
+        edited.next_sample();
+        assert_eq!(handle.get_parameter(2, "frequency"), None);
+
+        assert_carrier(&mut edited);
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(440.0));
+        let aux = AuxRoute::new(2, "frequency");
+        handle
+            .edit_graph(vec![GraphEdit::AddAuxiliary { id: 1, aux }])
+            .unwrap();
+        edited.next_sample();
+        assert_eq!(handle.get_parameter(2, "frequency"), Some(20.0));
+
+        // The output may move to another module
+        handle.set_parameter(1, "frequency", 440.0).unwrap();
+        handle
+            .edit_graph(vec![
+                GraphEdit::SetOutput(1),
+                GraphEdit::RemoveModule(0),
+                GraphEdit::RemoveAuxiliary {
+                    id: 1,
+                    linked_with: String::from("frequency"),
+                },
+                GraphEdit::RemoveModule(2),
+            ])
+            .unwrap();
+        edited.next_sample();
+        assert_carrier(&mut edited);
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(440.0));
+        assert_eq!(
+            handle.edit_graph(vec![GraphEdit::RemoveModule(0)]),
+            Err(ControlError::UnknownModule(0))
+        );
+    }
 }
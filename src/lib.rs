@@ -0,0 +1,79 @@
+//! # lionSynth
+//! A framework for building modular digital synthesizers.
+//!
+//! A synthesizer is a chain of [modules](module::Module), each one reading or generating a signal,
+//! processing it and making it available to the next module of the chain. The modules can be
+//! wired by hand or described in a YAML layout and loaded with the functions of [layout_yaml].
+//!
+//! The crate is organized as follows:
+//! * [module] - The [Module](module::Module) trait and everything a module is made of: parameters,
+//!   auxiliary inputs, events and the real time machinery.
+//! * [audio_backend] - Where the real time engines play their samples: a sound card, nowhere or
+//!   a wav file.
+//! * [bundled_modules] - Modules shipped with the framework, such as oscillators or mixers.
+//! * [document] - The document model layouts are read into, from YAML, JSON or TOML.
+//! * [hot_reload] - Plays a layout file in real time, reloading it whenever it changes.
+//! * [layout_yaml] - Loads layouts, either rendering them to a buffer or playing them in real time.
+//! * [migration] - Upgrades layouts written for older versions of the schema.
+//! * [module_registry] - Tells the layout loader how to build every module type, including
+//!   your own.
+//! * [offline] - Renders patches block by block, streaming long renders to a wav file.
+//! * [patch] - A synthesizer held in memory, which can be built by hand and saved as a layout.
+//! * [wav_export] - Writes signals to wav files, with the bit depth, channels, normalisation and
+//!   dithering of your choice.
+//!
+//! # Example
+//! ```no_run
+//! use lion_synth::{buffer_from_yaml, output_wav};
+//!
+//! let buffer = buffer_from_yaml("my_layout.yaml", 44100, 44100).unwrap();
+//! output_wav(buffer, "my_layout.wav", 44100).unwrap();
+//! ```
+
+pub mod audio_backend;
+mod back_end;
+pub mod bundled_modules;
+pub mod document;
+mod expression;
+pub mod hot_reload;
+pub mod layout_yaml;
+pub mod migration;
+pub mod module;
+pub mod module_registry;
+pub mod offline;
+pub mod patch;
+mod sub_patch;
+pub mod wav_export;
+
+pub use audio_backend::{AudioBackend, CpalBackend, FileBackend, NullBackend};
+pub use back_end::{
+    host_names, list_output_devices, output_device_names, output_wav, play_buffer,
+    play_buffer_with_output, write_wav, OutputConfig, OutputDevice, OutputDeviceInfo, OutputError,
+    OutputSampleFormat,
+};
+pub use document::Format;
+pub use hot_reload::HotReloadEngine;
+pub use layout_yaml::{
+    buffer_from_yaml, buffer_from_yaml_with_registry, check_layout, play_from_yaml,
+    validate_layout, Diagnostic, Layout, Location, RealTimeEngine, Severity, ValidationReport,
+    YamlParsingError,
+};
+pub use module_registry::{FactoryContext, ModuleFactory, ModuleRegistry};
+pub use offline::{OfflineRenderer, RenderError, RenderProgress};
+pub use patch::{AuxRoute, Patch};
+pub use wav_export::{
+    ClipPolicy, ExportError, ExportReport, Normalization, WavEncoder, WavExport, WavFormat,
+};
+
+/// The most common items for writing and wiring modules.
+pub mod prelude {
+    pub use crate::bundled_modules::prelude::*;
+    pub use crate::document::Format;
+    pub use crate::layout_yaml::{buffer_from_yaml, play_from_yaml, Layout, RealTimeEngine};
+    pub use crate::module::{
+        AuxDataHolder, AuxInputBuilder, AuxiliaryInput, Event, EventKind, Module, Parameter,
+        ParameterBuilder, TimedEvent,
+    };
+    pub use crate::module_registry::ModuleRegistry;
+    pub use crate::patch::{AuxRoute, Patch};
+}
@@ -1,50 +1,684 @@
-mod back_end;
-mod bundled_modules;
-mod layout_yaml;
-mod module;
-
 // LOGGING
 use simplelog::*;
 
-// MY STUFF
-use back_end::output_wav;
-use back_end::play_buffer;
-use layout_yaml::{buffer_from_yaml, play_from_yaml};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use lion_synth::hot_reload::DEFAULT_CROSSFADE;
+use lion_synth::migration::migrate_document;
+use lion_synth::{
+    list_output_devices, ClipPolicy, Format, HotReloadEngine, Layout, ModuleRegistry,
+    Normalization, OfflineRenderer, OutputConfig, OutputDevice, OutputSampleFormat, RealTimeEngine,
+    RenderError, ValidationReport, WavExport, WavFormat, YamlParsingError,
+};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+const DEFAULT_DURATION: u32 = 1000; // milliseconds
+/// Durations and sample rates are handed over to the library as [i32].
+const MAX_ARG: i64 = i32::MAX as i64;
+
+// EXIT CODES
+/// The command finished without errors.
+const EXIT_SUCCESS: u8 = 0;
+/// The layout could not be loaded.
+const EXIT_INVALID_LAYOUT: u8 = 1;
+// 2 is used by clap for usage errors.
+/// Something went wrong with the audio device or the file system.
+const EXIT_IO_ERROR: u8 = 3;
+
+/// A modular digital synthesizer.
+///
+/// Layouts are given as a path. If no such file exists, it is looked up in the `layouts` folder.
+/// Use `-` to read the layout from the standard input. Layouts may be written in YAML, JSON or
+/// TOML, told apart by the extension of the file.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Amount of information logged.
+    #[arg(long, value_enum, default_value_t = LogLevel::Info, global = true)]
+    log_level: LogLevel,
+
+    /// Only log errors. Overrides `--log-level`.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Log everything, including the inner process of the modules. Overrides `--log-level`.
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+
+    /// Format of the layouts, overriding the one given by their extension. Layouts read from the
+    /// standard input are YAML unless stated.
+    #[arg(long, value_enum, global = true)]
+    format: Option<LayoutFormat>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Renders a layout to a wav file.
+    Render {
+        /// Layout file, a name in the layouts folder or `-` for the standard input.
+        layout: String,
+        /// Output file. Defaults to `exports/<layout name>.wav`.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Duration of the render in milliseconds.
+        #[arg(short, long, default_value_t = DEFAULT_DURATION)]
+        #[arg(value_parser = clap::value_parser!(u32).range(1..=MAX_ARG))]
+        duration: u32,
+        /// Sample rate in Hz.
+        #[arg(short, long, default_value_t = DEFAULT_SAMPLE_RATE)]
+        #[arg(value_parser = clap::value_parser!(u32).range(1..=MAX_ARG))]
+        sample_rate: u32,
+        #[command(flatten)]
+        export: ExportArgs,
+    },
+    /// Plays a layout in real time through the default output device.
+    Play {
+        /// Layout file, a name in the layouts folder or `-` for the standard input.
+        layout: String,
+        /// Duration of the playback in milliseconds.
+        #[arg(short, long, default_value_t = DEFAULT_DURATION)]
+        #[arg(value_parser = clap::value_parser!(u32).range(1..=MAX_ARG))]
+        duration: u32,
+        /// Sample rate in Hz.
+        #[arg(short, long, default_value_t = DEFAULT_SAMPLE_RATE)]
+        #[arg(value_parser = clap::value_parser!(u32).range(1..=MAX_ARG))]
+        sample_rate: u32,
+        /// Reloads the layout whenever the file changes, crossfading to the new chain.
+        #[arg(short, long)]
+        watch: bool,
+        /// Length of the crossfade between the old and the reloaded chain, in milliseconds.
+        #[arg(long, default_value_t = DEFAULT_CROSSFADE, requires = "watch")]
+        crossfade: u32,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Checks a layout without rendering it, reporting every problem found.
+    Validate {
+        /// Layout file, a name in the layouts folder or `-` for the standard input.
+        layout: String,
+    },
+    /// Upgrades layouts written for older versions of the schema, rewriting the files in place.
+    ///
+    /// The comments of the upgraded files are not kept.
+    Migrate {
+        /// Layout files, names in the layouts folder or `-` for the standard input, which is
+        /// written to the standard output.
+        #[arg(required = true)]
+        layouts: Vec<String>,
+        /// Only report the changes, without writing any file.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Writes the routing of a layout as a Graphviz DOT graph.
+    ///
+    /// The graph can be drawn with `dot -Tsvg`, for instance.
+    Graph {
+        /// Layout file, a name in the layouts folder or `-` for the standard input.
+        layout: String,
+        /// Output file. Defaults to the standard output.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Lists the module types available for layouts.
+    ListModules,
+    /// Lists the available output devices.
+    Devices {
+        /// Audio host to list the devices of. Defaults to the default host of the platform.
+        #[arg(long)]
+        host: Option<String>,
+    },
+}
+
+/// How the wav file is written.
+#[derive(Args, Debug, Clone, PartialEq)]
+struct ExportArgs {
+    /// Sample format of the file.
+    #[arg(long, value_enum, default_value_t = WavSampleFormat::I16)]
+    sample_format: WavSampleFormat,
+    /// Number of channels of the file, the signal being copied to all of them.
+    #[arg(long, default_value_t = 1)]
+    channels: u16,
+    /// Normalises the highest sample to the given level in dBFS.
+    #[arg(long, allow_negative_numbers = true, conflicts_with = "normalize_lufs")]
+    normalize_peak: Option<f32>,
+    /// Normalises the integrated loudness to the given level in LUFS.
+    #[arg(long, allow_negative_numbers = true)]
+    normalize_lufs: Option<f32>,
+    /// Adds TPDF dither when writing integer samples.
+    #[arg(long)]
+    dither: bool,
+    /// What to do with the samples out of range.
+    #[arg(long, value_enum, default_value_t = Clip::Clamp)]
+    clip: Clip,
+}
+
+impl From<ExportArgs> for WavExport {
+    fn from(args: ExportArgs) -> Self {
+        let mut export = WavExport::new()
+            .with_format(args.sample_format.into())
+            .with_channels(args.channels)
+            .with_dither(args.dither)
+            .with_clip_policy(args.clip.into());
+        if let Some(level) = args.normalize_peak {
+            export = export.with_normalization(Normalization::Peak(level));
+        }
+        if let Some(level) = args.normalize_lufs {
+            export = export.with_normalization(Normalization::Loudness(level));
+        }
+        export
+    }
+}
+
+/// Where and how the sound is played.
+#[derive(Args, Debug, Clone, PartialEq)]
+struct OutputArgs {
+    /// Audio host, such as ALSA or JACK. Defaults to the default host of the platform.
+    #[arg(long)]
+    host: Option<String>,
+    /// Output device, by name or by the index given by the `devices` command.
+    #[arg(long, default_value = "default")]
+    device: OutputDevice,
+    /// Number of output channels, the signal being copied to all of them.
+    #[arg(long, default_value_t = 2)]
+    channels: u16,
+    /// Fixed buffer size in frames. Defaults to the one of the device.
+    #[arg(long)]
+    buffer_size: Option<u32>,
+    /// Sample format of the output. Defaults to the most precise one the device takes.
+    #[arg(long, value_enum)]
+    sample_format: Option<SampleFormat>,
+}
+
+impl From<OutputArgs> for OutputConfig {
+    fn from(args: OutputArgs) -> Self {
+        let mut output = OutputConfig::new()
+            .with_device(args.device)
+            .with_channels(args.channels);
+        if let Some(host) = args.host {
+            output = output.with_host(&host);
+        }
+        if let Some(frames) = args.buffer_size {
+            output = output.with_buffer_size(frames);
+        }
+        if let Some(format) = args.sample_format {
+            output = output.with_sample_format(format.into());
+        }
+        output
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum LayoutFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum WavSampleFormat {
+    I16,
+    I24,
+    I32,
+    F32,
+}
+
+impl From<WavSampleFormat> for WavFormat {
+    fn from(format: WavSampleFormat) -> Self {
+        match format {
+            WavSampleFormat::I16 => WavFormat::Int16,
+            WavSampleFormat::I24 => WavFormat::Int24,
+            WavSampleFormat::I32 => WavFormat::Int32,
+            WavSampleFormat::F32 => WavFormat::Float32,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Clip {
+    Clamp,
+    Soft,
+    Fail,
+}
+
+impl From<Clip> for ClipPolicy {
+    fn from(clip: Clip) -> Self {
+        match clip {
+            Clip::Clamp => ClipPolicy::Clamp,
+            Clip::Soft => ClipPolicy::SoftClip,
+            Clip::Fail => ClipPolicy::Fail,
+        }
+    }
+}
 
-const SAMPLE_RATE: i32 = 44100;
-const VERSION: &str = "0.5.0";
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum SampleFormat {
+    F32,
+    I16,
+    U16,
+}
 
-#[allow(dead_code)]
-#[cfg(debug_assertions)]
-fn print_type_of<T>(_: &T) {
-    println!("{}", std::any::type_name::<T>())
+impl From<SampleFormat> for OutputSampleFormat {
+    fn from(format: SampleFormat) -> Self {
+        match format {
+            SampleFormat::F32 => OutputSampleFormat::F32,
+            SampleFormat::I16 => OutputSampleFormat::I16,
+            SampleFormat::U16 => OutputSampleFormat::U16,
+        }
+    }
 }
 
-fn main() -> Result<(), anyhow::Error> {
+impl From<LayoutFormat> for Format {
+    fn from(format: LayoutFormat) -> Self {
+        match format {
+            LayoutFormat::Yaml => Format::Yaml,
+            LayoutFormat::Json => Format::Json,
+            LayoutFormat::Toml => Format::Toml,
+        }
+    }
+}
+
+impl Cli {
+    fn level_filter(&self) -> LevelFilter {
+        if self.quiet {
+            return LevelFilter::Error;
+        }
+        if self.verbose {
+            return LevelFilter::Trace;
+        }
+
+        match self.log_level {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
     // LOGGER INIT
     TermLogger::init(
-        LevelFilter::Debug,
+        cli.level_filter(),
         Config::default(),
         TerminalMode::Mixed,
         ColorChoice::Auto,
     )
     .expect("Failed to start simplelog");
-    // let logger = Logger::new();
+    if cli.level_filter() >= LevelFilter::Info {
+        show_features_info();
+    }
+
+    let format = cli.format.map(Format::from);
+    let code = match cli.command {
+        Command::Render {
+            layout,
+            output,
+            duration,
+            sample_rate,
+            export,
+        } => render(
+            &layout,
+            format,
+            output,
+            duration,
+            sample_rate,
+            export.into(),
+        ),
+        Command::Play {
+            layout,
+            duration,
+            sample_rate,
+            watch: true,
+            crossfade,
+            output,
+        } => play_watching(
+            &layout,
+            format,
+            duration,
+            sample_rate,
+            crossfade,
+            output.into(),
+        ),
+        Command::Play {
+            layout,
+            duration,
+            sample_rate,
+            output,
+            ..
+        } => play(&layout, format, duration, sample_rate, output.into()),
+        Command::Validate { layout } => validate(&layout, format),
+        Command::Migrate { layouts, dry_run } => layouts
+            .iter()
+            .map(|layout| migrate(layout, format, dry_run))
+            .max()
+            .unwrap_or(EXIT_SUCCESS),
+        Command::Graph { layout, output } => graph(&layout, format, output),
+        Command::ListModules => list_modules(),
+        Command::Devices { host } => devices(host.as_deref()),
+    };
+
+    ExitCode::from(code)
+}
 
-    // FILL BUFFER
-    info!("<b>Running <blue>demo program</>");
-    info!("<b>Program version: <cyan>{}</>", VERSION);
-    show_features_info();
-    let signal_duration: i32 = 1000; // milliseconds
-    let buffer_size: usize = (signal_duration * SAMPLE_RATE / 1000) as usize;
+fn render(
+    layout: &str,
+    format: Option<Format>,
+    output: Option<PathBuf>,
+    duration: u32,
+    sample_rate: u32,
+    export: WavExport,
+) -> u8 {
+    let output = output.unwrap_or_else(|| default_output(layout));
+    let frames = duration as u64 * sample_rate as u64 / 1000;
+    let renderer = open_layout(layout, format).and_then(|file| {
+        OfflineRenderer::from_layout(&file, sample_rate as i32, &ModuleRegistry::default())
+    });
+    let mut renderer = match renderer {
+        Ok(renderer) => renderer,
+        Err(err) => return invalid_layout(layout, err),
+    };
 
-    let stream_buffer = buffer_from_yaml("poli4phased.yaml", buffer_size, SAMPLE_RATE);
-    output_wav(stream_buffer.clone(), "test.wav", SAMPLE_RATE);
+    // Reports every tenth of the render
+    let mut reported = 0;
+    renderer = renderer.with_progress(move |progress| {
+        let tenths = (progress.fraction() * 10.0) as u32;
+        if tenths > reported {
+            reported = tenths;
+            info!("  <b>|_ Rendered: <cyan>{}%</>", tenths * 10);
+        }
+    });
 
-    play_buffer(stream_buffer, signal_duration, SAMPLE_RATE).expect("Error during playback.");
-    play_from_yaml("poli4.yaml", signal_duration, SAMPLE_RATE).expect("Error during playback.");
-    info!("<green><tick></> <b>Program finished <green>successfully</><b>.</>");
-    Ok(())
+    match renderer.render_to_wav(frames, &output, &export) {
+        Ok(report) => {
+            if export.get_normalization().is_some() {
+                info!(
+                    "<b>Normalised with a gain of <cyan>{:.2} dB</>",
+                    20.0 * report.gain.log10()
+                );
+            }
+            if report.clipped > 0 {
+                warn!(
+                    "<yellow><warn></> <b>{} samples were out of range (peak <red>{:.2}</><b>).</>",
+                    report.clipped, report.peak
+                );
+            }
+            info!(
+                "<green><tick></> <b>Rendered to <green>{}</><b>.</>",
+                output.display()
+            );
+            EXIT_SUCCESS
+        }
+        Err(RenderError::Layout(err)) => invalid_layout(layout, err),
+        Err(err) => {
+            error!(
+                "<b>Could not write <red>{}</><b>: {}</>",
+                output.display(),
+                err
+            );
+            EXIT_IO_ERROR
+        }
+    }
+}
+
+fn play(
+    layout: &str,
+    format: Option<Format>,
+    duration: u32,
+    sample_rate: u32,
+    output: OutputConfig,
+) -> u8 {
+    let engine = match open_layout(layout, format).and_then(|file| {
+        RealTimeEngine::from_layout(&file, sample_rate as i32, &ModuleRegistry::default())
+    }) {
+        Ok(engine) => engine,
+        Err(err) => return invalid_layout(layout, err),
+    };
+
+    match engine.play_with_output(duration as i32, &output) {
+        Ok(()) => EXIT_SUCCESS,
+        Err(err) => {
+            error!("<b>Error during <red>playback</><b>: {}</>", err);
+            EXIT_IO_ERROR
+        }
+    }
+}
+
+fn play_watching(
+    layout: &str,
+    format: Option<Format>,
+    duration: u32,
+    sample_rate: u32,
+    crossfade: u32,
+    output: OutputConfig,
+) -> u8 {
+    if layout == "-" {
+        error!("<b>The <red>standard input</><b> cannot be watched for changes.</>");
+        return EXIT_INVALID_LAYOUT;
+    }
+
+    let mut engine = HotReloadEngine::new(layout_path(layout), sample_rate as i32)
+        .with_crossfade(crossfade)
+        .with_output(output);
+    if let Some(format) = format {
+        engine = engine.with_format(format);
+    }
+
+    match engine.play(duration as i32) {
+        Ok(()) => EXIT_SUCCESS,
+        Err(err) => match err.downcast::<YamlParsingError>() {
+            Ok(err) => invalid_layout(layout, err),
+            Err(err) => {
+                error!("<b>Error during <red>playback</><b>: {}</>", err);
+                EXIT_IO_ERROR
+            }
+        },
+    }
+}
+
+fn validate(layout: &str, format: Option<Format>) -> u8 {
+    let report = match open_layout(layout, format) {
+        Ok(file) => file.validate(&ModuleRegistry::default()),
+        Err(err) => ValidationReport::from(err),
+    };
+
+    for diagnostic in &report.diagnostics {
+        println!("{}", diagnostic);
+    }
+
+    let errors = report.errors().count();
+    let warnings = report.warnings().count();
+    if report.is_valid() {
+        info!(
+            "<green><tick></> <b>Layout <green>{}</> <b>is valid ({} warnings).</>",
+            layout, warnings
+        );
+        EXIT_SUCCESS
+    } else {
+        error!(
+            "<b>Layout <red>{}</> <b>is not valid: {} errors, {} warnings.</>",
+            layout, errors, warnings
+        );
+        EXIT_INVALID_LAYOUT
+    }
+}
+
+fn migrate(layout: &str, format: Option<Format>, dry_run: bool) -> u8 {
+    let migration = match open_layout(layout, format)
+        .and_then(|file| migrate_document(file.get_document(), file.get_format()))
+    {
+        Ok(Some(migration)) => migration,
+        Ok(None) => {
+            info!(
+                "<green><tick></> <b>Layout <green>{}</> <b>is already up to date.</>",
+                layout
+            );
+            return EXIT_SUCCESS;
+        }
+        Err(err) => return invalid_layout(layout, err),
+    };
+
+    if dry_run {
+        for note in &migration.notes {
+            println!("{}", note);
+        }
+        return EXIT_SUCCESS;
+    }
+    if layout == "-" {
+        print!("{}", migration.document);
+        return EXIT_SUCCESS;
+    }
+
+    let path = layout_path(layout);
+    match fs::write(&path, &migration.document) {
+        Ok(()) => {
+            info!(
+                "<green><tick></> <b>Upgraded <green>{}</> <b>from version {} ({} changes).</>",
+                path.display(),
+                migration.from,
+                migration.notes.len()
+            );
+            EXIT_SUCCESS
+        }
+        Err(err) => {
+            error!(
+                "<b>Could not write <red>{}</><b>: {}</>",
+                path.display(),
+                err
+            );
+            EXIT_IO_ERROR
+        }
+    }
+}
+
+fn graph(layout: &str, format: Option<Format>, output: Option<PathBuf>) -> u8 {
+    let dot = match open_layout(layout, format)
+        .and_then(|file| file.to_patch(&ModuleRegistry::default()))
+    {
+        Ok(patch) => patch.to_dot(),
+        Err(err) => return invalid_layout(layout, err),
+    };
+
+    let Some(output) = output else {
+        print!("{}", dot);
+        return EXIT_SUCCESS;
+    };
+    match fs::write(&output, dot) {
+        Ok(()) => {
+            info!(
+                "<green><tick></> <b>Graph written to <green>{}</><b>.</>",
+                output.display()
+            );
+            EXIT_SUCCESS
+        }
+        Err(err) => {
+            error!(
+                "<b>Could not write <red>{}</><b>: {}</>",
+                output.display(),
+                err
+            );
+            EXIT_IO_ERROR
+        }
+    }
+}
+
+fn list_modules() -> u8 {
+    for name in ModuleRegistry::default().get_type_names() {
+        println!("{}", name);
+    }
+
+    EXIT_SUCCESS
+}
+
+fn devices(host: Option<&str>) -> u8 {
+    match list_output_devices(host) {
+        Ok(devices) => {
+            for device in devices {
+                let mark = if device.is_default { " (default)" } else { "" };
+                let formats: Vec<String> = device
+                    .sample_formats
+                    .iter()
+                    .map(|format| format.to_string())
+                    .collect();
+                println!("{}: {}{}", device.index, device.name, mark);
+                println!(
+                    "  |_ up to {} channels, {}-{} Hz, {}",
+                    device.max_channels,
+                    device.min_sample_rate,
+                    device.max_sample_rate,
+                    formats.join("/")
+                );
+            }
+            EXIT_SUCCESS
+        }
+        Err(err) => {
+            error!(
+                "<b>Could not query the <red>output devices</><b>: {}</>",
+                err
+            );
+            EXIT_IO_ERROR
+        }
+    }
+}
+
+/// Reports a layout that could not be loaded, returning the exit code.
+fn invalid_layout(layout: &str, err: YamlParsingError) -> u8 {
+    error!("<b>Invalid layout <red>{}</><b>: {}</>", layout, err);
+    EXIT_INVALID_LAYOUT
+}
+
+/// Reads the layout given in the command line: the standard input for `-`, the path itself if
+/// the file exists or else a file of the `layouts` folder. The format, if given, overrides the
+/// one of the file extension.
+fn open_layout(layout: &str, format: Option<Format>) -> Result<Layout, YamlParsingError> {
+    let file = if layout == "-" {
+        Layout::from_reader(io::stdin().lock())?
+    } else {
+        Layout::from_path(layout_path(layout))?
+    };
+
+    Ok(match format {
+        Some(format) => file.with_format(format),
+        None => file,
+    })
+}
+
+fn layout_path(layout: &str) -> PathBuf {
+    let path = PathBuf::from(layout);
+    if path.is_file() {
+        path
+    } else {
+        Path::new("layouts").join(path)
+    }
+}
+
+fn default_output(layout: &str) -> PathBuf {
+    let name = Path::new(layout)
+        .file_stem()
+        .filter(|_| layout != "-")
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "render".to_string());
+
+    Path::new("exports").join(format!("{}.wav", name))
 }
 
 fn show_features_info() {
@@ -56,36 +690,220 @@ fn show_features_info() {
     }
 }
 
-fn test() {
-    use crate::bundled_modules::{Sum2In, Sum2InBuilder};
-    use crate::module::*;
-    use bundled_modules::debug::*;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    const BUFFER_SIZE: usize = 10;
+    #[test]
+    fn test_render_args() {
+        let cli = Cli::try_parse_from([
+            "lion_synth",
+            "render",
+            "fm.yaml",
+            "-o",
+            "out.wav",
+            "--duration",
+            "500",
+            "--sample-rate",
+            "48000",
+        ])
+        .unwrap();
 
-    let mut in1_osc = OscDebug::new(SAMPLE_RATE);
-    let mut in2_osc = OscDebug::new(SAMPLE_RATE);
-    let mut sum = Sum2InBuilder::new().build().unwrap();
+        assert_eq!(cli.level_filter(), LevelFilter::Info);
+        match cli.command {
+            Command::Render {
+                layout,
+                output,
+                duration,
+                sample_rate,
+                export,
+            } => {
+                assert_eq!(layout, "fm.yaml");
+                assert_eq!(output, Some(PathBuf::from("out.wav")));
+                assert_eq!(duration, 500);
+                assert_eq!(sample_rate, 48000);
+                assert_eq!(WavExport::from(export), WavExport::default());
+            }
+            _ => panic!("Expected the render command"),
+        }
 
-    let mut buffer1 = vec![0.0f32; BUFFER_SIZE];
-    let mut buffer2 = vec![0.0f32; BUFFER_SIZE];
+        let cli = Cli::try_parse_from([
+            "lion_synth",
+            "render",
+            "fm.yaml",
+            "--sample-format",
+            "f32",
+            "--channels",
+            "2",
+            "--normalize-lufs",
+            "-14",
+            "--dither",
+            "--clip",
+            "soft",
+        ]);
+        match cli.unwrap().command {
+            Command::Render { export, .. } => assert_eq!(
+                WavExport::from(export),
+                WavExport::new()
+                    .with_format(WavFormat::Float32)
+                    .with_channels(2)
+                    .with_normalization(Normalization::Loudness(-14.0))
+                    .with_dither(true)
+                    .with_clip_policy(ClipPolicy::SoftClip)
+            ),
+            _ => panic!("Expected the render command"),
+        }
 
-    in1_osc.fill_buffer(&mut buffer1, SAMPLE_RATE, vec![]);
-    in2_osc.fill_buffer(&mut buffer2, SAMPLE_RATE, vec![]);
+        // Durations and sample rates must be positive
+        for (arg, value) in [
+            ("--duration", "-1"),
+            ("--duration", "0"),
+            ("--sample-rate", "0"),
+        ] {
+            let err = Cli::try_parse_from(["lion_synth", "render", "fm.yaml", arg, value])
+                .err()
+                .unwrap();
+            assert_eq!(err.exit_code(), 2);
+        }
 
-    assert_eq!(buffer1, buffer2);
+        // A single normalisation at once
+        assert!(Cli::try_parse_from([
+            "lion_synth",
+            "render",
+            "fm.yaml",
+            "--normalize-peak",
+            "-1",
+            "--normalize-lufs",
+            "-14",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_migrate_args() {
+        let cli = Cli::try_parse_from(["lion_synth", "migrate", "-n", "a.yaml", "b.yaml"]).unwrap();
+        match cli.command {
+            Command::Migrate { layouts, dry_run } => {
+                assert_eq!(layouts, vec!["a.yaml", "b.yaml"]);
+                assert!(dry_run);
+            }
+            _ => panic!("Expected the migrate command"),
+        }
+
+        assert!(Cli::try_parse_from(["lion_synth", "migrate"]).is_err());
+    }
+
+    #[test]
+    fn test_graph_args() {
+        let cli = Cli::try_parse_from(["lion_synth", "graph", "fm.yaml", "-o", "fm.dot"]).unwrap();
+        match cli.command {
+            Command::Graph { layout, output } => {
+                assert_eq!(layout, "fm.yaml");
+                assert_eq!(output, Some(PathBuf::from("fm.dot")));
+            }
+            _ => panic!("Expected the graph command"),
+        }
+
+        let cli = Cli::try_parse_from(["lion_synth", "graph", "-"]).unwrap();
+        assert!(matches!(cli.command, Command::Graph { output: None, .. }));
+    }
 
-    sum.fill_buffer(
-        &mut buffer1,
-        SAMPLE_RATE,
-        vec![AuxInputBuilder::new("in2", AuxDataHolder::Batch(buffer2))
-            .with_min(-1.0)
-            .with_max(1.0)
-            .build()
-            .unwrap()],
-    );
+    #[test]
+    fn test_play_args() {
+        let cli =
+            Cli::try_parse_from(["lion_synth", "play", "fm.yaml", "-w", "--crossfade", "200"]);
+        match cli.unwrap().command {
+            Command::Play {
+                watch, crossfade, ..
+            } => {
+                assert!(watch);
+                assert_eq!(crossfade, 200);
+            }
+            _ => panic!("Expected the play command"),
+        }
+
+        let cli = Cli::try_parse_from([
+            "lion_synth",
+            "play",
+            "fm.yaml",
+            "--device",
+            "1",
+            "--channels",
+            "1",
+            "--buffer-size",
+            "256",
+            "--sample-format",
+            "i16",
+        ]);
+        match cli.unwrap().command {
+            Command::Play { output, .. } => assert_eq!(
+                OutputConfig::from(output),
+                OutputConfig::new()
+                    .with_device(OutputDevice::Index(1))
+                    .with_channels(1)
+                    .with_buffer_size(256)
+                    .with_sample_format(OutputSampleFormat::I16)
+            ),
+            _ => panic!("Expected the play command"),
+        }
+
+        let cli = Cli::try_parse_from(["lion_synth", "play", "fm.yaml", "--device", "USB Audio"]);
+        match cli.unwrap().command {
+            Command::Play { output, .. } => {
+                assert_eq!(output.device, OutputDevice::Name("USB Audio".to_string()));
+                assert_eq!(OutputConfig::from(output).get_channels(), 2);
+            }
+            _ => panic!("Expected the play command"),
+        }
+
+        // The crossfade only makes sense when watching
+        assert!(
+            Cli::try_parse_from(["lion_synth", "play", "fm.yaml", "--crossfade", "200"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_log_level() {
+        let cli = Cli::try_parse_from(["lion_synth", "--log-level", "warn", "list-modules"]);
+        assert_eq!(cli.unwrap().level_filter(), LevelFilter::Warn);
+
+        let cli = Cli::try_parse_from(["lion_synth", "devices", "-q"]);
+        assert_eq!(cli.unwrap().level_filter(), LevelFilter::Error);
+
+        let cli = Cli::try_parse_from(["lion_synth", "-q", "-v", "devices"]);
+        assert!(cli.is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let cli = Cli::try_parse_from(["lion_synth", "validate", "-", "--format", "json"]).unwrap();
+        assert_eq!(cli.format.map(Format::from), Some(Format::Json));
+
+        let cli = Cli::try_parse_from(["lion_synth", "validate", "fm.toml"]).unwrap();
+        assert_eq!(cli.format, None);
+
+        assert!(Cli::try_parse_from(["lion_synth", "--format", "xml", "validate", "-"]).is_err());
+    }
+
+    #[test]
+    fn test_default_output() {
+        assert_eq!(
+            default_output("poli4.yaml"),
+            PathBuf::from("exports/poli4.wav")
+        );
+        assert_eq!(
+            default_output("../patches/bass.yaml"),
+            PathBuf::from("exports/bass.wav")
+        );
+        assert_eq!(default_output("-"), PathBuf::from("exports/render.wav"));
+    }
 
-    for item in buffer1 {
-        info!("{}", item);
+    #[test]
+    fn test_layout_path() {
+        assert_eq!(layout_path("Cargo.toml"), PathBuf::from("Cargo.toml"));
+        assert_eq!(
+            layout_path("fm.yaml"),
+            PathBuf::from("layouts").join("fm.yaml")
+        );
     }
 }
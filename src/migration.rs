@@ -0,0 +1,287 @@
+//! Upgrades layouts written for older versions of the schema.
+//!
+//! Each schema change comes with a migration step from one version to the next, so a document of
+//! any known version is upgraded step by step until reaching the latest one. Every change done
+//! to the document is reported as a [MigrationNote], so the user knows what was modified.
+//!
+//! Layouts are migrated in memory when loaded. To upgrade the files themselves, use
+//! [migrate_document] or the `migrate` command of the command line tool.
+
+use crate::document::{Format, Value};
+use crate::layout_yaml::{YamlParsingError, YAML_VERSION};
+use simplelog::{info, warn};
+use std::fmt;
+
+/// A change done to a document while upgrading it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationNote {
+    /// Version the step upgrades from.
+    pub from: String,
+    /// Version the step upgrades to.
+    pub to: String,
+    /// ID of the module changed, if the change belongs to any.
+    pub module_id: Option<i64>,
+    /// Path of the field changed, such as `layout[2].module.config.sample_rate`.
+    pub field: String,
+    pub change: String,
+}
+
+impl fmt::Display for MigrationNote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {}: {} (field '{}')",
+            self.from, self.to, self.change, self.field
+        )
+    }
+}
+
+/// A document upgraded to the latest version of the schema.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    /// Version the document was written for.
+    pub from: String,
+    /// The upgraded document.
+    pub document: String,
+    pub notes: Vec<MigrationNote>,
+}
+
+/// Upgrades a document from version `from` to version `to`.
+struct Step {
+    from: &'static str,
+    to: &'static str,
+    apply: fn(&mut Value, &mut Vec<Change>),
+}
+
+/// A change reported by a step, before knowing the versions involved.
+struct Change {
+    module_id: Option<i64>,
+    field: String,
+    change: &'static str,
+}
+
+/// Every migration step, in order. A new step must be added whenever the schema changes.
+const STEPS: &[Step] = &[Step {
+    from: "0.4",
+    to: "0.5",
+    apply: from_0_4,
+}];
+
+/// Reads the version of a document, which may be written as a number or as a string.
+pub(crate) fn get_version(doc: &Value) -> Result<String, YamlParsingError> {
+    let version = &doc["version"];
+    match version {
+        Value::Real(version) => Ok(version.to_string()),
+        Value::String(version) => Ok(version.to_string()),
+        Value::Missing => Err(YamlParsingError::MissingVersionNumber(
+            YAML_VERSION.to_string(),
+        )),
+        _ => Err(YamlParsingError::WrongFormat {
+            field_name: String::from("version"),
+            supported_format: String::from("f64, str"),
+        }),
+    }
+}
+
+/// Upgrades a parsed document to the latest version of the schema, returning the changes done.
+/// Documents already up to date are left untouched.
+/// # Expected errors
+/// * The version is missing or it is not a known one, such as a version newer than the latest.
+pub fn migrate(doc: &mut Value) -> Result<Vec<MigrationNote>, YamlParsingError> {
+    let mut version = get_version(doc)?;
+    let mut notes = Vec::new();
+
+    while version != YAML_VERSION {
+        let step = match STEPS.iter().find(|step| step.from == version) {
+            Some(step) => step,
+            None => {
+                return Err(YamlParsingError::VersionMismatch {
+                    using: version,
+                    latest: YAML_VERSION.to_string(),
+                })
+            }
+        };
+
+        info!(
+            "<b>Upgrading layout from version <yellow>{}</> <b>to <cyan>{}</><b>.</>",
+            step.from, step.to
+        );
+        let mut changes = Vec::new();
+        (step.apply)(doc, &mut changes);
+        set_version(doc, step.to);
+
+        for change in changes {
+            warn!("  |_ {}: {}", change.field, change.change);
+            notes.push(MigrationNote {
+                from: step.from.to_string(),
+                to: step.to.to_string(),
+                module_id: change.module_id,
+                field: change.field,
+                change: change.change.to_string(),
+            });
+        }
+        version = step.to.to_string();
+    }
+
+    Ok(notes)
+}
+
+/// Upgrades the text of a layout to the latest version of the schema, returning `None` if it is
+/// already up to date. The upgraded document is written in the same format it was read in.
+///
+/// The upgraded document is written from scratch, so the comments of the original one are lost.
+pub fn migrate_document(text: &str, format: Format) -> Result<Option<Migration>, YamlParsingError> {
+    let mut doc = format.parse(text)?;
+
+    let from = get_version(&doc)?;
+    if from == YAML_VERSION {
+        return Ok(None);
+    }
+    let notes = migrate(&mut doc)?;
+
+    Ok(Some(Migration {
+        from,
+        document: format.emit(&doc)?,
+        notes,
+    }))
+}
+
+fn set_version(doc: &mut Value, version: &str) {
+    if let Value::Map(doc) = doc {
+        if let Some(value) = doc.get_mut("version") {
+            *value = Value::Real(version.parse().unwrap_or_default());
+        }
+    }
+}
+
+/// Calls `f` with every module of the layout, together with its path and its ID.
+fn for_each_module(doc: &mut Value, mut f: impl FnMut(&mut Value, String, Option<i64>)) {
+    let layout = match doc {
+        Value::Map(doc) => doc.get_mut("layout"),
+        _ => None,
+    };
+
+    if let Some(Value::List(layout)) = layout {
+        for (index, item) in layout.iter_mut().enumerate() {
+            if let Value::Map(item) = item {
+                if let Some(module) = item.get_mut("module") {
+                    let module_id = module["id"].as_i64();
+                    f(module, format!("layout[{}].module", index), module_id);
+                }
+            }
+        }
+    }
+}
+
+// STEPS
+
+/// Version 0.5 moved the sample rate to the engine and dropped `-1` as a way of saying that a
+/// module has no input.
+fn from_0_4(doc: &mut Value, changes: &mut Vec<Change>) {
+    for_each_module(doc, |module, path, module_id| {
+        let module = match module {
+            Value::Map(module) => module,
+            _ => return,
+        };
+
+        if let Some(Value::Integer(from)) = module.get("input-from") {
+            if *from < 0 {
+                module.remove("input-from");
+                changes.push(Change {
+                    module_id,
+                    field: format!("{}.input-from", path),
+                    change: "removed, modules with no input leave the field out",
+                });
+            }
+        }
+
+        if let Some(Value::Map(config)) = module.get_mut("config") {
+            if config.remove("sample_rate").is_some() {
+                changes.push(Change {
+                    module_id,
+                    field: format!("{}.config.sample_rate", path),
+                    change: "removed, the sample rate is set by the engine",
+                });
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT_0_4: &str = "\
+version: 0.4
+layout:
+  - module:
+      id: 0
+      type: oscillator
+      os-out: true
+      input-from: -1
+      config:
+        name: modulator
+        sample_rate: 44100
+        frequency: 440.0
+  - module:
+      id: 1
+      type: pass_through
+";
+
+    #[test]
+    fn test_migrate() {
+        let mut doc = Format::Yaml.parse(LAYOUT_0_4).unwrap();
+        let notes = migrate(&mut doc).unwrap();
+
+        assert_eq!(get_version(&doc).unwrap(), YAML_VERSION);
+        assert_eq!(
+            notes
+                .iter()
+                .map(|note| note.field.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "layout[0].module.input-from",
+                "layout[0].module.config.sample_rate"
+            ]
+        );
+        assert!(notes.iter().all(|note| note.module_id == Some(0)));
+
+        let module = &doc["layout"][0]["module"];
+        assert!(module["input-from"].is_missing());
+        assert!(module["config"]["sample_rate"].is_missing());
+        assert_eq!(module["config"]["frequency"].as_f64(), Some(440.0));
+
+        // Already up to date
+        assert!(migrate(&mut doc).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_document() {
+        let migration = migrate_document(LAYOUT_0_4, Format::Yaml).unwrap().unwrap();
+        assert_eq!(migration.from, "0.4");
+        assert_eq!(migration.notes.len(), 2);
+        assert!(migrate_document(&migration.document, Format::Yaml)
+            .unwrap()
+            .is_none());
+
+        // Written back in the same format
+        let json = Format::Json
+            .emit(&Format::Yaml.parse(LAYOUT_0_4).unwrap())
+            .unwrap();
+        let migration = migrate_document(&json, Format::Json).unwrap().unwrap();
+        assert_eq!(migration.notes.len(), 2);
+        assert_eq!(
+            Format::Json.parse(&migration.document).unwrap()["version"].as_f64(),
+            Some(0.5)
+        );
+
+        assert!(matches!(
+            migrate_document("version: 0.3\nlayout: []", Format::Yaml),
+            Err(YamlParsingError::VersionMismatch { .. })
+        ));
+        assert!(matches!(
+            migrate_document("layout: []", Format::Yaml),
+            Err(YamlParsingError::MissingVersionNumber(_))
+        ));
+    }
+}
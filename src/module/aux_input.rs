@@ -1,5 +1,10 @@
 use crate::module::ModuleConsumer;
 
+/// Maximum value of an [AuxiliaryInput] built without one.
+pub(crate) const DEFAULT_AUX_MAX: f32 = 1.0;
+/// Minimum value of an [AuxiliaryInput] built without one.
+pub(crate) const DEFAULT_AUX_MIN: f32 = 0.0;
+
 /// An **Auxiliary Input** allows routing the output of a module to another one. They can also be
 /// understood as **side chain connections**.
 /// # Parameters
@@ -71,14 +76,15 @@ impl AuxiliaryInput {
     /// match the output of any module [-1, 1] to the values set when
     /// [building](struct@AuxInputBuilder) the auxiliary.
     /// ```rust
+    /// # use lion_synth::module::{AuxDataHolder, AuxInputBuilder};
     /// let buffer = vec![0.0f32; 10];
-    ///         
-    /// AuxInputBuilder::new("amplitude", buffer)
+    ///
+    /// AuxInputBuilder::new("amplitude", AuxDataHolder::Batch(buffer))
     ///     .with_min(0.0)
     ///     .with_max(1.0)
     ///     .build()
     ///     .unwrap();
-    ///         
+    ///
     /// // Input: -1.0; Output: 0.0
     /// // Input:  0.0; Output: 0.5
     /// // Input:  1.0; Output: 1.0
@@ -95,18 +101,21 @@ impl AuxiliaryInput {
 /// be used, please check the **[AuxiliaryInput]** page.
 /// # Usage
 /// ```rust
+/// # use lion_synth::bundled_modules::OscillatorBuilder;
+/// # use lion_synth::module::{AuxDataHolder, AuxInputBuilder, Module};
 /// let buffer = vec![0.0f32; 10]; // Buffer with the output of the previous module
 ///
 /// // Linking the auxiliary with the frequency (FM)
-/// let mut aux = AuxInputBuilder::new("frequency", buffer).build().unwrap();
+/// let aux = AuxInputBuilder::new("frequency", AuxDataHolder::Batch(buffer))
+///     .build()
+///     .unwrap();
 ///
 /// // Creating a buffer and an oscillator
 /// let mut buffer = vec![0.0f32; 10];
 /// let mut osc = OscillatorBuilder::new().build().unwrap();
 ///
 /// // Fill the data of the buffer with the new auxiliary.
-/// osc.fill_buffer_w_aux(&mut buffer, Some(vec![&mut aux]));
-///
+/// osc.fill_buffer(&mut buffer, 44100, vec![aux]);
 /// ```
 pub struct AuxInputBuilder {
     /// Tag matching the [Parameter] field.
@@ -154,8 +163,8 @@ impl AuxInputBuilder {
 
     /// Generates an [AuxiliaryInput] from the values specified.
     pub fn build(self) -> Result<AuxiliaryInput, String> {
-        let max = self.max.unwrap_or(1.0);
-        let min = self.min.unwrap_or(0.0);
+        let max = self.max.unwrap_or(DEFAULT_AUX_MAX);
+        let min = self.min.unwrap_or(DEFAULT_AUX_MIN);
 
         if max < min {
             return Err("Invalid range".to_string());
@@ -209,6 +218,16 @@ impl AuxDataHolder {
             _ => None,
         }
     }
+
+    /// Discards every value not consumed yet.
+    pub fn clear(&mut self) {
+        match self {
+            Self::Batch(buffer) => buffer.clear(),
+            Self::RealTime(consumer) => {
+                consumer.clear();
+            }
+        }
+    }
 }
 
 #[cfg(test)]
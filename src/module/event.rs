@@ -0,0 +1,108 @@
+use thiserror::Error;
+
+/// Events are messages exchanged among modules alongside the audio signal. Unlike samples, they
+/// are not continuous: they happen at a specific moment and carry a meaning of their own, such as
+/// a key being pressed or the tempo being changed.
+///
+/// Events are delivered by the [CoordinatorEntity](struct@crate::module::CoordinatorEntity) to the
+/// [`handle_event`](fn@crate::module::Module::handle_event) method of the modules declaring
+/// the matching [EventKind] as an input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A note starts playing. The note follows the MIDI numbering (69 is A4, 440 Hz) and the
+    /// velocity ranges from 0 to 1.
+    NoteOn { note: u8, velocity: f32 },
+    /// A note stops playing.
+    NoteOff { note: u8 },
+    /// A gate opens (`true`) or closes (`false`).
+    Gate(bool),
+    /// A single trigger, with no duration.
+    Trigger,
+    /// A [Parameter](struct@crate::module::Parameter) of the receiving module must change.
+    ParameterChange { tag: String, value: f32 },
+    /// The tempo changes, in beats per minute.
+    Tempo(f32),
+}
+
+/// The kind of an [Event], used by modules to declare which events they consume or produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Note,
+    Gate,
+    Trigger,
+    ParameterChange,
+    Tempo,
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::NoteOn { .. } | Self::NoteOff { .. } => EventKind::Note,
+            Self::Gate(_) => EventKind::Gate,
+            Self::Trigger => EventKind::Trigger,
+            Self::ParameterChange { .. } => EventKind::ParameterChange,
+            Self::Tempo(_) => EventKind::Tempo,
+        }
+    }
+}
+
+/// An [Event] together with the amount of samples to wait before delivering it. An offset of zero
+/// means the event is delivered before the next sample is processed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedEvent {
+    pub offset: u32,
+    pub event: Event,
+}
+
+impl TimedEvent {
+    pub fn new(offset: u32, event: Event) -> Self {
+        Self { offset, event }
+    }
+
+    /// An event to be delivered right away.
+    pub fn now(event: Event) -> Self {
+        Self::new(0, event)
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[non_exhaustive]
+pub enum EventError {
+    #[error("Module {0} not found in the chain")]
+    ModuleNotFound(i64),
+    #[error("Module {from} produces no event that module {to} accepts")]
+    IncompatibleEvents { from: i64, to: i64 },
+}
+
+/// Converts a MIDI note number to its frequency in Hz, using A4 (69) at 440 Hz as reference.
+pub fn note_to_frequency(note: u8) -> f32 {
+    440.0 * 2f32.powf((note as f32 - 69.0) / 12.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(
+            Event::NoteOn {
+                note: 60,
+                velocity: 1.0
+            }
+            .kind(),
+            EventKind::Note
+        );
+        assert_eq!(Event::NoteOff { note: 60 }.kind(), EventKind::Note);
+        assert_eq!(Event::Gate(true).kind(), EventKind::Gate);
+        assert_eq!(Event::Trigger.kind(), EventKind::Trigger);
+        assert_eq!(Event::Tempo(120.0).kind(), EventKind::Tempo);
+    }
+
+    #[test]
+    fn test_note_to_frequency() {
+        assert_eq!(note_to_frequency(69), 440.0);
+        assert_eq!(note_to_frequency(81), 880.0);
+        assert_eq!(note_to_frequency(57), 220.0);
+    }
+}
@@ -1,13 +1,20 @@
 mod aux_input;
+mod event;
 mod module;
 mod parameter;
 mod real_time;
 
 pub use aux_input::{AuxDataHolder, AuxInputBuilder, AuxiliaryInput};
+pub(crate) use aux_input::{DEFAULT_AUX_MAX, DEFAULT_AUX_MIN};
+pub use event::{note_to_frequency, Event, EventError, EventKind, TimedEvent};
+pub(crate) use module::advise_custom_fill;
 pub use module::Module;
 pub use parameter::{Parameter, ParameterBuilder};
+pub(crate) use real_time::{ChainLinks, Routing};
 pub use real_time::{
-    Clock, CoordinatorEntity, GeneratorModuleWrapper, LinkerModuleWrapper, ModuleWrapper,
+    Clock, ControlError, ControlHandle, CoordinatorEntity, GeneratorModuleWrapper, GraphEdit,
+    LinkerModuleWrapper, ModuleWrapper, ParameterChange, TimeSignature, Transport,
+    TransportCommand, TransportState, TICKS_PER_BEAT,
 };
 
 // TYPES
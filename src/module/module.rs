@@ -1,8 +1,15 @@
+use crate::document::Value;
 use simplelog::{error, info, warn};
 use std::collections::HashMap;
 
 use super::*;
 
+/// Recommends modules to fill buffers on their own. Logged by whatever fills the buffers through
+/// the default implementation, once per render.
+pub(crate) fn advise_custom_fill() {
+    warn!("<b>A <u>custom implementation</><b> for buffer filling with auxiliary inputs is recommended for better <yellow>performance</><b>.</>");
+}
+
 /// Receives a list of the last values of the given auxiliaries.
 pub fn pop_auxiliaries(
     auxiliaries: &mut Vec<AuxiliaryInput>,
@@ -56,16 +63,24 @@ pub fn pop_auxiliaries(
 /// the statefulness. The first will keep the values and the buffers until consumption (stateful).
 /// On the other hand, the second will calculate the value on a specific moment. The modules
 /// don't even remember the time of the clock.
+///
+/// # State
+/// The [behaviour](fn@Module::behavior) receives the module mutably, so modules such as filters,
+/// envelopes or delays can keep whatever state they need between samples in their own fields.
+/// Such state must be cleared in [`reset`](fn@Module::reset).
+///
+/// Modules must be [Send], as the chains of the real time engine may be built on another thread,
+/// such as when a layout is reloaded while playing.
 /// TODO: finish doc
-pub trait Module {
-    fn get_sample(&self, in_sample: f32, time: f32) -> f32 {
+pub trait Module: Send {
+    fn get_sample(&mut self, in_sample: f32, time: f64) -> f32 {
         self.behavior(in_sample, time)
     }
 
     fn get_sample_w_aux(
         &mut self,
         in_sample: f32,
-        time: f32,
+        time: f64,
         auxiliaries: HashMap<String, f32>,
     ) -> f32 {
         self.update_parameters(auxiliaries);
@@ -101,42 +116,73 @@ pub trait Module {
     /// return value of this function into the [`fill_buffer_at`](fn@Module::fill_buffer_at)
     /// function.
     ///
-    /// In the contrary, this function always starts with the clock at zero (the beginning).
+    /// In the contrary, this function always starts with the clock at zero (the beginning), so
+    /// the module is [reset](fn@Module::reset) before filling the buffer.
     ///
     /// # Arguments
     /// * `buffer` - The buffer to fill/modify.
     /// * `auxiliaries` - A vector with the auxiliary inputs for the operation. Can be empty.
     ///
     /// # Returns
-    /// The last value of the clock.
+    /// The position of the clock (in samples) after the last sample.
     // TODO stereo
     fn fill_buffer(
         &mut self,
         buffer: &mut Vec<f32>,
         sample_rate: i32,
         auxiliaries: Vec<AuxiliaryInput>,
-    ) -> f32 {
-        self.fill_buffer_at(buffer, 0.0, sample_rate, auxiliaries)
+    ) -> u64 {
+        self.reset();
+        self.fill_buffer_at(buffer, 0, sample_rate, auxiliaries)
     }
 
     /// Does the same as [`fill_buffer`](fn@Module::fill_buffer) function though a starting time
-    /// for the clock can be specified.
+    /// for the clock can be specified. The module sees a stopped [Transport], see
+    /// [`fill_buffer_with_transport`](fn@Module::fill_buffer_with_transport) for driving one.
     ///
     /// Please read [`fill_buffer`](fn@Module::fill_buffer) for more information.
     /// # Arguments
     /// * `buffer` - The buffer to fill/modify.
     /// * `auxiliaries` - A vector with the auxiliary inputs for the operation. Can be empty.
-    /// * `start_at` - The value of the clock where it should start.
+    /// * `start_at` - The position of the clock (in samples) where it should start.
     ///
     /// # Returns
-    /// The last value of the clock.
+    /// The position of the clock (in samples) after the last sample.
     fn fill_buffer_at(
         &mut self,
         buffer: &mut Vec<f32>,
-        start_at: f32,
+        start_at: u64,
+        sample_rate: i32,
+        auxiliaries: Vec<AuxiliaryInput>,
+    ) -> u64 {
+        advise_custom_fill();
+
+        let mut transport = Transport::new(sample_rate);
+        self.fill_buffer_with_transport(buffer, start_at, sample_rate, auxiliaries, &mut transport)
+    }
+
+    /// Does the same as [`fill_buffer_at`](fn@Module::fill_buffer_at), handing the state of the
+    /// transport over to the module before every sample, as the [CoordinatorEntity] does. The
+    /// transport moves forward one sample at a time, so the same one must be given to the
+    /// following calls. The offline renders of a [Patch](struct@crate::Patch) go through here.
+    ///
+    /// Please read [`fill_buffer`](fn@Module::fill_buffer) for more information.
+    /// # Arguments
+    /// * `buffer` - The buffer to fill/modify.
+    /// * `start_at` - The position of the clock (in samples) where it should start.
+    /// * `auxiliaries` - A vector with the auxiliary inputs for the operation. Can be empty.
+    /// * `transport` - The transport at the position of the first sample.
+    ///
+    /// # Returns
+    /// The position of the clock (in samples) after the last sample.
+    fn fill_buffer_with_transport(
+        &mut self,
+        buffer: &mut Vec<f32>,
+        start_at: u64,
         sample_rate: i32,
         mut auxiliaries: Vec<AuxiliaryInput>,
-    ) -> f32 {
+        transport: &mut Transport,
+    ) -> u64 {
         #[cfg(feature = "verbose_modules")]
         {
             info!("<b>Running module <cyan>{}</>", self.get_name());
@@ -144,7 +190,7 @@ pub trait Module {
 
         // TODO modularize this function not to reimplement the unnecessary.
         // maybe receive a closure with popping the values?
-        warn!("<b>A <u>custom implementation</><b> for buffer filling with auxiliary inputs is recommended for better <yellow>performance</><b>.</>");
+        // Renders done block by block call it over and over, so they give the advice themselves
 
         let mut clock = Clock::new_at(sample_rate, start_at);
 
@@ -168,24 +214,63 @@ pub trait Module {
 
         // FILLING THE BUFFER IS THIS EASY
         buffer.iter_mut().for_each(|sample| {
+            self.update_transport(&transport.get_state());
             self.update_parameters(pop_auxiliaries(
                 &mut auxiliaries,
                 self.get_current_parameter_values(),
             ));
-            *sample = self.get_sample(*sample, clock.inc())
+            *sample = self.get_sample(*sample, clock.inc());
+            transport.advance();
         });
 
-        clock.get_time()
+        clock.get_sample_pos()
     }
 
+    /// Prepares the module for processing at a given sample rate, before any sample is requested.
+    /// Modules depending on the sample rate (delays, filters...) should allocate and calculate
+    /// whatever they need here rather than in the processing methods.
+    ///
+    /// It is called by the [CoordinatorEntity] and the YAML entry points. When using a module on
+    /// its own, call it before [`fill_buffer`](fn@Module::fill_buffer).
+    /// # Arguments
+    /// * `sample_rate`: the sample rate of the engine.
+    /// * `max_block`: the maximum amount of samples that will be requested at once.
+    fn prepare(&mut self, _sample_rate: i32, _max_block: usize) {}
+
+    /// Clears the state of the module, as if no sample had been processed yet. Called every time
+    /// the playback (re)starts from the beginning, including [`fill_buffer`](fn@Module::fill_buffer).
+    ///
+    /// Parameters set by the user should be kept. Only the state generated while processing
+    /// (such as delay lines, envelopes or the values received from auxiliaries) must be cleared.
+    fn reset(&mut self) {}
+
+    /// Frees the resources acquired in [`prepare`](fn@Module::prepare). Called once the module
+    /// will not process any more samples unless it is prepared again.
+    fn release(&mut self) {}
+
+    /// The state generated while processing that a module of the same type can take over, such
+    /// as the position of an oscillator within its cycle. Hot reloads hand it over to the module
+    /// replacing one not edited, so the sound goes on in phase. It should be small, as it is taken
+    /// between two samples. Modules with nothing to hand over return [null](Value::Null).
+    fn get_state(&self) -> Value {
+        Value::Null
+    }
+
+    /// Takes over the [state](fn@Module::get_state) of a module of the same type.
+    fn set_state(&mut self, _state: &Value) {}
+
     /// Defines the behaviour of the module. Is it going to generate data? Is it going to clip the
     /// data under a threshold? Here is where the magic happens. The **behaviour is what defines
     /// a module.**
     /// # Arguments
     /// * `in_data`: the sample to modify, if any. Won't use it if creating a generator module.
+    /// * `time`: the time of the sample, in seconds since the beginning. It never wraps.
+    ///   Periodic modules (such as oscillators) should move their own phase forward every
+    ///   sample instead of calculating it from the time, or the phase jumps whenever their
+    ///   frequency changes.
     /// # Returns
     /// A generated or modified sample.
-    fn behavior(&self, in_data: f32, time: f32) -> f32;
+    fn behavior(&mut self, in_data: f32, time: f64) -> f32;
 
     /*/// Adds a parameter to the list of parameters. If the tag is already in the list,
     /// the operation gets rejected.
@@ -222,7 +307,7 @@ pub trait Module {
     /// ## Example
     /// You can find find a real implementation in the
     /// [Oscillator](struct@crate::bundled_modules::Oscillator) module, **implementation section**.
-    /// ```rust
+    /// ```ignore
     /// pub fn get_name_of_param(&self) -> f32 { // All parameters should return f32
     ///     self.get_parameter("parameter_tag").unwrap().get_value() // Hiding the operation
     /// }
@@ -264,9 +349,100 @@ pub trait Module {
         }
     }
 
+    /// Receives the state of the [Transport](struct@crate::module::Transport) before every sample
+    /// processed by the coordinator. Modules synced to the tempo, such as sequencers or
+    /// delays, should keep what they need from it.
+    fn update_transport(&mut self, _transport: &TransportState) {}
+
+    /// Kinds of [Event] the module is able to handle. Modules don't receive any event unless
+    /// they declare it here.
+    fn get_event_inputs(&self) -> Vec<EventKind> {
+        vec![]
+    }
+
+    /// Kinds of [Event] the module may produce through [`emit_events`](fn@Module::emit_events).
+    fn get_event_outputs(&self) -> Vec<EventKind> {
+        vec![]
+    }
+
+    /// Reacts to an incoming [Event]. Only called for the kinds declared in
+    /// [`get_event_inputs`](fn@Module::get_event_inputs) and always before processing the sample
+    /// the event is scheduled for.
+    ///
+    /// By default, parameter changes are applied and the rest of events are ignored.
+    fn handle_event(&mut self, event: &Event) {
+        if let Event::ParameterChange { tag, value } = event {
+            self.update_parameters(HashMap::from([(tag.clone(), *value)]));
+        }
+    }
+
+    /// Collects the events produced by the module. Called by the coordinator right after
+    /// every sample is generated.
+    /// # Arguments
+    /// * `time`: the time of the sample just generated.
+    fn emit_events(&mut self, _time: f64) -> Vec<TimedEvent> {
+        vec![]
+    }
+
+    /// Describes the module as it is written in a layout: its `type` and its `config` node, with
+    /// the current values of its parameters. The config may be [null](Value::Null) if the module
+    /// has nothing to configure.
+    ///
+    /// It is what allows saving a [Patch](struct@crate::patch::Patch) as a layout, so the type
+    /// must be the one the module is [registered](struct@crate::ModuleRegistry) with. Modules
+    /// returning `None` (the default) cannot be saved.
+    fn get_layout_config(&self) -> Option<(String, Value)> {
+        None
+    }
+
     // USEFUL FOR DEBUGGING
     fn get_name(&self) -> String;
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    /// Outputs the average of the last two input samples.
+    struct TwoPointAverage {
+        previous: f32,
+    }
+
+    impl Module for TwoPointAverage {
+        fn behavior(&mut self, in_data: f32, _time: f64) -> f32 {
+            let value = (in_data + self.previous) / 2.0;
+            self.previous = in_data;
+            value
+        }
+
+        fn reset(&mut self) {
+            self.previous = 0.0;
+        }
+
+        fn get_parameters(&self) -> Option<Vec<&Parameter>> {
+            None
+        }
+
+        fn get_parameters_mutable(&mut self) -> Option<Vec<&mut Parameter>> {
+            None
+        }
+
+        fn get_name(&self) -> String {
+            "Two point average".to_string()
+        }
+    }
+
+    #[test]
+    fn test_stateful_module() {
+        let mut module = TwoPointAverage { previous: 0.0 };
+
+        let mut buffer = vec![2.0, 4.0, 0.0, 0.0];
+        module.fill_buffer(&mut buffer, 44100, vec![]);
+        assert_eq!(buffer, vec![1.0, 3.0, 2.0, 0.0]);
+
+        // The state of the previous buffer is cleared
+        let mut buffer = vec![2.0, 2.0];
+        module.fill_buffer(&mut buffer, 44100, vec![]);
+        assert_eq!(buffer, vec![1.0, 2.0]);
+    }
+}
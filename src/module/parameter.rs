@@ -51,6 +51,11 @@ impl Parameter {
         }
     }
 
+    /// Sets the parameter back to its default value.
+    pub fn reset(&mut self) {
+        self.current = self.default;
+    }
+
     /// Increases the value of the parameter upon maximum.
     pub fn inc(&mut self) {
         // if value exceeds the maximum, keep the max value.
@@ -82,9 +87,10 @@ impl Parameter {
 /// information about the fields and how should it be used.
 /// # Example
 /// ```rust
+/// # use lion_synth::module::ParameterBuilder;
 /// // A parameter for changing the value of the frequency at any given moment.
 /// ParameterBuilder::new("frequency".to_string())
-///     .with_min(22000.0) // 22k Hz for the max
+///     .with_max(22000.0) // 22k Hz for the max
 ///     .with_min(10.0) // 10  Hz for the min
 ///     .with_step(10.0) // Increments from 10 to 10 Hz
 ///     .with_default(440.0) // The value starting at is 440 Hz
@@ -152,11 +158,17 @@ impl ParameterBuilder {
         let tag = self.tag;
 
         if max < min {
-            return Err("Non valid max/min range.".to_string());
+            return Err(format!(
+                "Non valid max/min range for '{}': [{}, {}].",
+                tag, min, max
+            ));
         }
 
         if default > max || default < min {
-            return Err("Default value is out of range.".to_string());
+            return Err(format!(
+                "Default value of '{}' is out of range: {} not in [{}, {}].",
+                tag, default, min, max
+            ));
         }
 
         // This is not technically an error - but it is simply stupid (or just a slip-up).
@@ -336,6 +348,15 @@ mod tests {
             assert_eq!(parameter.get_value(), 1.2, "Greater than check wrong");
         }
 
+        #[test]
+        fn test_reset() {
+            let mut parameter = get_parameter();
+
+            parameter.set(1.0);
+            parameter.reset();
+            assert_eq!(parameter.get_value(), 0.5, "Not back to default");
+        }
+
         #[test]
         fn test_inc() {
             let mut parameter = get_parameter();
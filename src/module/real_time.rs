@@ -1,7 +1,13 @@
+use crate::layout_yaml::build_wrapper_chain;
 use crate::module::module::pop_auxiliaries;
 use crate::module::*;
-use simplelog::{info, warn};
-use std::collections::LinkedList;
+use crate::patch::{AuxRoute, ChainCell};
+use crossbeam::channel::{Receiver, Sender, TrySendError};
+use ringbuf::HeapRb;
+use simplelog::{error, info, warn};
+use std::collections::{HashMap, HashSet, LinkedList};
+use std::sync::atomic::{fence, AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 
 use thiserror::Error;
 
@@ -14,13 +20,24 @@ pub enum WrapperError {
     ProducerFull(String),
 }
 
-pub trait ModuleWrapper {
-    fn gen_sample(&mut self, time: f32) -> Result<(), WrapperError>;
+pub trait ModuleWrapper: Send {
+    fn gen_sample(&mut self, time: f64) -> Result<(), WrapperError>;
     fn get_name(&self) -> String;
+    /// The identifier of the wrapped module within the layout, if it has one. Used by the
+    /// [ControlHandle] to address the module from outside the audio loop.
+    fn get_id(&self) -> Option<i64>;
+    fn get_module(&self) -> &dyn Module;
+    fn get_mut_module(&mut self) -> &mut dyn Module;
+    /// [Resets](fn@Module::reset) the wrapped module and discards the samples waiting in the
+    /// ring buffers it consumes from.
+    fn reset(&mut self);
     fn get_producer(&self) -> &ModuleProducer;
     fn get_mut_producer(&mut self) -> &mut ModuleProducer;
     fn get_consumer(&self) -> Option<&ModuleConsumer>;
     fn get_mut_consumer(&mut self) -> Option<&mut ModuleConsumer>;
+    /// Exchanges the wrapped module with the given one, so a module can move to a chain with
+    /// different connections without being reallocated.
+    fn swap_module(&mut self, module: &mut Box<dyn Module>);
 }
 
 /// A **linker module** is a module able to consume data from modules, process it, and deliver it
@@ -39,6 +56,7 @@ pub trait ModuleWrapper {
 /// the chain, and the *consumer* of the linker module must be connected to the *producer* of the
 /// **previous module** in the chain.
 pub struct LinkerModuleWrapper {
+    id: Option<i64>,
     module: Box<dyn Module>,
     consumer: ModuleConsumer,
     producer: ModuleProducer,
@@ -53,16 +71,23 @@ impl LinkerModuleWrapper {
         aux_inputs: Vec<AuxiliaryInput>,
     ) -> Self {
         Self {
+            id: None,
             module,
             consumer,
             producer,
             aux_inputs,
         }
     }
+
+    /// Sets the identifier the wrapped module is known by (its layout ID).
+    pub fn with_id(mut self, id: i64) -> Self {
+        self.id = Some(id);
+        self
+    }
 }
 
 impl ModuleWrapper for LinkerModuleWrapper {
-    fn gen_sample(&mut self, time: f32) -> Result<(), WrapperError> {
+    fn gen_sample(&mut self, time: f64) -> Result<(), WrapperError> {
         if self.consumer.is_empty() {
             warn!("<b>Buffer <yellow>empty</><b> in Linker Module.</>");
             warn!("  |_ name: {}", self.module.get_name());
@@ -94,6 +119,26 @@ impl ModuleWrapper for LinkerModuleWrapper {
         self.module.get_name().clone()
     }
 
+    fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    fn get_module(&self) -> &dyn Module {
+        self.module.as_ref()
+    }
+
+    fn get_mut_module(&mut self) -> &mut dyn Module {
+        self.module.as_mut()
+    }
+
+    fn reset(&mut self) {
+        self.module.reset();
+        self.consumer.clear();
+        self.aux_inputs
+            .iter_mut()
+            .for_each(|aux| aux.get_mut_data().clear());
+    }
+
     fn get_producer(&self) -> &ModuleProducer {
         &self.producer
     }
@@ -109,6 +154,10 @@ impl ModuleWrapper for LinkerModuleWrapper {
     fn get_mut_consumer(&mut self) -> Option<&mut ModuleConsumer> {
         Some(&mut self.consumer)
     }
+
+    fn swap_module(&mut self, module: &mut Box<dyn Module>) {
+        std::mem::swap(&mut self.module, module);
+    }
 }
 
 /// A **generator module** is a module able to generate and deliver data to another module.
@@ -122,6 +171,7 @@ impl ModuleWrapper for LinkerModuleWrapper {
 /// The *producer* of a generator module must be connected to the *consumer* of the **next module** in
 /// the chain.
 pub struct GeneratorModuleWrapper {
+    id: Option<i64>,
     module: Box<dyn Module>,
     producer: ModuleProducer,
     aux_inputs: Vec<AuxiliaryInput>,
@@ -134,15 +184,22 @@ impl GeneratorModuleWrapper {
         aux_inputs: Vec<AuxiliaryInput>,
     ) -> Self {
         Self {
+            id: None,
             module,
             producer,
             aux_inputs,
         }
     }
+
+    /// Sets the identifier the wrapped module is known by (its layout ID).
+    pub fn with_id(mut self, id: i64) -> Self {
+        self.id = Some(id);
+        self
+    }
 }
 
 impl ModuleWrapper for GeneratorModuleWrapper {
-    fn gen_sample(&mut self, time: f32) -> Result<(), WrapperError> {
+    fn gen_sample(&mut self, time: f64) -> Result<(), WrapperError> {
         if self.producer.is_full() {
             warn!("<b>Buffer <yellow>full</><b> in Generator Module.</>");
             warn!("  |_ name: {}", self.module.get_name());
@@ -165,6 +222,25 @@ impl ModuleWrapper for GeneratorModuleWrapper {
         self.module.get_name().clone()
     }
 
+    fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    fn get_module(&self) -> &dyn Module {
+        self.module.as_ref()
+    }
+
+    fn get_mut_module(&mut self) -> &mut dyn Module {
+        self.module.as_mut()
+    }
+
+    fn reset(&mut self) {
+        self.module.reset();
+        self.aux_inputs
+            .iter_mut()
+            .for_each(|aux| aux.get_mut_data().clear());
+    }
+
     fn get_producer(&self) -> &ModuleProducer {
         &self.producer
     }
@@ -180,73 +256,1104 @@ impl ModuleWrapper for GeneratorModuleWrapper {
     fn get_mut_consumer(&mut self) -> Option<&mut ModuleConsumer> {
         None
     }
+
+    fn swap_module(&mut self, module: &mut Box<dyn Module>) {
+        std::mem::swap(&mut self.module, module);
+    }
 }
 
 /// A structure with some bundled methods to easily manage time synchronization.
+///
+/// The clock counts samples since the beginning in a [u64], so it never wraps around and is sample
+/// accurate no matter how long it runs. The time in seconds is derived from it as a [f64].
 pub struct Clock {
-    tick: f32,
-    sample_rate: f32,
+    tick: u64,
+    sample_rate: f64,
 }
 
 impl Clock {
     pub fn new(sample_rate: i32) -> Self {
-        Self {
-            tick: 0.0,
-            sample_rate: sample_rate as f32,
-        }
+        Self::new_at(sample_rate, 0)
     }
 
-    pub fn new_at(sample_rate: i32, start_at: f32) -> Self {
+    /// Creates a clock starting at the given sample.
+    pub fn new_at(sample_rate: i32, start_at: u64) -> Self {
         Self {
             tick: start_at,
-            sample_rate: sample_rate as f32,
+            sample_rate: sample_rate as f64,
         }
     }
 
-    pub fn get_sample_pos(&self) -> f32 {
+    /// Amount of samples elapsed since the beginning.
+    pub fn get_sample_pos(&self) -> u64 {
         self.tick
     }
 
-    pub fn get_time(&self) -> f32 {
-        self.tick / self.sample_rate
+    /// Seconds elapsed since the beginning.
+    pub fn get_time(&self) -> f64 {
+        self.tick as f64 / self.sample_rate
     }
 
-    pub fn get_sample_rate(&self) -> f32 {
+    pub fn get_sample_rate(&self) -> f64 {
         self.sample_rate
     }
 
-    pub fn post_inc(&mut self) -> f32 {
-        self.tick = (self.tick + 1.0) % self.sample_rate;
-        self.tick / self.sample_rate
+    /// Moves the clock one sample forward and returns the new time.
+    pub fn post_inc(&mut self) -> f64 {
+        self.tick += 1;
+        self.get_time()
+    }
+
+    /// Moves the clock one sample forward and returns the time before moving.
+    pub fn inc(&mut self) -> f64 {
+        let prev = self.get_time();
+        self.tick += 1;
+        prev
+    }
+}
+
+/// Resolution of the [Transport] position, in ticks per beat.
+pub const TICKS_PER_BEAT: u32 = 960;
+
+/// The time signature of the [Transport], such as 3/4 or 6/8.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSignature {
+    /// Amount of beats in a bar (numerator).
+    pub beats_per_bar: u32,
+    /// Note value of a beat (denominator).
+    pub beat_unit: u32,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self {
+            beats_per_bar: 4,
+            beat_unit: 4,
+        }
+    }
+}
+
+/// A snapshot of the [Transport] handed over to every module before each sample through
+/// [`update_transport`](fn@Module::update_transport).
+///
+/// Bars and beats start counting at one, as displayed by any sequencer, while ticks start at zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransportState {
+    pub playing: bool,
+    /// Tempo in beats per minute.
+    pub tempo: f32,
+    pub time_signature: TimeSignature,
+    /// Position in beats since the beginning.
+    pub beat_position: f64,
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+impl TransportState {
+    /// Position within the current beat, from 0 to 1. Handy for tempo synced LFOs.
+    pub fn beat_phase(&self) -> f32 {
+        self.beat_position.fract() as f32
+    }
+}
+
+/// Keeps the musical time of the [CoordinatorEntity]: tempo, time signature, position (bars, beats
+/// and ticks) and whether it is playing. Unlike the [Clock], the position only advances while
+/// playing.
+#[derive(Clone)]
+pub struct Transport {
+    playing: bool,
+    tempo: f32,
+    time_signature: TimeSignature,
+    beat_position: f64,
+    sample_rate: f32,
+}
+
+impl Transport {
+    /// A stopped transport at 120 BPM in 4/4.
+    pub fn new(sample_rate: i32) -> Self {
+        Self {
+            playing: false,
+            tempo: 120.0,
+            time_signature: TimeSignature::default(),
+            beat_position: 0.0,
+            sample_rate: sample_rate as f32,
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn get_tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    /// Sets the tempo in beats per minute. Non positive values are rejected.
+    pub fn set_tempo(&mut self, tempo: f32) -> Result<(), String> {
+        if !tempo.is_finite() || tempo <= 0.0 {
+            return Err(format!("Invalid tempo: {}", tempo));
+        }
+
+        self.tempo = tempo;
+        Ok(())
+    }
+
+    pub fn get_time_signature(&self) -> TimeSignature {
+        self.time_signature
+    }
+
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) -> Result<(), String> {
+        if time_signature.beats_per_bar == 0 || !time_signature.beat_unit.is_power_of_two() {
+            return Err(format!(
+                "Invalid time signature: {}/{}",
+                time_signature.beats_per_bar, time_signature.beat_unit
+            ));
+        }
+
+        self.time_signature = time_signature;
+        Ok(())
+    }
+
+    /// Moves the position to the given beat.
+    pub fn locate(&mut self, beat_position: f64) {
+        self.beat_position = beat_position.max(0.0);
+    }
+
+    /// Moves one sample forward, if playing.
+    pub fn advance(&mut self) {
+        if self.playing {
+            self.beat_position += self.tempo as f64 / 60.0 / self.sample_rate as f64;
+        }
+    }
+
+    pub fn get_state(&self) -> TransportState {
+        let beats_per_bar = self.time_signature.beats_per_bar as f64;
+        let whole_beats = self.beat_position.floor();
+
+        TransportState {
+            playing: self.playing,
+            tempo: self.tempo,
+            time_signature: self.time_signature,
+            beat_position: self.beat_position,
+            bar: (whole_beats / beats_per_bar) as u32 + 1,
+            beat: (whole_beats % beats_per_bar) as u32 + 1,
+            tick: (self.beat_position.fract() * TICKS_PER_BEAT as f64) as u32,
+        }
+    }
+}
+
+/// The latest [TransportState], published by the coordinator after every tick and read by the
+/// [ControlHandle]s without locking. A sequence number, odd while the state is being written,
+/// tells the readers to try again when they caught it half written.
+#[derive(Default)]
+struct TransportCell {
+    sequence: AtomicU64,
+    playing: AtomicBool,
+    tempo: AtomicU32,
+    beats_per_bar: AtomicU32,
+    beat_unit: AtomicU32,
+    beat_position: AtomicU64,
+    bar: AtomicU32,
+    beat: AtomicU32,
+    tick: AtomicU32,
+}
+
+impl TransportCell {
+    fn new(state: TransportState) -> Self {
+        let cell = Self::default();
+        cell.publish(&state);
+        cell
+    }
+
+    /// Stores a new state. Only the coordinator owning the cell writes to it, so writes never
+    /// overlap.
+    fn publish(&self, state: &TransportState) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        self.sequence.store(sequence + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.playing.store(state.playing, Ordering::Relaxed);
+        self.tempo.store(state.tempo.to_bits(), Ordering::Relaxed);
+        let signature = state.time_signature;
+        self.beats_per_bar
+            .store(signature.beats_per_bar, Ordering::Relaxed);
+        self.beat_unit.store(signature.beat_unit, Ordering::Relaxed);
+        self.beat_position
+            .store(state.beat_position.to_bits(), Ordering::Relaxed);
+        self.bar.store(state.bar, Ordering::Relaxed);
+        self.beat.store(state.beat, Ordering::Relaxed);
+        self.tick.store(state.tick, Ordering::Relaxed);
+
+        self.sequence.store(sequence + 2, Ordering::Release);
+    }
+
+    fn load(&self) -> TransportState {
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            if !sequence.is_multiple_of(2) {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            let state = TransportState {
+                playing: self.playing.load(Ordering::Relaxed),
+                tempo: f32::from_bits(self.tempo.load(Ordering::Relaxed)),
+                time_signature: TimeSignature {
+                    beats_per_bar: self.beats_per_bar.load(Ordering::Relaxed),
+                    beat_unit: self.beat_unit.load(Ordering::Relaxed),
+                },
+                beat_position: f64::from_bits(self.beat_position.load(Ordering::Relaxed)),
+                bar: self.bar.load(Ordering::Relaxed),
+                beat: self.beat.load(Ordering::Relaxed),
+                tick: self.tick.load(Ordering::Relaxed),
+            };
+
+            fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) == sequence {
+                return state;
+            }
+        }
+    }
+}
+
+/// Capacity of the queue holding the parameter changes not yet applied by the coordinator.
+const CONTROL_QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug, Error, PartialEq)]
+#[non_exhaustive]
+pub enum ControlError {
+    #[error("Module {0} not found in the chain")]
+    UnknownModule(i64),
+    #[error("No parameter '{tag}' found in module {module_id}")]
+    UnknownParameter { module_id: i64, tag: String },
+    #[error("Too many parameter changes pending. Try again after the next tick.")]
+    QueueFull,
+    #[error("The engine is no longer running.")]
+    Disconnected,
+    #[error("Module {0} already exists")]
+    DuplicatedModule(i64),
+    #[error("Module {0} would feed more than one module")]
+    ReusedModule(i64),
+    #[error("No auxiliary '{tag}' found in module {module_id}")]
+    UnknownAuxiliary { module_id: i64, tag: String },
+    #[error("Invalid range for auxiliary '{tag}' of module {module_id}")]
+    InvalidAuxRange { module_id: i64, tag: String },
+    #[error("The chain was not built from a patch, so its connections are unknown.")]
+    NotEditable,
+    #[error("The edited chain could not be built: {0}")]
+    InvalidGraph(String),
+}
+
+/// A change of a [Parameter] requested from outside the audio loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterChange {
+    pub module_id: i64,
+    pub tag: String,
+    pub value: f32,
+}
+
+/// A command for the [Transport] sent from outside the audio loop.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportCommand {
+    Start,
+    Stop,
+    Locate(f64),
+    SetTempo(f32),
+    SetTimeSignature(TimeSignature),
+}
+
+/// A change to the wiring of a running chain, sent through
+/// [`edit_graph`](fn@ControlHandle::edit_graph). The edits mirror the methods of a
+/// [Patch](struct@crate::Patch).
+pub enum GraphEdit {
+    /// Adds a module, not wired to any other one yet.
+    AddModule { id: i64, module: Box<dyn Module> },
+    /// Removes a module. No other module may be wired to it once every edit is applied.
+    RemoveModule(i64),
+    /// Feeds the output of module `from` to the input of module `id`, or leaves module `id`
+    /// without input if `None`.
+    SetInput { id: i64, from: Option<i64> },
+    /// Routes an auxiliary to a parameter of module `id`.
+    AddAuxiliary { id: i64, aux: AuxRoute },
+    /// Removes every auxiliary of module `id` linked with the given parameter.
+    RemoveAuxiliary { id: i64, linked_with: String },
+    /// Sets the module linked to the output.
+    SetOutput(i64),
+}
+
+/// Everything a [ControlHandle] can ask the coordinator to do.
+enum ControlMessage {
+    Parameter(ParameterChange),
+    Event(i64, TimedEvent),
+    Transport(TransportCommand),
+    Graph(ChainRebuild),
+}
+
+/// Connections of a module within the chain.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ChainLinks {
+    pub(crate) from_module: Option<i64>,
+    pub(crate) auxiliaries: Vec<AuxRoute>,
+}
+
+/// How the modules of a chain are wired, needed to rebuild the chain when it is edited. Modules
+/// not connected to the output are kept too, so they can be wired again later.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Routing {
+    pub(crate) links: HashMap<i64, ChainLinks>,
+    pub(crate) output: i64,
+    /// Tells apart every routing a chain went through, so a chain built for one of them is never
+    /// swapped into a coordinator playing another.
+    pub(crate) revision: u64,
+}
+
+impl Routing {
+    /// Applies the edits in order, checking the resulting chain can be built. Returns the modules
+    /// connected to the output.
+    fn apply(&mut self, edits: &[GraphEdit]) -> Result<HashSet<i64>, ControlError> {
+        for edit in edits {
+            match edit {
+                GraphEdit::AddModule { id, .. } => {
+                    if self.links.contains_key(id) {
+                        return Err(ControlError::DuplicatedModule(*id));
+                    }
+                    self.links.insert(*id, ChainLinks::default());
+                }
+                GraphEdit::RemoveModule(id) => {
+                    self.links
+                        .remove(id)
+                        .ok_or(ControlError::UnknownModule(*id))?;
+                }
+                GraphEdit::SetInput { id, from } => {
+                    self.get_links_mutable(*id)?.from_module = *from
+                }
+                GraphEdit::AddAuxiliary { id, aux } => {
+                    self.get_links_mutable(*id)?.auxiliaries.push(aux.clone())
+                }
+                GraphEdit::RemoveAuxiliary { id, linked_with } => {
+                    let auxiliaries = &mut self.get_links_mutable(*id)?.auxiliaries;
+                    let count = auxiliaries.len();
+                    auxiliaries.retain(|aux| aux.linked_with != *linked_with);
+
+                    if auxiliaries.len() == count {
+                        return Err(ControlError::UnknownAuxiliary {
+                            module_id: *id,
+                            tag: linked_with.clone(),
+                        });
+                    }
+                }
+                GraphEdit::SetOutput(id) => {
+                    self.get_links_mutable(*id)?;
+                    self.output = *id;
+                }
+            }
+        }
+
+        self.check()
+    }
+
+    /// Checks every module referenced exists and every module connected to the output feeds a
+    /// single module, which also rules out loops. Returns the modules connected to the output.
+    fn check(&self) -> Result<HashSet<i64>, ControlError> {
+        if !self.links.contains_key(&self.output) {
+            return Err(ControlError::UnknownModule(self.output));
+        }
+
+        for (id, links) in &self.links {
+            for aux in &links.auxiliaries {
+                let max = aux.max.unwrap_or(DEFAULT_AUX_MAX);
+                let min = aux.min.unwrap_or(DEFAULT_AUX_MIN);
+                if max < min {
+                    return Err(ControlError::InvalidAuxRange {
+                        module_id: *id,
+                        tag: aux.linked_with.clone(),
+                    });
+                }
+            }
+
+            let aux_from = links.auxiliaries.iter().map(|aux| aux.from_module);
+            for from in links.from_module.into_iter().chain(aux_from) {
+                if !self.links.contains_key(&from) {
+                    return Err(ControlError::UnknownModule(from));
+                }
+            }
+        }
+
+        let mut visited = HashSet::new();
+        let mut pending = vec![self.output];
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id) {
+                return Err(ControlError::ReusedModule(id));
+            }
+
+            let links = &self.links[&id];
+            pending.extend(links.from_module);
+            pending.extend(links.auxiliaries.iter().map(|aux| aux.from_module));
+        }
+
+        Ok(visited)
+    }
+
+    fn get_links_mutable(&mut self, id: i64) -> Result<&mut ChainLinks, ControlError> {
+        self.links
+            .get_mut(&id)
+            .ok_or(ControlError::UnknownModule(id))
+    }
+}
+
+/// Where the coordinator publishes the value of a parameter after every tick, as the bits of an
+/// [f32], so the [ControlHandle]s read it without locking the audio loop.
+type Slot = Arc<AtomicU32>;
+
+/// The slots of the parameters of a module, in the order [`get_parameters`](fn@Module::get_parameters)
+/// returns them.
+type ModuleSlots = Vec<(String, Slot)>;
+
+fn module_slots(module: &dyn Module) -> ModuleSlots {
+    module
+        .get_parameters()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|parameter| {
+            let value = AtomicU32::new(parameter.get_value().to_bits());
+            (parameter.get_tag().to_string(), Arc::new(value))
+        })
+        .collect()
+}
+
+/// The slots of every parameter, indexed by module ID. A table is never modified once shared:
+/// whenever the chain changes a new one is built, sharing the slots of the modules kept, and
+/// swapped with the previous one.
+#[derive(Default, Clone)]
+struct ParameterTable {
+    /// The modules processed by the chain.
+    attached: HashMap<i64, ModuleSlots>,
+    /// The modules waiting to be wired again, with the values they had when detached.
+    detached: HashMap<i64, ModuleSlots>,
+}
+
+impl ParameterTable {
+    /// Builds the table of the modules given, reading their current values.
+    fn new<'a>(
+        wrapper_chain: &LinkedList<Box<dyn ModuleWrapper>>,
+        detached: impl Iterator<Item = (&'a i64, &'a Box<dyn Module>)>,
+    ) -> Self {
+        let attached = wrapper_chain
+            .iter()
+            .filter_map(|wrapper| Some((wrapper.get_id()?, module_slots(wrapper.get_module()))))
+            .collect();
+        let detached = detached
+            .map(|(id, module)| (*id, module_slots(module.as_ref())))
+            .collect();
+
+        Self { attached, detached }
+    }
+
+    /// Builds the table once the edits leading to `routing` are applied, moving the slots of
+    /// every module to where the edits left it. The modules added come with their own slots.
+    fn rearranged(
+        &self,
+        routing: &Routing,
+        attached: &HashSet<i64>,
+        added: HashMap<i64, ModuleSlots>,
+    ) -> Self {
+        let mut modules: HashMap<i64, ModuleSlots> = self
+            .attached
+            .iter()
+            .chain(self.detached.iter())
+            .filter(|(id, _)| routing.links.contains_key(id))
+            .map(|(id, slots)| (*id, slots.clone()))
+            .collect();
+        modules.extend(added);
+
+        let mut table = Self::default();
+        for (id, slots) in modules {
+            match attached.contains(&id) {
+                true => table.attached.insert(id, slots),
+                false => table.detached.insert(id, slots),
+            };
+        }
+        table
+    }
+
+    /// The slots every module of the chain publishes to, in the order of the chain.
+    fn chain_slots(&self, wrapper_chain: &LinkedList<Box<dyn ModuleWrapper>>) -> Vec<Vec<Slot>> {
+        wrapper_chain
+            .iter()
+            .map(|wrapper| {
+                wrapper
+                    .get_id()
+                    .and_then(|id| self.attached.get(&id))
+                    .map(|slots| slots.iter().map(|(_, slot)| Arc::clone(slot)).collect())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+/// Source of the [revisions](Routing::revision), unique across coordinators so a chain built
+/// for one of them is never swapped into another after a reload.
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A chain built by [`edit_graph`](fn@ControlHandle::edit_graph) outside the audio loop. The
+/// modules already playing are not in it yet, they are left vacant until the coordinator moves
+/// them in. Once swapped in, the same structure carries the chain replaced back to the
+/// [ControlHandle]s, so it is dropped outside the audio loop too.
+struct ChainRebuild {
+    /// Revision of the routing the chain must replace.
+    base: u64,
+    revision: u64,
+    wrapper_chain: LinkedList<Box<dyn ModuleWrapper>>,
+    detached: HashMap<i64, Box<dyn Module>>,
+    /// The modules to be moved from the chain playing.
+    moved: Vec<i64>,
+    /// The slots every module of the chain publishes to.
+    parameter_slots: Vec<Vec<Slot>>,
+}
+
+impl ChainRebuild {
+    /// Builds the chain resulting from the edits, already applied to `routing`, publishing to the
+    /// slots of `table`. The modules added are prepared here.
+    fn build(
+        base: u64,
+        routing: &Routing,
+        edits: Vec<GraphEdit>,
+        sample_rate: i32,
+        table: &ParameterTable,
+    ) -> Result<Self, ControlError> {
+        let mut added = HashMap::new();
+        for edit in edits {
+            if let GraphEdit::AddModule { id, module } = edit {
+                if routing.links.contains_key(&id) {
+                    added.insert(id, module);
+                }
+            }
+        }
+
+        let mut moved = Vec::new();
+        let mut cells: HashMap<i64, ChainCell> = routing
+            .links
+            .iter()
+            .map(|(id, links)| {
+                let module = match added.remove(id) {
+                    Some(mut module) => {
+                        module.prepare(sample_rate, 1);
+                        module
+                    }
+                    None => {
+                        moved.push(*id);
+                        Box::new(Vacant) as Box<dyn Module>
+                    }
+                };
+                let cell = ChainCell {
+                    from_module: links.from_module,
+                    module,
+                    auxiliaries: links.auxiliaries.clone(),
+                };
+                (*id, cell)
+            })
+            .collect();
+
+        // The output delivers to the same ring buffer as the chain it replaces
+        let (producer, _) = HeapRb::<f32>::new(1).split();
+        let mut wrapper_chain = LinkedList::new();
+        build_wrapper_chain(
+            &mut cells,
+            &mut HashSet::new(),
+            routing.output,
+            &mut wrapper_chain,
+            producer,
+        )
+        .map_err(|err| ControlError::InvalidGraph(err.to_string()))?;
+
+        Ok(Self {
+            base,
+            revision: routing.revision,
+            parameter_slots: table.chain_slots(&wrapper_chain),
+            wrapper_chain,
+            detached: cells
+                .into_iter()
+                .map(|(id, cell)| (id, cell.module))
+                .collect(),
+            moved,
+        })
+    }
+
+    /// [Releases](fn@Module::release) every module left in the chain.
+    fn release(mut self) {
+        self.wrapper_chain
+            .iter_mut()
+            .for_each(|wrapper| wrapper.get_mut_module().release());
+        self.detached
+            .values_mut()
+            .for_each(|module| module.release());
     }
+}
+
+/// Stands in for a module while it is moved from a chain to another. Being empty, boxing it
+/// allocates nothing.
+struct Vacant;
+
+impl Module for Vacant {
+    fn behavior(&mut self, _in_data: f32, _time: f64) -> f32 {
+        0.0
+    }
+
+    fn get_parameters(&self) -> Option<Vec<&Parameter>> {
+        None
+    }
+
+    fn get_parameters_mutable(&mut self) -> Option<Vec<&mut Parameter>> {
+        None
+    }
+
+    fn get_name(&self) -> String {
+        "Vacant".to_string()
+    }
+}
+
+/// Exchanges the module with the given ID, either in the chain or detached, with `module`.
+fn exchange_module(
+    wrapper_chain: &mut LinkedList<Box<dyn ModuleWrapper>>,
+    detached: &mut HashMap<i64, Box<dyn Module>>,
+    id: i64,
+    module: &mut Box<dyn Module>,
+) {
+    match wrapper_chain
+        .iter_mut()
+        .find(|wrapper| wrapper.get_id() == Some(id))
+    {
+        Some(wrapper) => wrapper.swap_module(module),
+        None => {
+            if let Some(stored) = detached.get_mut(&id) {
+                std::mem::swap(stored, module);
+            }
+        }
+    }
+}
 
-    pub fn inc(&mut self) -> f32 {
-        let prev = self.tick;
-        self.tick = (self.tick + 1.0) % self.sample_rate;
-        prev / self.sample_rate
+/// Releases and drops the chains replaced since the last call.
+fn collect_garbage(garbage: &Receiver<ChainRebuild>) {
+    while let Ok(replaced) = garbage.try_recv() {
+        replaced.release();
     }
 }
 
+/// A handle for controlling the parameters of the modules while the [CoordinatorEntity] is
+/// running, possibly from another thread. It can be cloned freely.
+///
+/// Changes are sent through a lock-free channel and applied by the coordinator **between ticks**,
+/// so a module never sees a parameter changing in the middle of a sample.
+/// # Example
+/// ```no_run
+/// # use lion_synth::module::CoordinatorEntity;
+/// # use std::collections::LinkedList;
+/// # use std::thread;
+/// # let coordinator = CoordinatorEntity::new(44100, LinkedList::new());
+/// let handle = coordinator.control_handle();
+///
+/// thread::spawn(move || {
+///     handle.set_parameter(1, "frequency", 220.0).unwrap();
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ControlHandle {
+    sender: Sender<ControlMessage>,
+    values: Arc<RwLock<Arc<ParameterTable>>>,
+    transport: Arc<TransportCell>,
+    routing: Arc<RwLock<Option<Routing>>>,
+    garbage: Receiver<ChainRebuild>,
+    sample_rate: i32,
+}
+
+impl ControlHandle {
+    /// Requests a new value for a parameter. The value will be range checked by the
+    /// [Parameter] itself when applied.
+    ///
+    /// # Arguments
+    /// * `module_id` - The ID of the module in the layout.
+    /// * `tag` - The tag of the parameter.
+    /// * `value` - The new value of the parameter.
+    pub fn set_parameter(&self, module_id: i64, tag: &str, value: f32) -> Result<(), ControlError> {
+        if self.get_parameter(module_id, tag).is_none() {
+            return Err(ControlError::UnknownParameter {
+                module_id,
+                tag: tag.to_string(),
+            });
+        }
+
+        self.send(ControlMessage::Parameter(ParameterChange {
+            module_id,
+            tag: tag.to_string(),
+            value,
+        }))
+    }
+
+    /// Sends an [Event] to a module, such as the notes coming from a MIDI source. The offset of the
+    /// event counts from the next tick of the coordinator.
+    pub fn send_event(&self, module_id: i64, event: TimedEvent) -> Result<(), ControlError> {
+        if !self
+            .values
+            .read()
+            .unwrap()
+            .attached
+            .contains_key(&module_id)
+        {
+            return Err(ControlError::UnknownModule(module_id));
+        }
+
+        self.send(ControlMessage::Event(module_id, event))
+    }
+
+    /// Sends a command to the [Transport], applied between ticks like any other change.
+    pub fn send_transport(&self, command: TransportCommand) -> Result<(), ControlError> {
+        self.send(ControlMessage::Transport(command))
+    }
+
+    /// Changes the wiring of the chain: modules may be added or removed and their audio and
+    /// auxiliary connections routed anew. The edits are applied in order, and the new chain is
+    /// built right here, with ring buffers of its own. It is swapped in between two ticks, when
+    /// the modules already in the chain are moved to the new one, so they keep their state.
+    ///
+    /// The edits are rejected as a whole if the resulting chain cannot be built. Modules left
+    /// without a path to the output stop being processed, but are kept until removed, so they
+    /// can be wired again. The modules removed are released and dropped on the next edit, never
+    /// by the audio loop.
+    /// # Example
+    /// ```no_run
+    /// # use lion_synth::module::GraphEdit;
+    /// # use lion_synth::prelude::*;
+    /// # let engine = RealTimeEngine::from_yaml("fm.yaml", 44100).unwrap();
+    /// # let handle = engine.control_handle();
+    /// # let lfo = OscillatorBuilder::new().with_frequency(2.0).build().unwrap();
+    /// handle.edit_graph(vec![
+    ///     GraphEdit::AddModule { id: 3, module: Box::new(lfo) },
+    ///     GraphEdit::AddAuxiliary { id: 1, aux: AuxRoute::new(3, "frequency").with_max(440.0) },
+    /// ])?;
+    /// # Ok::<(), lion_synth::module::ControlError>(())
+    /// ```
+    pub fn edit_graph(&self, edits: Vec<GraphEdit>) -> Result<(), ControlError> {
+        collect_garbage(&self.garbage);
+
+        // Held until the chain is sent, so the chains are built in the order they are swapped in
+        let mut shared = self.routing.write().unwrap();
+        let previous = shared.as_ref().ok_or(ControlError::NotEditable)?;
+        let mut routing = previous.clone();
+        let attached = routing.apply(&edits)?;
+        routing.revision = next_revision();
+
+        let mut added = HashMap::new();
+        for edit in &edits {
+            if let GraphEdit::AddModule { id, module } = edit {
+                added.insert(*id, module_slots(module.as_ref()));
+            }
+        }
+        added.retain(|id, _| routing.links.contains_key(id));
+        let table = self
+            .values
+            .read()
+            .unwrap()
+            .rearranged(&routing, &attached, added);
+
+        let rebuild =
+            ChainRebuild::build(previous.revision, &routing, edits, self.sample_rate, &table)?;
+        self.send(ControlMessage::Graph(rebuild))?;
+
+        *self.values.write().unwrap() = Arc::new(table);
+        *shared = Some(routing);
+        Ok(())
+    }
+
+    /// Retrieves the state of the [Transport] at the end of the last tick.
+    pub fn get_transport(&self) -> TransportState {
+        self.transport.load()
+    }
+
+    /// Retrieves the value a parameter had at the end of the last tick, if exists.
+    pub fn get_parameter(&self, module_id: i64, tag: &str) -> Option<f32> {
+        let table = Arc::clone(&self.values.read().unwrap());
+
+        table
+            .attached
+            .get(&module_id)?
+            .iter()
+            .find(|(parameter, _)| parameter == tag)
+            .map(|(_, slot)| f32::from_bits(slot.load(Ordering::Relaxed)))
+    }
+
+    fn send(&self, message: ControlMessage) -> Result<(), ControlError> {
+        match self.sender.try_send(message) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(ControlError::QueueFull),
+            Err(TrySendError::Disconnected(_)) => Err(ControlError::Disconnected),
+        }
+    }
+}
+
+/// A connection for the [Event]s produced by a module to reach another one.
+struct EventRoute {
+    from: i64,
+    to: i64,
+    /// The kinds both produced by the sender and accepted by the receiver.
+    kinds: Vec<EventKind>,
+}
+
+/// An [Event] waiting to be delivered.
+struct PendingEvent {
+    to: i64,
+    /// Samples left until delivery.
+    remaining: u32,
+    event: Event,
+}
+
 pub struct CoordinatorEntity {
     clock: Clock,
+    transport: Transport,
     wrapper_chain: LinkedList<Box<dyn ModuleWrapper>>,
+    control_sender: Sender<ControlMessage>,
+    control_receiver: Receiver<ControlMessage>,
+    parameter_values: Arc<RwLock<Arc<ParameterTable>>>,
+    /// The slots every module of the chain publishes to, in the order of the chain.
+    parameter_slots: Vec<Vec<Slot>>,
+    transport_state: Arc<TransportCell>,
+    event_routes: Vec<EventRoute>,
+    pending_events: Vec<PendingEvent>,
+    routing: Arc<RwLock<Option<Routing>>>,
+    /// Revision of the routing of the chain playing.
+    revision: u64,
+    /// Modules not connected to the output, waiting to be wired again.
+    detached: HashMap<i64, Box<dyn Module>>,
+    garbage_sender: Sender<ChainRebuild>,
+    garbage_receiver: Receiver<ChainRebuild>,
 }
 
 impl CoordinatorEntity {
     pub fn new(sample_rate: i32, chain: LinkedList<Box<dyn ModuleWrapper>>) -> Self {
-        Self {
+        let (control_sender, control_receiver) = crossbeam::channel::bounded(CONTROL_QUEUE_SIZE);
+        let (garbage_sender, garbage_receiver) = crossbeam::channel::bounded(CONTROL_QUEUE_SIZE);
+        let transport = Transport::new(sample_rate);
+
+        let mut coordinator = Self {
             clock: Clock::new(sample_rate),
+            transport_state: Arc::new(TransportCell::new(transport.get_state())),
+            transport,
             wrapper_chain: chain,
-        }
+            control_sender,
+            control_receiver,
+            parameter_values: Arc::new(RwLock::new(Arc::default())),
+            parameter_slots: Vec::new(),
+            event_routes: Vec::new(),
+            pending_events: Vec::new(),
+            routing: Arc::new(RwLock::new(None)),
+            revision: 0,
+            detached: HashMap::new(),
+            garbage_sender,
+            garbage_receiver,
+        };
+
+        coordinator.register_parameters();
+        coordinator
     }
 
     pub fn tick(&mut self) {
+        self.receive_control_messages();
+
+        let time = self.clock.get_time();
+        let transport_state = self.transport.get_state();
+        let routes = &self.event_routes;
+        let pending_events = &mut self.pending_events;
+        let mut tempo = None;
+
         self.wrapper_chain.iter_mut().for_each(|module| {
-            module.gen_sample(self.clock.get_time()).unwrap();
+            let id = module.get_id();
+
+            module.get_mut_module().update_transport(&transport_state);
+            if let Some(id) = id {
+                deliver_events(pending_events, id, module.get_mut_module());
+            }
+
+            module.gen_sample(time).unwrap();
+
+            // Events are collected even if nobody listens, as producing them may change the
+            // state of the module.
+            let events = module.get_mut_module().emit_events(time);
+            tempo = events
+                .iter()
+                .rev()
+                .find_map(|timed| match timed.event {
+                    Event::Tempo(tempo) => Some(tempo),
+                    _ => None,
+                })
+                .or(tempo);
+
+            if let Some(id) = id {
+                route_events(pending_events, routes, id, events);
+            }
         });
 
         // POST OPERATIONS
+        // Tempo changes coming from the modules drive the transport too
+        if let Some(tempo) = tempo {
+            if let Err(msg) = self.transport.set_tempo(tempo) {
+                warn!("<b>Tempo change <yellow>rejected</><b>: {}</>", msg);
+            }
+        }
+
         self.clock.inc();
+        self.transport.advance();
+        self.publish_parameter_values();
+        self.transport_state.publish(&self.transport.get_state());
+        self.pending_events
+            .iter_mut()
+            .for_each(|event| event.remaining = event.remaining.saturating_sub(1));
+    }
+
+    /// Routes the [Event]s produced by a module to another one. Only the kinds of events produced
+    /// by the sender and accepted by the receiver will be delivered.
+    ///
+    /// Events reach the receiver on the same tick they are produced only if the receiver comes
+    /// after the sender in the chain. Otherwise they arrive one sample later.
+    pub fn connect_events(&mut self, from: i64, to: i64) -> Result<(), EventError> {
+        let outputs = self
+            .find_module(from)
+            .ok_or(EventError::ModuleNotFound(from))?
+            .get_event_outputs();
+        let inputs = self
+            .find_module(to)
+            .ok_or(EventError::ModuleNotFound(to))?
+            .get_event_inputs();
+
+        let kinds: Vec<EventKind> = outputs
+            .into_iter()
+            .filter(|kind| inputs.contains(kind))
+            .collect();
+
+        if kinds.is_empty() {
+            return Err(EventError::IncompatibleEvents { from, to });
+        }
+
+        self.event_routes.push(EventRoute { from, to, kinds });
+        Ok(())
+    }
+
+    /// Prepares every module of the chain for processing at the sample rate of the coordinator.
+    /// Modules are fed one sample per tick, so the block size is one.
+    pub fn prepare(&mut self) {
+        let sample_rate = self.clock.get_sample_rate() as i32;
+
+        self.wrapper_chain
+            .iter_mut()
+            .for_each(|wrapper| wrapper.get_mut_module().prepare(sample_rate, 1));
+    }
+
+    /// Takes the coordinator back to the beginning: the clock and the transport position start
+    /// over, pending events are dropped and every module is [reset](fn@Module::reset).
+    pub fn reset(&mut self) {
+        self.clock = Clock::new(self.clock.get_sample_rate() as i32);
+        self.transport.locate(0.0);
+        self.pending_events.clear();
+
+        self.wrapper_chain
+            .iter_mut()
+            .for_each(|wrapper| wrapper.reset());
+        self.transport_state.publish(&self.transport.get_state());
+    }
+
+    /// [Releases](fn@Module::release) every module of the chain. The coordinator must be
+    /// [prepared](fn@CoordinatorEntity::prepare) again before ticking.
+    pub fn release(&mut self) {
+        self.wrapper_chain
+            .iter_mut()
+            .for_each(|wrapper| wrapper.get_mut_module().release());
+        collect_garbage(&self.garbage_receiver);
+    }
+
+    /// Takes over from another coordinator, so this chain can replace the previous one while
+    /// playing without starting over:
+    /// * The clock and the transport position go on from where the previous ones are. The tempo
+    ///   and the time signature of this coordinator are kept.
+    /// * The [ControlHandle]s of the previous coordinator control this one from now on.
+    /// * The modules listed in `unchanged` get the current value of every parameter and the
+    ///   [state](fn@Module::get_state) of the module with the same ID in the previous chain, so
+    ///   changes made while playing are not lost and they go on in phase.
+    pub fn continue_from(&mut self, previous: &mut CoordinatorEntity, unchanged: &[i64]) {
+        let sample_rate = self.clock.get_sample_rate() as i32;
+        self.clock = Clock::new_at(sample_rate, previous.clock.get_sample_pos());
+        self.transport.locate(previous.transport.beat_position);
+        self.transport.playing = previous.transport.playing;
+
+        for id in unchanged {
+            let (values, state) = match previous.find_module(*id) {
+                Some(module) => (module.get_current_parameter_values(), module.get_state()),
+                None => continue,
+            };
+            let wrapper = self
+                .wrapper_chain
+                .iter_mut()
+                .find(|wrapper| wrapper.get_id() == Some(*id));
+
+            if let Some(wrapper) = wrapper {
+                let module = wrapper.get_mut_module();
+                for (tag, value) in values {
+                    if let Some(parameter) = module.get_parameter_mutable(&tag) {
+                        parameter.set(value);
+                    }
+                }
+                module.set_state(&state);
+            }
+        }
+
+        std::mem::swap(&mut self.control_sender, &mut previous.control_sender);
+        std::mem::swap(&mut self.control_receiver, &mut previous.control_receiver);
+        std::mem::swap(&mut self.parameter_values, &mut previous.parameter_values);
+        std::mem::swap(&mut self.transport_state, &mut previous.transport_state);
+        std::mem::swap(&mut self.routing, &mut previous.routing);
+        std::mem::swap(
+            &mut *self.routing.write().unwrap(),
+            &mut *previous.routing.write().unwrap(),
+        );
+        std::mem::swap(&mut self.garbage_sender, &mut previous.garbage_sender);
+        std::mem::swap(&mut self.garbage_receiver, &mut previous.garbage_receiver);
+
+        self.register_parameters();
+        self.transport_state.publish(&self.transport.get_state());
+    }
+
+    /// Creates a new [ControlHandle] for modifying the parameters of the chain while running.
+    pub fn control_handle(&self) -> ControlHandle {
+        ControlHandle {
+            sender: self.control_sender.clone(),
+            values: Arc::clone(&self.parameter_values),
+            transport: Arc::clone(&self.transport_state),
+            routing: Arc::clone(&self.routing),
+            garbage: self.garbage_receiver.clone(),
+            sample_rate: self.clock.get_sample_rate() as i32,
+        }
+    }
+
+    pub fn get_clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn get_transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    pub fn get_mut_transport(&mut self) -> &mut Transport {
+        &mut self.transport
     }
 
     pub fn display_order(&self) {
@@ -261,11 +1368,215 @@ impl CoordinatorEntity {
 
     pub fn add_module(&mut self, wrapper: Box<dyn ModuleWrapper>) {
         self.wrapper_chain.push_back(wrapper);
+        self.register_parameters();
     }
 
     pub fn is_full(&self) -> bool {
         self.wrapper_chain.back().unwrap().get_producer().is_full()
     }
+
+    /// Builds the table read by the [ControlHandle]s again, from every module of the chain and
+    /// the detached ones. Modules without an ID cannot be addressed and are skipped.
+    fn register_parameters(&mut self) {
+        let table = ParameterTable::new(&self.wrapper_chain, self.detached.iter());
+        self.parameter_slots = table.chain_slots(&self.wrapper_chain);
+        *self.parameter_values.write().unwrap() = Arc::new(table);
+    }
+
+    /// Applies every change requested through the [ControlHandle]s since the last tick.
+    fn receive_control_messages(&mut self) {
+        while let Ok(message) = self.control_receiver.try_recv() {
+            match message {
+                ControlMessage::Parameter(change) => self.apply_parameter_change(change),
+                ControlMessage::Event(to, timed) => self.schedule_event(to, timed),
+                ControlMessage::Transport(command) => self.apply_transport_command(command),
+                ControlMessage::Graph(rebuild) => self.swap_chain(rebuild),
+            }
+        }
+    }
+
+    fn apply_parameter_change(&mut self, change: ParameterChange) {
+        let wrapper = self
+            .wrapper_chain
+            .iter_mut()
+            .find(|wrapper| wrapper.get_id() == Some(change.module_id));
+
+        match wrapper {
+            Some(wrapper) => match wrapper.get_mut_module().get_parameter_mutable(&change.tag) {
+                Some(parameter) => parameter.set(change.value),
+                None => {
+                    error!("<b>Parameter tag <red>not found</><b>.</>");
+                    error!("  |_ name: {}", change.tag);
+                }
+            },
+            None => {
+                error!("<b>Module <red>not found</><b> in the chain.</>");
+                error!("  |_ id: {}", change.module_id);
+            }
+        }
+    }
+
+    /// Sets how the modules of the chain are wired, so it can be
+    /// [edited](fn@ControlHandle::edit_graph) while running. Modules not connected to the output
+    /// are handed over as well.
+    pub(crate) fn set_routing(
+        &mut self,
+        mut routing: Routing,
+        detached: HashMap<i64, Box<dyn Module>>,
+    ) {
+        routing.revision = next_revision();
+        self.revision = routing.revision;
+        *self.routing.write().unwrap() = Some(routing);
+        self.detached = detached;
+        self.register_parameters();
+    }
+
+    /// Swaps in a chain rebuilt by a [ControlHandle], moving the modules of the chain playing to
+    /// it. Nothing is allocated nor dropped here: the chain replaced is sent back to the handles.
+    fn swap_chain(&mut self, mut rebuild: ChainRebuild) {
+        if rebuild.base != self.revision {
+            error!("<b>Graph edits <red>discarded</><b>: the chain was replaced since they were sent.</>");
+        } else {
+            for id in &rebuild.moved {
+                let mut module: Box<dyn Module> = Box::new(Vacant);
+                exchange_module(
+                    &mut self.wrapper_chain,
+                    &mut self.detached,
+                    *id,
+                    &mut module,
+                );
+                exchange_module(
+                    &mut rebuild.wrapper_chain,
+                    &mut rebuild.detached,
+                    *id,
+                    &mut module,
+                );
+            }
+            if let (Some(playing), Some(rebuilt)) = (
+                self.wrapper_chain.back_mut(),
+                rebuild.wrapper_chain.back_mut(),
+            ) {
+                std::mem::swap(playing.get_mut_producer(), rebuilt.get_mut_producer());
+            }
+
+            std::mem::swap(&mut self.wrapper_chain, &mut rebuild.wrapper_chain);
+            std::mem::swap(&mut self.detached, &mut rebuild.detached);
+            std::mem::swap(&mut self.parameter_slots, &mut rebuild.parameter_slots);
+            self.revision = rebuild.revision;
+
+            // Forget about the modules no longer there
+            let (chain, detached) = (&self.wrapper_chain, &self.detached);
+            let exists = |id: i64| {
+                detached.contains_key(&id)
+                    || chain.iter().any(|wrapper| wrapper.get_id() == Some(id))
+            };
+            self.event_routes
+                .retain(|route| exists(route.from) && exists(route.to));
+            self.pending_events.retain(|pending| exists(pending.to));
+        }
+
+        if self.garbage_sender.try_send(rebuild).is_err() {
+            warn!("<b>Too many chains replaced <yellow>waiting</><b> to be dropped.</>");
+        }
+    }
+
+    fn apply_transport_command(&mut self, command: TransportCommand) {
+        let result = match command {
+            TransportCommand::Start => {
+                self.transport.start();
+                Ok(())
+            }
+            TransportCommand::Stop => {
+                self.transport.stop();
+                Ok(())
+            }
+            TransportCommand::Locate(position) => {
+                self.transport.locate(position);
+                Ok(())
+            }
+            TransportCommand::SetTempo(tempo) => self.transport.set_tempo(tempo),
+            TransportCommand::SetTimeSignature(signature) => {
+                self.transport.set_time_signature(signature)
+            }
+        };
+
+        if let Err(msg) = result {
+            warn!("<b>Transport command <yellow>rejected</><b>: {}</>", msg);
+        }
+    }
+
+    fn find_module(&self, id: i64) -> Option<&dyn Module> {
+        self.wrapper_chain
+            .iter()
+            .find(|wrapper| wrapper.get_id() == Some(id))
+            .map(|wrapper| wrapper.get_module())
+    }
+
+    /// Schedules an event sent through a [ControlHandle].
+    fn schedule_event(&mut self, to: i64, timed: TimedEvent) {
+        let accepted = match self.find_module(to) {
+            Some(module) => module.get_event_inputs().contains(&timed.event.kind()),
+            None => false,
+        };
+
+        if accepted {
+            self.pending_events.push(PendingEvent {
+                to,
+                remaining: timed.offset,
+                event: timed.event,
+            });
+        } else {
+            warn!("<b>Event <yellow>discarded</><b>.</>");
+            warn!("  |_ event: {:?}", timed.event);
+            warn!("  |_ id: {}", to);
+        }
+    }
+
+    /// Stores the current value of the parameters for the [ControlHandle]s to read.
+    fn publish_parameter_values(&self) {
+        for (wrapper, slots) in self.wrapper_chain.iter().zip(&self.parameter_slots) {
+            if let Some(parameters) = wrapper.get_module().get_parameters() {
+                for (parameter, slot) in parameters.iter().zip(slots) {
+                    slot.store(parameter.get_value().to_bits(), Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+/// Hands the events due for a module over to it, keeping the rest in the queue.
+fn deliver_events(pending_events: &mut Vec<PendingEvent>, id: i64, module: &mut dyn Module) {
+    pending_events.retain(|pending| {
+        if pending.to == id && pending.remaining == 0 {
+            module.handle_event(&pending.event);
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Queues the events produced by a module for every module connected to it.
+fn route_events(
+    pending_events: &mut Vec<PendingEvent>,
+    routes: &[EventRoute],
+    from: i64,
+    events: Vec<TimedEvent>,
+) {
+    for timed in events {
+        let kind = timed.event.kind();
+
+        routes
+            .iter()
+            .filter(|route| route.from == from && route.kinds.contains(&kind))
+            .for_each(|route| {
+                pending_events.push(PendingEvent {
+                    to: route.to,
+                    remaining: timed.offset,
+                    event: timed.event.clone(),
+                })
+            });
+    }
 }
 
 #[cfg(test)]
@@ -300,7 +1611,7 @@ mod tests {
         assert_eq!(test_osc.get_sample(0.0, time), post_chain);
 
         for time in 0..44100 {
-            let time = time as f32;
+            let time = time as f64;
             w1.gen_sample(time).unwrap();
             w2.gen_sample(time).unwrap();
 
@@ -398,4 +1709,497 @@ mod tests {
         for time in 0..44100 {}
         assert_eq!(test_osc.get_sample(0.0, 0.0), final_consumer.pop().unwrap())
     }
+
+    #[test]
+    fn test_control_handle() {
+        let osc = OscillatorBuilder::new().build().unwrap();
+        let pt = PassTrough::new();
+
+        let rb1: HeapRb<f32> = HeapRb::new(10);
+        let rb2: HeapRb<f32> = HeapRb::new(10);
+        let (p1, c1) = rb1.split();
+        let (p2, mut final_consumer) = rb2.split();
+
+        let w1 = GeneratorModuleWrapper::new(Box::new(osc), p1, vec![]).with_id(1);
+        let w2 = LinkerModuleWrapper::new(Box::new(pt), c1, p2, vec![]).with_id(0);
+
+        let mut coordinator = CoordinatorEntity::new(44100, LinkedList::new());
+        coordinator.add_module(Box::new(w1));
+        coordinator.add_module(Box::new(w2));
+
+        let handle = coordinator.control_handle();
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(440.0));
+        assert_eq!(
+            handle.set_parameter(0, "frequency", 220.0),
+            Err(ControlError::UnknownParameter {
+                module_id: 0,
+                tag: "frequency".to_string()
+            })
+        );
+
+        let remote = handle.clone();
+        thread::spawn(move || remote.set_parameter(1, "frequency", 220.0).unwrap())
+            .join()
+            .unwrap();
+
+        // Not applied until the next tick
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(440.0));
+        coordinator.tick();
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(220.0));
+
+        let mut test_osc = OscillatorBuilder::new()
+            .with_frequency(220.0)
+            .build()
+            .unwrap();
+        assert_eq!(test_osc.get_sample(0.0, 0.0), final_consumer.pop().unwrap());
+
+        // The connections of chains built by hand are unknown
+        assert_eq!(
+            handle.edit_graph(vec![GraphEdit::RemoveModule(1)]),
+            Err(ControlError::NotEditable)
+        );
+
+        drop(coordinator);
+        assert_eq!(
+            handle.set_parameter(1, "frequency", 330.0),
+            Err(ControlError::Disconnected)
+        );
+    }
+
+    fn osc_coordinator(frequency: f32) -> (CoordinatorEntity, ModuleConsumer) {
+        let osc = OscillatorBuilder::new()
+            .with_frequency(frequency)
+            .build()
+            .unwrap();
+        let rb: HeapRb<f32> = HeapRb::new(10);
+        let (producer, consumer) = rb.split();
+
+        let mut coordinator = CoordinatorEntity::new(44100, LinkedList::new());
+        coordinator.add_module(Box::new(
+            GeneratorModuleWrapper::new(Box::new(osc), producer, vec![]).with_id(1),
+        ));
+        (coordinator, consumer)
+    }
+
+    #[test]
+    fn test_continue_from() {
+        let (mut previous, mut previous_output) = osc_coordinator(440.0);
+        let handle = previous.control_handle();
+        previous.get_mut_transport().start();
+        handle.set_parameter(1, "amplitude", 0.5).unwrap();
+        for _ in 0..100 {
+            previous.tick();
+            previous_output.pop();
+        }
+
+        let (mut unchanged, mut consumer) = osc_coordinator(440.0);
+        unchanged.continue_from(&mut previous, &[1]);
+        assert_eq!(unchanged.get_clock().get_sample_pos(), 100);
+        assert_eq!(
+            unchanged.get_transport().get_state(),
+            previous.get_transport().get_state()
+        );
+        assert_eq!(handle.get_parameter(1, "amplitude"), Some(0.5));
+
+        // The oscillator goes on from where the previous one is
+        unchanged.tick();
+        let mut test_osc = OscillatorBuilder::new()
+            .with_amplitude(0.5)
+            .build()
+            .unwrap();
+        test_osc.prepare(44100, 1);
+        for sample in 0..100 {
+            test_osc.get_sample(0.0, sample as f64 / 44100.0);
+        }
+        assert_eq!(
+            test_osc.get_sample(0.0, 100.0 / 44100.0),
+            consumer.pop().unwrap()
+        );
+
+        // Handles now reach the new coordinator
+        handle.set_parameter(1, "frequency", 220.0).unwrap();
+        unchanged.tick();
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(220.0));
+
+        let (mut changed, _) = osc_coordinator(330.0);
+        changed.continue_from(&mut unchanged, &[]);
+        assert_eq!(handle.get_parameter(1, "amplitude"), Some(1.0));
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(330.0));
+    }
+
+    /// Emits a note on its first sample and releases it on the third one.
+    struct NoteSource {
+        count: u32,
+    }
+
+    impl Module for NoteSource {
+        fn behavior(&mut self, _in_data: f32, _time: f64) -> f32 {
+            0.0
+        }
+
+        fn get_parameters(&self) -> Option<Vec<&Parameter>> {
+            None
+        }
+
+        fn get_parameters_mutable(&mut self) -> Option<Vec<&mut Parameter>> {
+            None
+        }
+
+        fn get_event_outputs(&self) -> Vec<EventKind> {
+            vec![EventKind::Note]
+        }
+
+        fn emit_events(&mut self, _time: f64) -> Vec<TimedEvent> {
+            self.count += 1;
+
+            match self.count {
+                1 => vec![TimedEvent::now(Event::NoteOn {
+                    note: 57,
+                    velocity: 0.5,
+                })],
+                3 => vec![TimedEvent::now(Event::NoteOff { note: 57 })],
+                _ => vec![],
+            }
+        }
+
+        fn get_name(&self) -> String {
+            "Note source".to_string()
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let source = NoteSource { count: 0 };
+        let osc = OscillatorBuilder::new().build().unwrap();
+
+        let rb1: HeapRb<f32> = HeapRb::new(10);
+        let rb2: HeapRb<f32> = HeapRb::new(10);
+        let (p1, _c1) = rb1.split();
+        let (p2, _c2) = rb2.split();
+
+        let mut coordinator = CoordinatorEntity::new(44100, LinkedList::new());
+        coordinator.add_module(Box::new(
+            GeneratorModuleWrapper::new(Box::new(source), p1, vec![]).with_id(2),
+        ));
+        coordinator.add_module(Box::new(
+            GeneratorModuleWrapper::new(Box::new(osc), p2, vec![]).with_id(1),
+        ));
+
+        assert_eq!(
+            coordinator.connect_events(1, 2),
+            Err(EventError::IncompatibleEvents { from: 1, to: 2 })
+        );
+        assert_eq!(
+            coordinator.connect_events(2, 3),
+            Err(EventError::ModuleNotFound(3))
+        );
+        coordinator.connect_events(2, 1).unwrap();
+
+        let handle = coordinator.control_handle();
+
+        coordinator.tick();
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(220.0));
+        assert_eq!(handle.get_parameter(1, "amplitude"), Some(0.5));
+
+        coordinator.tick();
+        coordinator.tick();
+        assert_eq!(handle.get_parameter(1, "amplitude"), Some(0.0));
+
+        // EXTERNAL EVENTS
+        handle
+            .send_event(
+                1,
+                TimedEvent::new(
+                    2,
+                    Event::NoteOn {
+                        note: 69,
+                        velocity: 1.0,
+                    },
+                ),
+            )
+            .unwrap();
+        assert_eq!(
+            handle.send_event(5, TimedEvent::now(Event::Trigger)),
+            Err(ControlError::UnknownModule(5))
+        );
+
+        coordinator.tick();
+        coordinator.tick();
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(220.0));
+        coordinator.tick();
+        assert_eq!(handle.get_parameter(1, "frequency"), Some(440.0));
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut clock = Clock::new(44100);
+
+        for _ in 0..44100 {
+            clock.inc();
+        }
+        assert_eq!(clock.get_sample_pos(), 44100, "Clock wrapped around");
+        assert_eq!(clock.get_time(), 1.0);
+
+        // Ten days of audio
+        let start = 44100 * 60 * 60 * 24 * 10;
+        let mut clock = Clock::new_at(44100, start);
+        assert_eq!(clock.inc(), 864000.0);
+        assert_eq!(clock.post_inc(), (start + 2) as f64 / 44100.0);
+        assert_eq!(clock.get_sample_pos(), start + 2);
+    }
+
+    #[test]
+    fn test_transport() {
+        // 120 BPM at 8 Hz: four samples per beat
+        let mut transport = Transport::new(8);
+        transport
+            .set_time_signature(TimeSignature {
+                beats_per_bar: 3,
+                beat_unit: 4,
+            })
+            .unwrap();
+
+        transport.advance();
+        assert_eq!(
+            transport.get_state().beat_position,
+            0.0,
+            "Stopped transport moved"
+        );
+
+        transport.start();
+        for _ in 0..14 {
+            transport.advance();
+        }
+
+        let state = transport.get_state();
+        assert_eq!(state.beat_position, 3.5);
+        assert_eq!(
+            (state.bar, state.beat, state.tick),
+            (2, 1, TICKS_PER_BEAT / 2)
+        );
+        assert_eq!(state.beat_phase(), 0.5);
+
+        assert!(transport.set_tempo(0.0).is_err());
+        assert!(transport.set_tempo(f32::NAN).is_err());
+        assert!(transport
+            .set_time_signature(TimeSignature {
+                beats_per_bar: 4,
+                beat_unit: 3
+            })
+            .is_err());
+        assert_eq!(transport.get_tempo(), 120.0);
+    }
+
+    #[test]
+    fn test_transport_cell() {
+        let state = |n: u32| TransportState {
+            playing: n.is_multiple_of(2),
+            tempo: n as f32,
+            time_signature: TimeSignature {
+                beats_per_bar: n,
+                beat_unit: n,
+            },
+            beat_position: n as f64,
+            bar: n,
+            beat: n,
+            tick: n,
+        };
+        let cell = Arc::new(TransportCell::new(state(0)));
+        assert_eq!(cell.load(), state(0));
+
+        // Readers never see a state half written
+        let writer = Arc::clone(&cell);
+        let writer = thread::spawn(move || (1..100_000).for_each(|n| writer.publish(&state(n))));
+        while !writer.is_finished() {
+            let read = cell.load();
+            assert_eq!(read, state(read.bar));
+        }
+        writer.join().unwrap();
+        assert_eq!(cell.load(), state(99_999));
+    }
+
+    #[test]
+    fn test_transport_commands() {
+        let osc = OscillatorBuilder::new().build().unwrap();
+        let rb: HeapRb<f32> = HeapRb::new(10);
+        let (p, _c) = rb.split();
+
+        let mut coordinator = CoordinatorEntity::new(8, LinkedList::new());
+        coordinator.add_module(Box::new(
+            GeneratorModuleWrapper::new(Box::new(osc), p, vec![]).with_id(0),
+        ));
+        let handle = coordinator.control_handle();
+
+        handle
+            .send_transport(TransportCommand::SetTempo(240.0))
+            .unwrap();
+        handle.send_transport(TransportCommand::Start).unwrap();
+        coordinator.tick();
+
+        let state = handle.get_transport();
+        assert!(state.playing);
+        assert_eq!(state.tempo, 240.0);
+        assert_eq!(state.beat_position, 0.5);
+
+        handle.send_transport(TransportCommand::Stop).unwrap();
+        handle
+            .send_transport(TransportCommand::Locate(4.0))
+            .unwrap();
+        coordinator.tick();
+
+        let state = handle.get_transport();
+        assert!(!state.playing);
+        assert_eq!((state.bar, state.beat), (2, 1));
+    }
+
+    /// Keeps track of the lifecycle calls it receives.
+    struct LifecycleProbe {
+        calls: Arc<RwLock<Vec<String>>>,
+    }
+
+    impl Module for LifecycleProbe {
+        fn behavior(&mut self, _in_data: f32, time: f64) -> f32 {
+            time as f32
+        }
+
+        fn get_parameters(&self) -> Option<Vec<&Parameter>> {
+            None
+        }
+
+        fn get_parameters_mutable(&mut self) -> Option<Vec<&mut Parameter>> {
+            None
+        }
+
+        fn prepare(&mut self, sample_rate: i32, max_block: usize) {
+            let call = format!("prepare {} {}", sample_rate, max_block);
+            self.calls.write().unwrap().push(call);
+        }
+
+        fn reset(&mut self) {
+            self.calls.write().unwrap().push("reset".to_string());
+        }
+
+        fn release(&mut self) {
+            self.calls.write().unwrap().push("release".to_string());
+        }
+
+        fn get_name(&self) -> String {
+            "Lifecycle probe".to_string()
+        }
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let calls = Arc::new(RwLock::new(Vec::new()));
+        let probe = LifecycleProbe {
+            calls: Arc::clone(&calls),
+        };
+
+        let rb1: HeapRb<f32> = HeapRb::new(10);
+        let rb2: HeapRb<f32> = HeapRb::new(10);
+        let (p1, c1) = rb1.split();
+        let (p2, mut final_consumer) = rb2.split();
+
+        let mut coordinator = CoordinatorEntity::new(48000, LinkedList::new());
+        coordinator.add_module(Box::new(GeneratorModuleWrapper::new(
+            Box::new(probe),
+            p1,
+            vec![],
+        )));
+        coordinator.add_module(Box::new(LinkerModuleWrapper::new(
+            Box::new(PassTrough::new()),
+            c1,
+            p2,
+            vec![],
+        )));
+
+        coordinator.prepare();
+        coordinator.get_mut_transport().start();
+        for _ in 0..3 {
+            coordinator.tick();
+        }
+        assert_eq!(coordinator.get_clock().get_sample_pos(), 3);
+
+        coordinator.reset();
+        assert_eq!(coordinator.get_clock().get_sample_pos(), 0);
+        assert_eq!(coordinator.get_transport().get_state().beat_position, 0.0);
+
+        final_consumer.clear();
+        coordinator.tick();
+        assert_eq!(final_consumer.pop(), Some(0.0), "Time did not start over");
+
+        coordinator.release();
+        assert_eq!(
+            *calls.read().unwrap(),
+            vec!["prepare 48000 1", "reset", "release"]
+        );
+
+        // Filling a buffer always starts from the beginning
+        let mut probe = LifecycleProbe {
+            calls: Arc::clone(&calls),
+        };
+        probe.fill_buffer(&mut vec![0.0; 4], 48000, vec![]);
+        assert_eq!(calls.read().unwrap().last().unwrap(), "reset");
+    }
+
+    #[test]
+    fn test_swap_chain() {
+        let (mut coordinator, mut consumer) = osc_coordinator(440.0);
+        let (mut reference, mut expected) = osc_coordinator(440.0);
+        let routing = Routing {
+            links: HashMap::from([(1, ChainLinks::default())]),
+            output: 1,
+            ..Default::default()
+        };
+        coordinator.set_routing(routing.clone(), HashMap::new());
+        let handle = coordinator.control_handle();
+
+        // Modules added are prepared before reaching the audio loop
+        let calls = Arc::new(RwLock::new(Vec::new()));
+        let probe = LifecycleProbe {
+            calls: Arc::clone(&calls),
+        };
+        let add = GraphEdit::AddModule {
+            id: 2,
+            module: Box::new(probe),
+        };
+        handle.edit_graph(vec![add]).unwrap();
+        assert_eq!(*calls.read().unwrap(), vec!["prepare 44100 1"]);
+
+        // The chain delivers to the same consumer
+        for _ in 0..5 {
+            coordinator.tick();
+            reference.tick();
+            assert_eq!(consumer.pop(), expected.pop());
+        }
+
+        // Modules removed are released outside the audio loop, on the next edit
+        handle.edit_graph(vec![GraphEdit::RemoveModule(2)]).unwrap();
+        coordinator.tick();
+        assert_eq!(calls.read().unwrap().len(), 1);
+        handle
+            .edit_graph(vec![GraphEdit::SetInput { id: 1, from: None }])
+            .unwrap();
+        assert_eq!(*calls.read().unwrap(), vec!["prepare 44100 1", "release"]);
+
+        // Chains built for a routing replaced in the meantime are discarded
+        let add = GraphEdit::AddModule {
+            id: 3,
+            module: Box::new(PassTrough::new()),
+        };
+        handle
+            .edit_graph(vec![
+                add,
+                GraphEdit::SetInput {
+                    id: 1,
+                    from: Some(3),
+                },
+            ])
+            .unwrap();
+        coordinator.set_routing(routing, HashMap::new());
+        coordinator.tick();
+        reference.tick();
+        assert_eq!(consumer.pop(), expected.pop());
+        assert_eq!(coordinator.wrapper_chain.len(), 1);
+    }
 }
@@ -0,0 +1,421 @@
+use crate::bundled_modules::debug::*;
+use crate::bundled_modules::prelude::Sum3InBuilder;
+use crate::bundled_modules::*;
+use crate::document::{Map, Value};
+use crate::layout_yaml::YamlParsingError;
+use crate::module::Module;
+use simplelog::{error, info, warn};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+
+/// A function creating a module from the `config` node of a layout. It also receives the
+/// [FactoryContext] of the module, with its ID and the folder of the layout.
+pub type ModuleFactory =
+    Box<dyn Fn(&Value, &FactoryContext) -> Result<Box<dyn Module>, YamlParsingError> + Send + Sync>;
+
+/// Information about where a module is being created, passed to every [ModuleFactory].
+#[derive(Debug, Clone, Copy)]
+pub struct FactoryContext<'a> {
+    /// The ID of the module in the layout, handy for reporting errors.
+    pub module_id: i64,
+    /// The folder relative paths of the layout are resolved from, if any.
+    pub base_dir: Option<&'a Path>,
+}
+
+impl FactoryContext<'_> {
+    /// A context for a module with no layout folder, so paths are resolved from the working
+    /// directory.
+    pub fn new(module_id: i64) -> Self {
+        Self {
+            module_id,
+            base_dir: None,
+        }
+    }
+
+    /// Resolves a path found in the config of the module, such as the one of a sample or a
+    /// sub-patch. Relative paths are joined to the folder of the layout.
+    pub fn resolve_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        resolve_path(self.base_dir, path.as_ref())
+    }
+}
+
+pub(crate) fn resolve_path(base_dir: Option<&Path>, path: &Path) -> PathBuf {
+    match base_dir {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// The **module registry** maps the `type` field of the modules in a layout to the function
+/// creating them, so the loader knows how to build every module.
+///
+/// The [default](fn@ModuleRegistry::default) registry comes with every bundled module. To use your
+/// own [Module] implementations in a layout, register them before loading it.
+/// # Example
+/// ```no_run
+/// # use lion_synth::prelude::*;
+/// # use lion_synth::buffer_from_yaml_with_registry;
+/// # struct MyModule {
+/// #     gain: f32,
+/// # }
+/// # impl MyModule {
+/// #     fn new(gain: f32) -> Self {
+/// #         Self { gain }
+/// #     }
+/// # }
+/// # impl Module for MyModule {
+/// #     fn behavior(&mut self, in_data: f32, _time: f64) -> f32 {
+/// #         in_data * self.gain
+/// #     }
+/// #     fn get_parameters(&self) -> Option<Vec<&Parameter>> {
+/// #         None
+/// #     }
+/// #     fn get_parameters_mutable(&mut self) -> Option<Vec<&mut Parameter>> {
+/// #         None
+/// #     }
+/// #     fn get_name(&self) -> String {
+/// #         String::from("my_module")
+/// #     }
+/// # }
+/// let mut registry = ModuleRegistry::default();
+///
+/// registry.register("my_module", |config, _context| {
+///     let gain = config["gain"].as_f64().unwrap_or(1.0) as f32;
+///     Ok(Box::new(MyModule::new(gain)))
+/// });
+///
+/// let buffer = buffer_from_yaml_with_registry("my_layout.yaml", 44100, 44100, &registry);
+/// ```
+pub struct ModuleRegistry {
+    factories: HashMap<String, ModuleFactory>,
+}
+
+impl ModuleRegistry {
+    /// Creates a registry with no module type at all. Use [`default`](fn@ModuleRegistry::default)
+    /// for a registry with the bundled modules.
+    pub fn new() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Adds a module type to the registry. If the type was already registered, the new factory
+    /// replaces the previous one, which also allows overriding the bundled modules.
+    pub fn register<F>(&mut self, type_name: &str, factory: F)
+    where
+        F: Fn(&Value, &FactoryContext) -> Result<Box<dyn Module>, YamlParsingError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.factories
+            .insert(type_name.to_string(), Box::new(factory));
+    }
+
+    pub fn contains(&self, type_name: &str) -> bool {
+        self.factories.contains_key(type_name)
+    }
+
+    /// Every type name registered, in alphabetical order.
+    pub fn get_type_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.factories.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Creates a module of the given type.
+    /// # Arguments
+    /// * `type_name` - The `type` field of the module in the layout.
+    /// * `config` - The `config` node of the module. May be null if not present.
+    /// * `context` - The ID of the module and the folder of its layout.
+    pub fn create(
+        &self,
+        type_name: &str,
+        config: &Value,
+        context: &FactoryContext,
+    ) -> Result<Box<dyn Module>, YamlParsingError> {
+        match self.factories.get(type_name) {
+            Some(factory) => factory(config, context),
+            None => {
+                error!(
+                    "<b>Module type <red>not known</><b>. ID: {}.</>",
+                    context.module_id
+                );
+                Err(YamlParsingError::UnknownType(type_name.to_string()))
+            }
+        }
+    }
+}
+
+impl Default for ModuleRegistry {
+    /// A registry with every bundled module.
+    fn default() -> Self {
+        let mut registry = Self::new();
+
+        registry.register("oscillator", oscillator_from_yaml);
+        registry.register("sum", sum_from_yaml);
+        registry.register("osc_debug", |_, _| Ok(Box::new(OscDebug::new())));
+        registry.register("pass_through", |_, _| Ok(Box::new(PassTrough::new())));
+
+        registry
+    }
+}
+
+/// Builds the `config` node of a module, leaving out the fields with no value.
+pub(crate) fn config_node(fields: Vec<(&str, Option<Value>)>) -> Value {
+    let mut config = Map::new();
+    for (key, value) in fields {
+        if let Some(value) = value {
+            config.insert(key, value);
+        }
+    }
+
+    Value::Map(config)
+}
+
+/// A float written so the loader reads it back as the very same value.
+pub(crate) fn real_node(value: f32) -> Value {
+    Value::Real(format!("{:?}", value).parse().unwrap_or(value as f64))
+}
+
+/// The name given in the layout, without the suffix added by the builder of the module.
+pub(crate) fn name_node(name: &str, suffix: &str) -> Option<Value> {
+    name.strip_suffix(suffix)
+        .filter(|name| !name.is_empty())
+        .map(|name| Value::String(name.to_string()))
+}
+
+/// The names the `wave` of an oscillator accepts.
+const WAVE_SHAPES: &str = "sin, sine, tri, triangle, saw, sqr, square, pulse";
+
+fn oscillator_from_yaml(
+    config: &Value,
+    context: &FactoryContext,
+) -> Result<Box<dyn Module>, YamlParsingError> {
+    if config.is_null() {
+        info!("No configuration found for oscillator");
+        return Ok(Box::new(
+            OscillatorBuilder::new()
+                .build()
+                .map_err(YamlParsingError::Builder)?,
+        ));
+    }
+
+    let name = config["name"].as_str();
+    let amp = config["amplitude"].as_f64();
+    let freq = config["frequency"].as_f64();
+    let phase = config["phase"].as_f64();
+    let pwd = config["pwd"].as_f64();
+
+    let wave = match &config["wave"] {
+        Value::Missing => None,
+        yaml => Some(match yaml.as_str() {
+            Some("sin" | "sine") => WaveShape::Sine,
+            Some("tri" | "triangle") => WaveShape::Triangle,
+            Some("saw") => WaveShape::Saw,
+            Some("sqr" | "square") => WaveShape::Square,
+            Some("pulse") => WaveShape::Pulse(pwd.map_or(PI, |x| x as f32)),
+            _ => {
+                error!(
+                    "<b>Unknown <red>wave</> <b>for oscillator. ID: {}.</>",
+                    context.module_id
+                );
+                return Err(YamlParsingError::WrongFormat {
+                    field_name: String::from("wave"),
+                    supported_format: String::from(WAVE_SHAPES),
+                });
+            }
+        }),
+    };
+
+    Ok(Box::new(
+        OscillatorBuilder::with_all_yaml_fmt(name, amp, freq, phase, wave, pwd)
+            .build()
+            .map_err(YamlParsingError::Builder)?,
+    ))
+}
+
+fn sum_from_yaml(
+    config: &Value,
+    context: &FactoryContext,
+) -> Result<Box<dyn Module>, YamlParsingError> {
+    use YamlParsingError::*;
+
+    let module_id = context.module_id;
+
+    let name = config["name"].as_str();
+    let input_amount = match config["input-amount"].as_i64() {
+        Some(input_amount) => input_amount,
+        None => {
+            error!(
+                "<b>Invalid format or no <red>input amount</> <b>provided for sum module. ID: {}.</>",
+                module_id
+            );
+            return Err(MissingField(String::from("input-amount")));
+        }
+    };
+
+    let out_gain = &config["out-gain"];
+    let in_1_gain = &config["in-1"];
+    let in_2_gain = &config["in-2"];
+    let in_3_gain = &config["in-3"];
+
+    let items: Vec<Option<f64>> = [out_gain, in_1_gain, in_2_gain, in_3_gain]
+        .into_iter()
+        .map(|value| match value {
+            Value::Real(x) => Some(*x),
+            Value::Integer(x) => Some(*x as f64),
+            _ => None,
+        })
+        .collect();
+    let (out_gain, in_1_gain, in_2_gain, in_3_gain) = (items[0], items[1], items[2], items[3]);
+
+    if input_amount <= 1 {
+        error!("<b><redInvalid amount</> <b>of inputs declared</>");
+        error!("  |_ id: {}", module_id);
+        Err(InvalidInputAmount(input_amount))
+    } else if input_amount == 2 {
+        Ok(Box::new(
+            Sum2InBuilder::with_all_yaml(name, out_gain, in_1_gain, in_2_gain)
+                .build()
+                .map_err(YamlParsingError::Builder)?,
+        ))
+    } else if input_amount == 3 {
+        Ok(Box::new(
+            Sum3InBuilder::with_all_yaml(name, out_gain, in_1_gain, in_2_gain, in_3_gain)
+                .build()
+                .map_err(YamlParsingError::Builder)?,
+        ))
+    } else {
+        if in_1_gain.is_some() || in_2_gain.is_some() || in_3_gain.is_some() {
+            warn!("<b>For sum modules with a size greater than 3 is <yellow>not possible to specify the input gain</> <b>for each input. Instead, you have to specify it in the module itself.</>");
+            warn!("  * found in module with id: {}", module_id);
+        }
+
+        Ok(Box::new(
+            VarSumBuilder::with_all_yaml(name, input_amount, out_gain)
+                .build()
+                .map_err(YamlParsingError::Builder)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Format;
+    use crate::module::Parameter;
+
+    struct Gain {
+        gain: f32,
+    }
+
+    impl Module for Gain {
+        fn behavior(&mut self, in_data: f32, _time: f64) -> f32 {
+            in_data * self.gain
+        }
+
+        fn get_parameters(&self) -> Option<Vec<&Parameter>> {
+            None
+        }
+
+        fn get_parameters_mutable(&mut self) -> Option<Vec<&mut Parameter>> {
+            None
+        }
+
+        fn get_name(&self) -> String {
+            "Gain".to_string()
+        }
+    }
+
+    fn get_config(text: &str) -> Value {
+        Format::Yaml.parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_default() {
+        let registry = ModuleRegistry::default();
+
+        assert_eq!(
+            registry.get_type_names(),
+            vec!["osc_debug", "oscillator", "pass_through", "sum"]
+        );
+
+        let osc = registry
+            .create(
+                "oscillator",
+                &get_config("frequency: 220.0"),
+                &FactoryContext::new(0),
+            )
+            .unwrap();
+        assert_eq!(osc.get_parameter("frequency").unwrap().get_value(), 220.0);
+
+        // Integers are read as reals, as JSON and TOML documents often write them
+        let config = Format::Json.parse("{\"frequency\": 220}").unwrap();
+        let osc = registry
+            .create("oscillator", &config, &FactoryContext::new(0))
+            .unwrap();
+        assert_eq!(osc.get_parameter("frequency").unwrap().get_value(), 220.0);
+
+        // An unknown wave is an error rather than the default shape
+        let osc = registry.create(
+            "oscillator",
+            &get_config("wave: sinus"),
+            &FactoryContext::new(0),
+        );
+        assert!(matches!(
+            osc,
+            Err(YamlParsingError::WrongFormat { field_name, .. }) if field_name == "wave"
+        ));
+
+        let sum = registry.create(
+            "sum",
+            &get_config("input-amount: 1"),
+            &FactoryContext::new(3),
+        );
+        assert!(matches!(sum, Err(YamlParsingError::InvalidInputAmount(1))));
+    }
+
+    #[test]
+    fn test_custom_module() {
+        let mut registry = ModuleRegistry::default();
+
+        assert!(matches!(
+            registry.create("gain", &Value::Null, &FactoryContext::new(0)),
+            Err(YamlParsingError::UnknownType(_))
+        ));
+
+        registry.register("gain", |config, _| {
+            let gain = config["gain"].as_f64().unwrap_or(1.0) as f32;
+            Ok(Box::new(Gain { gain }))
+        });
+        assert!(registry.contains("gain"));
+
+        let mut gain = registry
+            .create("gain", &get_config("gain: 0.5"), &FactoryContext::new(0))
+            .unwrap();
+        assert_eq!(gain.get_sample(1.0, 0.0), 0.5);
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let context = FactoryContext {
+            module_id: 0,
+            base_dir: Some(Path::new("patches/drums")),
+        };
+        assert_eq!(
+            context.resolve_path("kick.wav"),
+            PathBuf::from("patches/drums/kick.wav")
+        );
+        assert_eq!(
+            context.resolve_path("/samples/kick.wav"),
+            PathBuf::from("/samples/kick.wav")
+        );
+
+        assert_eq!(
+            FactoryContext::new(0).resolve_path("kick.wav"),
+            PathBuf::from("kick.wav")
+        );
+    }
+}
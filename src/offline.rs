@@ -0,0 +1,539 @@
+//! Renders patches offline, a block at a time.
+//!
+//! [`Layout::render`](fn@crate::Layout::render) renders every module to a buffer as long as the
+//! whole signal. The [OfflineRenderer] goes through the same modules block after block instead,
+//! so long renders are streamed to a wav file holding a single block of every module in memory.
+//! The samples are the same as the ones of the full render, bit for bit.
+//! # Example
+//! ```no_run
+//! use lion_synth::{Layout, ModuleRegistry, OfflineRenderer, WavExport};
+//!
+//! let layout = Layout::from_path("layouts/fm.yaml").unwrap();
+//! let mut renderer = OfflineRenderer::from_layout(&layout, 44100, &ModuleRegistry::default())
+//!     .unwrap()
+//!     .with_progress(|progress| println!("{:.0}%", 100.0 * progress.fraction()));
+//! // Ten minutes
+//! renderer
+//!     .render_to_wav(600 * 44100, "exports/fm.wav", &WavExport::default())
+//!     .unwrap();
+//! ```
+
+use crate::layout_yaml::{aux_error, take_cell, Layout, YamlParsingError};
+use crate::module::{
+    advise_custom_fill, AuxDataHolder, AuxInputBuilder, AuxiliaryInput, Module, Transport,
+};
+use crate::module_registry::ModuleRegistry;
+use crate::patch::{AuxRoute, ChainCell, Patch};
+use crate::wav_export::{ExportError, ExportReport, WavExport};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Default amount of frames rendered at once.
+pub const DEFAULT_RENDER_BLOCK: usize = 8192;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RenderError {
+    #[error(transparent)]
+    Layout(#[from] YamlParsingError),
+    #[error(transparent)]
+    Export(#[from] ExportError),
+    #[error("The render was cancelled")]
+    Cancelled,
+}
+
+/// How far a render went, given to the callback set with
+/// [with_progress](OfflineRenderer::with_progress) after every block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderProgress {
+    /// Frames rendered in the current pass.
+    pub rendered: u64,
+    pub total: u64,
+    /// Current pass, starting at 1. Normalised renders take two passes: the first one measures
+    /// the signal and the second one writes it.
+    pub pass: usize,
+    pub passes: usize,
+}
+
+impl RenderProgress {
+    /// Part of the whole render done, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        let done = (self.pass - 1) as f64 + self.rendered as f64 / self.total as f64;
+        done / self.passes as f64
+    }
+}
+
+/// Renders a patch block by block. See the [module documentation](self).
+pub struct OfflineRenderer {
+    root: RenderNode,
+    sample_rate: i32,
+    block_size: usize,
+    position: u64,
+    prepared: bool,
+    /// Whether the advice on filling buffers was given in the current render.
+    advised: bool,
+    progress: Option<Box<dyn FnMut(RenderProgress) + Send>>,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl OfflineRenderer {
+    /// Builds the renderer from a [Patch]. The modules are moved into the renderer.
+    pub fn from_patch(mut patch: Patch, sample_rate: i32) -> Result<Self, YamlParsingError> {
+        let output = patch.check_links()?;
+        let transport = patch.offline_transport(sample_rate)?;
+        let root = RenderNode::new(&mut patch.modules, &mut HashSet::new(), output, &transport)?;
+
+        Ok(Self {
+            root,
+            sample_rate,
+            block_size: DEFAULT_RENDER_BLOCK,
+            position: 0,
+            prepared: false,
+            advised: false,
+            progress: None,
+            cancel: None,
+        })
+    }
+
+    pub fn from_layout(
+        layout: &Layout,
+        sample_rate: i32,
+        registry: &ModuleRegistry,
+    ) -> Result<Self, YamlParsingError> {
+        Self::from_patch(layout.to_patch(registry)?, sample_rate)
+    }
+
+    /// Sets the amount of frames rendered at once by [render_to_wav](Self::render_to_wav).
+    pub fn with_block_size(mut self, frames: usize) -> Self {
+        self.block_size = frames.max(1);
+        self
+    }
+
+    /// Calls the given function after every block written by [render_to_wav](Self::render_to_wav).
+    pub fn with_progress(mut self, progress: impl FnMut(RenderProgress) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Stops [render_to_wav](Self::render_to_wav) as soon as the flag is raised, such as from
+    /// another thread.
+    pub fn with_cancel_flag(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn get_sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+
+    /// Frames rendered since the beginning.
+    pub fn get_position(&self) -> u64 {
+        self.position
+    }
+
+    /// Renders the next `frames` samples of the output. The first block starts from the beginning,
+    /// resetting every module.
+    pub fn render_block(&mut self, frames: usize) -> Result<Vec<f32>, YamlParsingError> {
+        if !self.prepared {
+            self.root
+                .prepare(self.sample_rate, frames.max(self.block_size));
+            self.prepared = true;
+        }
+        if !self.advised {
+            advise_custom_fill();
+            self.advised = true;
+        }
+
+        let block = self.root.render(frames, self.position, self.sample_rate)?;
+        self.position += frames as u64;
+        Ok(block)
+    }
+
+    /// Starts again from the beginning: the next block rendered resets every module.
+    pub fn restart(&mut self) {
+        self.position = 0;
+        self.advised = false;
+    }
+
+    /// Frees the resources of the modules. They are prepared again if more blocks are rendered.
+    pub fn release(&mut self) {
+        if self.prepared {
+            self.root.release();
+            self.prepared = false;
+        }
+    }
+
+    /// Renders `frames` samples from the beginning into a wav file. The file is the same as the
+    /// one [WavExport::write] writes from the full render. Normalised exports render the patch
+    /// twice, the first time to measure it. If the render fails or is cancelled, the incomplete
+    /// file is removed.
+    pub fn render_to_wav(
+        &mut self,
+        frames: u64,
+        path: impl AsRef<Path>,
+        export: &WavExport,
+    ) -> Result<ExportReport, RenderError> {
+        let path = path.as_ref();
+        self.restart();
+        let passes = match export.get_normalization() {
+            Some(_) => 2,
+            None => 1,
+        };
+
+        let mut gain = 1.0;
+        if passes == 2 {
+            let mut meter = export.meter(1, self.sample_rate);
+            self.render_pass(frames, 1, passes, |block| {
+                meter.push(block);
+                Ok(())
+            })?;
+            gain = meter.gain();
+        }
+
+        let mut encoder = export.encoder(path, self.sample_rate, gain)?;
+        let result = self.render_pass(frames, passes, passes, |block| {
+            encoder.write_mono(block).map_err(RenderError::from)
+        });
+        self.release();
+
+        match result {
+            Ok(()) => Ok(encoder.finalize()?),
+            Err(err) => {
+                drop(encoder);
+                let _ = fs::remove_file(path);
+                Err(err)
+            }
+        }
+    }
+
+    fn render_pass(
+        &mut self,
+        frames: u64,
+        pass: usize,
+        passes: usize,
+        mut write: impl FnMut(&[f32]) -> Result<(), RenderError>,
+    ) -> Result<(), RenderError> {
+        // Every pass is part of the same render
+        self.position = 0;
+
+        while self.position < frames {
+            if let Some(cancel) = &self.cancel {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(RenderError::Cancelled);
+                }
+            }
+
+            let length = (frames - self.position).min(self.block_size as u64) as usize;
+            let block = self.render_block(length)?;
+            write(&block)?;
+
+            if let Some(progress) = &mut self.progress {
+                progress(RenderProgress {
+                    rendered: self.position,
+                    total: frames,
+                    pass,
+                    passes,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for OfflineRenderer {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// A module with the ones feeding it, rendered before it on every block.
+struct RenderNode {
+    module: Box<dyn Module>,
+    input: Option<Box<RenderNode>>,
+    auxiliaries: Vec<(AuxRoute, RenderNode)>,
+    id: i64,
+    /// The transport the module plays along, going on from block to block.
+    transport: Transport,
+}
+
+impl RenderNode {
+    /// Takes the module at `id` and every module feeding it out of `modules`. Every module plays
+    /// along its own copy of `transport`.
+    fn new(
+        modules: &mut HashMap<i64, ChainCell>,
+        taken: &mut HashSet<i64>,
+        id: i64,
+        transport: &Transport,
+    ) -> Result<Self, YamlParsingError> {
+        let cell = take_cell(modules, taken, id)?;
+
+        let mut auxiliaries = Vec::new();
+        for aux in cell.auxiliaries {
+            let node = RenderNode::new(modules, taken, aux.from_module, transport)?;
+            auxiliaries.push((aux, node));
+        }
+        let input = match cell.from_module {
+            Some(from) => Some(Box::new(RenderNode::new(modules, taken, from, transport)?)),
+            None => None,
+        };
+
+        Ok(Self {
+            module: cell.module,
+            input,
+            auxiliaries,
+            id,
+            transport: transport.clone(),
+        })
+    }
+
+    fn prepare(&mut self, sample_rate: i32, max_block: usize) {
+        self.module.prepare(sample_rate, max_block);
+        self.nodes()
+            .for_each(|node| node.prepare(sample_rate, max_block));
+    }
+
+    fn release(&mut self) {
+        self.module.release();
+        self.nodes().for_each(RenderNode::release);
+    }
+
+    fn nodes(&mut self) -> impl Iterator<Item = &mut RenderNode> {
+        self.auxiliaries
+            .iter_mut()
+            .map(|(_, node)| node)
+            .chain(self.input.as_deref_mut())
+    }
+
+    /// Renders a block of the module, in the same order as the full render.
+    fn render(
+        &mut self,
+        frames: usize,
+        position: u64,
+        sample_rate: i32,
+    ) -> Result<Vec<f32>, YamlParsingError> {
+        let mut aux_list: Vec<AuxiliaryInput> = Vec::new();
+        for (route, node) in self.auxiliaries.iter_mut() {
+            let aux_buffer = node.render(frames, position, sample_rate)?;
+            let aux = AuxInputBuilder::new(&route.linked_with, AuxDataHolder::Batch(aux_buffer))
+                .with_all_yaml(route.max, route.min)
+                .build()
+                .map_err(|err| aux_error(self.id, err))?;
+            aux_list.push(aux);
+        }
+
+        let mut buffer = match &mut self.input {
+            Some(input) => input.render(frames, position, sample_rate)?,
+            None => vec![0.0f32; frames],
+        };
+
+        // The first block starts from a clean state, as the full render does
+        if position == 0 {
+            self.module.reset();
+            self.transport.locate(0.0);
+        }
+        self.module.fill_buffer_with_transport(
+            &mut buffer,
+            position,
+            sample_rate,
+            aux_list,
+            &mut self.transport,
+        );
+
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav_export::{Normalization, WavFormat};
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    const FRAMES: usize = 10000;
+
+    fn renderer(file: &str) -> OfflineRenderer {
+        let layout = Layout::from_path(format!("layouts/{}", file)).unwrap();
+        OfflineRenderer::from_layout(&layout, 44100, &ModuleRegistry::default()).unwrap()
+    }
+
+    fn full_render(file: &str) -> Vec<f32> {
+        let layout = Layout::from_path(format!("layouts/{}", file)).unwrap();
+        layout.render(FRAMES, 44100).unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("lion_synth_{}_{}.wav", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_same_as_full_render() {
+        for file in ["fm.yaml", "poli4.yaml", "poli4phased.yaml"] {
+            let expected = full_render(file);
+
+            // Blocks of any size, even uneven ones
+            for block_size in [1000, 777, FRAMES] {
+                let mut renderer = renderer(file);
+                let mut rendered = Vec::new();
+                while rendered.len() < FRAMES {
+                    let length = (FRAMES - rendered.len()).min(block_size);
+                    rendered.extend(renderer.render_block(length).unwrap());
+                }
+                assert_eq!(rendered, expected, "{} in blocks of {}", file, block_size);
+            }
+
+            // Again from the beginning
+            let mut renderer = renderer(file);
+            renderer.render_block(500).unwrap();
+            renderer.restart();
+            assert_eq!(renderer.render_block(FRAMES).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_render_to_wav() {
+        let expected_path = temp_path("render_expected");
+        let path = temp_path("render_streamed");
+
+        for export in [
+            WavExport::default(),
+            WavExport::new()
+                .with_format(WavFormat::Int24)
+                .with_channels(2)
+                .with_dither(true),
+            WavExport::new().with_normalization(Normalization::Peak(-1.0)),
+            WavExport::new()
+                .with_format(WavFormat::Float32)
+                .with_normalization(Normalization::Loudness(-18.0)),
+        ] {
+            let expected_report = export
+                .write(&full_render("poli4.yaml"), &expected_path, 44100)
+                .unwrap();
+            let report = renderer("poli4.yaml")
+                .with_block_size(1024)
+                .render_to_wav(FRAMES as u64, &path, &export)
+                .unwrap();
+
+            assert_eq!(report, expected_report);
+            assert_eq!(fs::read(&path).unwrap(), fs::read(&expected_path).unwrap());
+        }
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&expected_path).unwrap();
+    }
+
+    #[test]
+    fn test_progress() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        let path = temp_path("render_progress");
+
+        renderer("fm.yaml")
+            .with_block_size(4000)
+            .with_progress(move |progress| sink.lock().unwrap().push(progress))
+            .render_to_wav(
+                FRAMES as u64,
+                &path,
+                &WavExport::new().with_normalization(Normalization::Peak(0.0)),
+            )
+            .unwrap();
+
+        let reports = reports.lock().unwrap();
+        let rendered: Vec<(u64, usize)> = reports.iter().map(|p| (p.rendered, p.pass)).collect();
+        assert_eq!(
+            rendered,
+            [
+                (4000, 1),
+                (8000, 1),
+                (10000, 1),
+                (4000, 2),
+                (8000, 2),
+                (10000, 2)
+            ]
+        );
+        assert_eq!(reports[2].fraction(), 0.5);
+        assert_eq!(reports[5].fraction(), 1.0);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cancel() {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&cancel);
+        let path = temp_path("render_cancel");
+
+        let result = renderer("fm.yaml")
+            .with_block_size(1000)
+            .with_cancel_flag(Arc::clone(&cancel))
+            .with_progress(move |progress| {
+                if progress.rendered >= 3000 {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            })
+            .render_to_wav(FRAMES as u64, &path, &WavExport::default());
+
+        assert!(matches!(result, Err(RenderError::Cancelled)));
+        // No incomplete file is left behind
+        assert!(!path.exists());
+    }
+
+    /// Outputs the beat position of the transport it plays along.
+    struct BeatProbe {
+        beat_position: f64,
+    }
+
+    impl Module for BeatProbe {
+        fn behavior(&mut self, _in_data: f32, _time: f64) -> f32 {
+            self.beat_position as f32
+        }
+
+        fn get_parameters(&self) -> Option<Vec<&crate::module::Parameter>> {
+            None
+        }
+
+        fn get_parameters_mutable(&mut self) -> Option<Vec<&mut crate::module::Parameter>> {
+            None
+        }
+
+        fn update_transport(&mut self, transport: &crate::module::TransportState) {
+            self.beat_position = transport.beat_position;
+        }
+
+        fn get_name(&self) -> String {
+            "Beat probe".to_string()
+        }
+    }
+
+    #[test]
+    fn test_transport() {
+        let patch = || {
+            let mut patch = Patch::new();
+            let probe = BeatProbe {
+                beat_position: -1.0,
+            };
+            patch.add_module(0, Box::new(probe)).unwrap();
+            patch.set_output(0).unwrap();
+            patch.set_tempo(30.0).unwrap();
+            patch
+        };
+
+        // Half a beat per second
+        let expected = patch().render(4000, 1000).unwrap();
+        assert_eq!(expected[0], 0.0);
+        assert!((expected[2000] - 1.0).abs() < 1e-6);
+
+        let mut renderer = OfflineRenderer::from_patch(patch(), 1000).unwrap();
+        let mut rendered = renderer.render_block(1500).unwrap();
+        rendered.extend(renderer.render_block(2500).unwrap());
+        assert_eq!(rendered, expected);
+    }
+}
@@ -0,0 +1,565 @@
+use crate::document::{Format, Map, Value};
+use crate::layout_yaml::{fill_buffer, YamlParsingError, YAML_VERSION};
+use crate::module::{Module, TimeSignature, Transport, DEFAULT_AUX_MAX, DEFAULT_AUX_MIN};
+use crate::module_registry::real_node;
+use simplelog::info;
+use std::collections::{HashMap, HashSet};
+
+/// A module of the chain, together with the modules feeding it.
+pub(crate) struct ChainCell {
+    pub(crate) from_module: Option<i64>,
+    pub(crate) module: Box<dyn Module>,
+    pub(crate) auxiliaries: Vec<AuxRoute>,
+}
+
+/// Routes the output of a module to a [parameter](struct@crate::module::Parameter) of another
+/// one, as the `auxiliaries` of a layout do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuxRoute {
+    /// ID of the module feeding the auxiliary.
+    pub from_module: i64,
+    /// Tag of the parameter updated by the auxiliary.
+    pub linked_with: String,
+    pub max: Option<f32>,
+    pub min: Option<f32>,
+}
+
+impl AuxRoute {
+    pub fn new(from_module: i64, linked_with: &str) -> Self {
+        Self {
+            from_module,
+            linked_with: linked_with.to_string(),
+            max: None,
+            min: None,
+        }
+    }
+
+    pub fn with_max(mut self, max: f32) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    pub fn with_min(mut self, min: f32) -> Self {
+        self.min = Some(min);
+        self
+    }
+}
+
+/// A **patch** is a synthesizer held in memory: its modules, identified by their IDs, and how
+/// they are wired. It is what a [Layout](struct@crate::Layout) is loaded into, though it can also
+/// be built by hand and then [saved](fn@Patch::to_yaml) as a layout.
+///
+/// Every module of a patch must describe itself through
+/// [`get_layout_config`](fn@Module::get_layout_config) to be saved, as the bundled modules do.
+/// # Example
+/// ```
+/// # use lion_synth::prelude::*;
+/// # use lion_synth::{AuxRoute, Patch};
+/// let mut patch = Patch::new();
+///
+/// let carrier = OscillatorBuilder::new().build().unwrap();
+/// let modulator = OscillatorBuilder::new().with_frequency(220.0).build().unwrap();
+/// patch.add_module(0, Box::new(Sum2InBuilder::new().build().unwrap())).unwrap();
+/// patch.add_module(1, Box::new(carrier)).unwrap();
+/// patch.add_module(2, Box::new(modulator)).unwrap();
+///
+/// patch.set_input(0, 1).unwrap();
+/// patch.add_auxiliary(0, AuxRoute::new(2, "in2")).unwrap();
+/// patch.set_output(0).unwrap();
+///
+/// let yaml = patch.to_yaml().unwrap();
+/// ```
+#[derive(Default)]
+pub struct Patch {
+    pub(crate) modules: HashMap<i64, ChainCell>,
+    pub(crate) output: Option<i64>,
+    pub(crate) tempo: Option<f32>,
+    pub(crate) time_signature: Option<TimeSignature>,
+}
+
+impl Patch {
+    /// An empty patch, with no modules at all.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a module, not wired to any other one yet.
+    pub fn add_module(&mut self, id: i64, module: Box<dyn Module>) -> Result<(), YamlParsingError> {
+        if self.modules.contains_key(&id) {
+            return Err(YamlParsingError::DuplicatedID(id));
+        }
+
+        self.modules.insert(
+            id,
+            ChainCell {
+                from_module: None,
+                module,
+                auxiliaries: Vec::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Feeds the output of module `from` to the input of module `id`, as `input-from` does in a
+    /// layout.
+    pub fn set_input(&mut self, id: i64, from: i64) -> Result<(), YamlParsingError> {
+        self.get_cell_mutable(id)?.from_module = Some(from);
+        Ok(())
+    }
+
+    /// Routes an auxiliary to a parameter of module `id`.
+    pub fn add_auxiliary(&mut self, id: i64, aux: AuxRoute) -> Result<(), YamlParsingError> {
+        self.get_cell_mutable(id)?.auxiliaries.push(aux);
+        Ok(())
+    }
+
+    /// Sets the module linked to the Operating System output, the last one of the chain.
+    pub fn set_output(&mut self, id: i64) -> Result<(), YamlParsingError> {
+        self.get_cell_mutable(id)?;
+        self.output = Some(id);
+        Ok(())
+    }
+
+    pub fn set_tempo(&mut self, tempo: f32) -> Result<(), YamlParsingError> {
+        if !(tempo > 0.0 && tempo.is_finite()) {
+            return Err(YamlParsingError::InvalidTempo(tempo as f64));
+        }
+
+        self.tempo = Some(tempo);
+        Ok(())
+    }
+
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) {
+        self.time_signature = Some(time_signature);
+    }
+
+    /// IDs of every module of the patch, in ascending order.
+    pub fn get_ids(&self) -> Vec<i64> {
+        let mut ids: Vec<i64> = self.modules.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn get_module(&self, id: i64) -> Option<&dyn Module> {
+        self.modules.get(&id).map(|cell| cell.module.as_ref())
+    }
+
+    /// Retrieves a module given its ID, so its parameters can be changed before saving or
+    /// rendering the patch.
+    pub fn get_module_mutable(&mut self, id: i64) -> Option<&mut (dyn Module + 'static)> {
+        self.modules.get_mut(&id).map(|cell| cell.module.as_mut())
+    }
+
+    /// ID of the module feeding the input of module `id`, if any.
+    pub fn get_input(&self, id: i64) -> Option<i64> {
+        self.modules.get(&id).and_then(|cell| cell.from_module)
+    }
+
+    pub fn get_auxiliaries(&self, id: i64) -> &[AuxRoute] {
+        match self.modules.get(&id) {
+            Some(cell) => &cell.auxiliaries,
+            None => &[],
+        }
+    }
+
+    pub fn get_output(&self) -> Option<i64> {
+        self.output
+    }
+
+    pub fn get_tempo(&self) -> Option<f32> {
+        self.tempo
+    }
+
+    pub fn get_time_signature(&self) -> Option<TimeSignature> {
+        self.time_signature
+    }
+
+    /// Writes the patch as a YAML layout of the latest version, with the current values of the
+    /// parameters of every module. Loading it back results in an identical patch.
+    /// # Expected errors
+    /// * A module that does not implement [`get_layout_config`](fn@Module::get_layout_config).
+    pub fn to_yaml(&self) -> Result<String, YamlParsingError> {
+        self.to_document(Format::Yaml)
+    }
+
+    /// Same as [`to_yaml`](fn@Patch::to_yaml), writing the layout in any of the supported
+    /// formats.
+    pub fn to_document(&self, format: Format) -> Result<String, YamlParsingError> {
+        let mut layout: Vec<Value> = Vec::new();
+
+        for id in self.get_ids() {
+            let cell = &self.modules[&id];
+            let (module_type, config) = cell
+                .module
+                .get_layout_config()
+                .ok_or(YamlParsingError::NotSerializable(id))?;
+
+            let mut module = Map::new();
+            module.insert("id", Value::Integer(id));
+            module.insert("type", Value::String(module_type));
+            if self.output == Some(id) {
+                module.insert("os-out", Value::Boolean(true));
+            }
+            if let Some(from) = cell.from_module {
+                module.insert("input-from", Value::Integer(from));
+            }
+            if !config.is_null() {
+                module.insert("config", config);
+            }
+            if !cell.auxiliaries.is_empty() {
+                let auxiliaries = cell.auxiliaries.iter().map(aux_node).collect();
+                module.insert("auxiliaries", Value::List(auxiliaries));
+            }
+
+            layout.push(Value::Map(Map::from_iter([("module", Value::Map(module))])));
+        }
+
+        let mut doc = Map::new();
+        let version = YAML_VERSION.parse().unwrap_or_default();
+        doc.insert("version", Value::Real(version));
+        if let Some(tempo) = self.tempo {
+            doc.insert("tempo", real_node(tempo));
+        }
+        if let Some(signature) = self.time_signature {
+            let signature = format!("{}/{}", signature.beats_per_bar, signature.beat_unit);
+            doc.insert("time-signature", Value::String(signature));
+        }
+        doc.insert("layout", Value::List(layout));
+
+        format.emit(&Value::Map(doc))
+    }
+
+    /// Writes the routing of the patch as a [Graphviz](https://graphviz.org) DOT graph. Audio
+    /// links (`input-from`) are drawn as solid edges, and auxiliaries as dashed ones labelled with
+    /// their `linked-with` tag and the range they are mapped to.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph patch {\n    rankdir=LR;\n    node [shape=box];\n");
+        dot.push_str("    output [label=\"OS output\", shape=doublecircle];\n");
+
+        let ids = self.get_ids();
+        for id in &ids {
+            let name = dot_escape(&self.modules[id].module.get_name());
+            dot.push_str(&format!("    m{} [label=\"{}: {}\"];\n", id, id, name));
+        }
+        for id in &ids {
+            let cell = &self.modules[id];
+            if let Some(from) = cell.from_module {
+                dot.push_str(&format!("    m{} -> m{};\n", from, id));
+            }
+            for aux in &cell.auxiliaries {
+                dot.push_str(&format!(
+                    "    m{} -> m{} [style=dashed, label=\"{}\\n[{:?}, {:?}]\"];\n",
+                    aux.from_module,
+                    id,
+                    dot_escape(&aux.linked_with),
+                    aux.min.unwrap_or(DEFAULT_AUX_MIN),
+                    aux.max.unwrap_or(DEFAULT_AUX_MAX)
+                ));
+            }
+        }
+        if let Some(output) = self.output {
+            dot.push_str(&format!("    m{} -> output;\n", output));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the patch offline, returning the first `buffer_length` samples of its output. The
+    /// modules are moved into the render, so the patch is consumed.
+    pub fn render(
+        mut self,
+        buffer_length: usize,
+        sample_rate: i32,
+    ) -> Result<Vec<f32>, YamlParsingError> {
+        let output = self.check_links()?;
+        let transport = self.offline_transport(sample_rate)?;
+
+        info!("<b>Filling buffer:</>\n");
+        fill_buffer(
+            &mut self.modules,
+            &mut HashSet::new(),
+            output,
+            buffer_length,
+            sample_rate,
+            &transport,
+        )
+    }
+
+    /// Sets the tempo and the time signature of the patch to the transport, leaving the rest as it
+    /// is. Values set by hand have not been checked yet.
+    pub(crate) fn configure_transport(
+        &self,
+        transport: &mut Transport,
+    ) -> Result<(), YamlParsingError> {
+        if let Some(tempo) = self.tempo {
+            transport
+                .set_tempo(tempo)
+                .map_err(|_| YamlParsingError::InvalidTempo(tempo as f64))?;
+        }
+        if let Some(time_signature) = self.time_signature {
+            transport
+                .set_time_signature(time_signature)
+                .map_err(|err| YamlParsingError::WrongFormat {
+                    field_name: String::from("time-signature"),
+                    supported_format: err,
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// The transport offline renders play along: started from the beginning, at the tempo and
+    /// in the time signature of the patch.
+    pub(crate) fn offline_transport(
+        &self,
+        sample_rate: i32,
+    ) -> Result<Transport, YamlParsingError> {
+        let mut transport = Transport::new(sample_rate);
+        self.configure_transport(&mut transport)?;
+        transport.start();
+        Ok(transport)
+    }
+
+    /// Checks every module referenced exists, returning the ID of the output module.
+    pub(crate) fn check_links(&self) -> Result<i64, YamlParsingError> {
+        let output = self.output.ok_or(YamlParsingError::MissingOpSysOutput)?;
+
+        for cell in self.modules.values() {
+            let aux_from = cell.auxiliaries.iter().map(|aux| aux.from_module);
+            for id in cell.from_module.into_iter().chain(aux_from) {
+                if !self.modules.contains_key(&id) {
+                    return Err(YamlParsingError::ModuleNotFound(id));
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn get_cell_mutable(&mut self, id: i64) -> Result<&mut ChainCell, YamlParsingError> {
+        self.modules
+            .get_mut(&id)
+            .ok_or(YamlParsingError::ModuleNotFound(id))
+    }
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn aux_node(aux: &AuxRoute) -> Value {
+    let mut node = Map::new();
+    node.insert("from-id", Value::Integer(aux.from_module));
+    node.insert("linked-with", Value::String(aux.linked_with.clone()));
+    if let Some(max) = aux.max {
+        node.insert("max", real_node(max));
+    }
+    if let Some(min) = aux.min {
+        node.insert("min", real_node(min));
+    }
+
+    Value::Map(Map::from_iter([("aux", Value::Map(node))]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundled_modules::{OscillatorBuilder, Sum2InBuilder};
+    use crate::layout_yaml::Layout;
+    use crate::module::Parameter;
+    use crate::module_registry::ModuleRegistry;
+
+    fn load(yaml: &str) -> Patch {
+        Layout::from_document(yaml)
+            .to_patch(&ModuleRegistry::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for file in [
+            "fm.yaml",
+            "poli2.yaml",
+            "poli3.yaml",
+            "poli4.yaml",
+            "poli4phased.yaml",
+            "pulse_passthrough.yaml",
+        ] {
+            let layout = Layout::from_path(format!("layouts/{}", file)).unwrap();
+            let patch = layout.to_patch(&ModuleRegistry::default()).unwrap();
+            let yaml = patch.to_yaml().unwrap();
+
+            // load -> save -> load
+            let reloaded = load(&yaml);
+            assert_eq!(
+                reloaded.to_yaml().unwrap(),
+                yaml,
+                "Unstable save of {}",
+                file
+            );
+            assert_eq!(
+                reloaded.render(2000, 44100).unwrap(),
+                patch.render(2000, 44100).unwrap(),
+                "Render of {} differs once saved",
+                file
+            );
+        }
+    }
+
+    #[test]
+    fn test_built_patch() {
+        let sum = Sum2InBuilder::new()
+            .with_name("master".to_string())
+            .with_gain(0.5)
+            .build()
+            .unwrap();
+        let carrier = OscillatorBuilder::new()
+            .with_frequency(220.0)
+            .build()
+            .unwrap();
+        let modulator = OscillatorBuilder::new()
+            .with_name("modulator")
+            .with_amplitude(0.25)
+            .build()
+            .unwrap();
+
+        let mut patch = Patch::new();
+        patch.add_module(0, Box::new(sum)).unwrap();
+        patch.add_module(1, Box::new(carrier)).unwrap();
+        patch.add_module(2, Box::new(modulator)).unwrap();
+        assert!(matches!(
+            patch.add_module(2, Box::new(OscillatorBuilder::new().build().unwrap())),
+            Err(YamlParsingError::DuplicatedID(2))
+        ));
+
+        patch.set_input(0, 1).unwrap();
+        patch
+            .add_auxiliary(0, AuxRoute::new(2, "in2").with_max(0.5).with_min(-0.5))
+            .unwrap();
+        patch.set_output(0).unwrap();
+        patch.set_tempo(90.0).unwrap();
+        patch.set_time_signature(TimeSignature {
+            beats_per_bar: 3,
+            beat_unit: 4,
+        });
+        patch
+            .get_module_mutable(1)
+            .unwrap()
+            .get_parameter_mutable("frequency")
+            .unwrap()
+            .set(330.0);
+
+        let reloaded = load(&patch.to_yaml().unwrap());
+        assert_eq!(reloaded.get_ids(), vec![0, 1, 2]);
+        assert_eq!(reloaded.get_output(), Some(0));
+        assert_eq!(reloaded.get_input(0), Some(1));
+        assert_eq!(
+            reloaded.get_auxiliaries(0),
+            &[AuxRoute::new(2, "in2").with_max(0.5).with_min(-0.5)]
+        );
+        assert_eq!(reloaded.get_tempo(), Some(90.0));
+        assert_eq!(
+            reloaded.get_time_signature(),
+            Some(TimeSignature {
+                beats_per_bar: 3,
+                beat_unit: 4
+            })
+        );
+
+        let carrier = reloaded.get_module(1).unwrap();
+        assert_eq!(
+            carrier.get_parameter("frequency").unwrap().get_value(),
+            330.0
+        );
+        assert_eq!(
+            reloaded.get_module(2).unwrap().get_name(),
+            "modulator Oscillator"
+        );
+
+        assert_eq!(
+            reloaded.render(2000, 44100).unwrap(),
+            patch.render(2000, 44100).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut patch = Patch::new();
+        let sum = Sum2InBuilder::new()
+            .with_name("\"master\"".to_string())
+            .build()
+            .unwrap();
+        patch.add_module(0, Box::new(sum)).unwrap();
+        let carrier = OscillatorBuilder::new()
+            .with_name("carrier")
+            .build()
+            .unwrap();
+        patch.add_module(1, Box::new(carrier)).unwrap();
+        let lfo = OscillatorBuilder::new().with_name("lfo").build().unwrap();
+        patch.add_module(2, Box::new(lfo)).unwrap();
+
+        patch.set_input(0, 1).unwrap();
+        patch
+            .add_auxiliary(0, AuxRoute::new(2, "in2").with_max(0.5))
+            .unwrap();
+        patch
+            .add_auxiliary(
+                1,
+                AuxRoute::new(2, "frequency")
+                    .with_max(440.0)
+                    .with_min(220.0),
+            )
+            .unwrap();
+        patch.set_output(0).unwrap();
+
+        assert_eq!(
+            patch.to_dot(),
+            "digraph patch {\n    rankdir=LR;\n    node [shape=box];\n    \
+            output [label=\"OS output\", shape=doublecircle];\n    \
+            m0 [label=\"0: \\\"master\\\" Sum 2in\"];\n    \
+            m1 [label=\"1: carrier Oscillator\"];\n    \
+            m2 [label=\"2: lfo Oscillator\"];\n    \
+            m1 -> m0;\n    \
+            m2 -> m0 [style=dashed, label=\"in2\\n[0.0, 0.5]\"];\n    \
+            m2 -> m1 [style=dashed, label=\"frequency\\n[220.0, 440.0]\"];\n    \
+            m0 -> output;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_not_serializable() {
+        struct Silence {}
+
+        impl Module for Silence {
+            fn behavior(&mut self, _in_data: f32, _time: f64) -> f32 {
+                0.0
+            }
+
+            fn get_parameters(&self) -> Option<Vec<&Parameter>> {
+                None
+            }
+
+            fn get_parameters_mutable(&mut self) -> Option<Vec<&mut Parameter>> {
+                None
+            }
+
+            fn get_name(&self) -> String {
+                "Silence".to_string()
+            }
+        }
+
+        let mut patch = Patch::new();
+        patch.add_module(4, Box::new(Silence {})).unwrap();
+        patch.set_output(4).unwrap();
+
+        assert!(matches!(
+            patch.to_yaml(),
+            Err(YamlParsingError::NotSerializable(4))
+        ));
+        assert!(matches!(
+            patch.set_input(5, 4),
+            Err(YamlParsingError::ModuleNotFound(5))
+        ));
+    }
+}
@@ -0,0 +1,774 @@
+//! Flattens the sub-patches of a layout into plain modules.
+//!
+//! A **sub-patch** is a group of modules defined once, under `sub-patches`, and used as a single
+//! module as many times as needed, naming it as the `type` of the module:
+//! ```yaml
+//! include:
+//!   - voices.yaml         # more sub-patches, read from another file
+//!
+//! sub-patches:
+//!   phased-voice:
+//!     input: 1            # receives the input of the sub-patch (optional)
+//!     output: 0           # its output is the output of the sub-patch
+//!     inputs:             # auxiliaries the sub-patch can be linked with (optional)
+//!       detune:
+//!         module: 2
+//!         linked-with: frequency
+//!     parameters:         # config fields set from the instances (optional)
+//!       frequency:
+//!         default: 440.0  # (optional)
+//!         targets: [1.frequency, 2.frequency]
+//!     layout:
+//!       - module:
+//!           id: 0
+//!           ...
+//!
+//! layout:
+//!   - module:
+//!       id: 3
+//!       type: phased-voice
+//!       config:
+//!         frequency: 523.25
+//! ```
+//! The IDs of the modules of a sub-patch are local to it. When flattened, the output module
+//! takes the ID of the instance, so the rest of the layout can refer to it as a regular module,
+//! while the other modules get new IDs, greater than any other in the layout.
+
+use crate::document::{Format, Map, Value};
+use crate::layout_yaml::YamlParsingError;
+use crate::module_registry::resolve_path;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// How deep sub-patches may be nested into each other, mostly to catch recursive definitions.
+const MAX_DEPTH: usize = 16;
+
+/// A module of the flattened layout.
+pub(crate) struct FlatModule {
+    /// Path of the module in the document, such as `layout[2].module`, used to report errors.
+    pub(crate) path: String,
+    pub(crate) module: Value,
+    /// The auxiliaries handed over by a sub-patch instance, by index.
+    pub(crate) forwarded: HashMap<usize, ForwardedAux>,
+}
+
+/// An auxiliary of a sub-patch instance handed over to the output of the sub-patch, as it is
+/// linked with none of the inputs exposed. The output must then have the parameter itself.
+#[derive(Clone)]
+pub(crate) struct ForwardedAux {
+    /// Path of the auxiliary in the instance, such as `layout[0].module.auxiliaries[1].aux`.
+    pub(crate) path: String,
+    /// Name of the sub-patch used by the instance.
+    pub(crate) sub_patch: String,
+}
+
+struct SubPatch {
+    path: String,
+    input: Option<i64>,
+    output: i64,
+    /// Module and parameter tag of every auxiliary input, by name.
+    inputs: HashMap<String, (i64, String)>,
+    parameters: Vec<ExposedParameter>,
+    layout: Vec<Value>,
+}
+
+struct ExposedParameter {
+    name: String,
+    default: Option<Value>,
+    /// Module and config field set by the parameter.
+    targets: Vec<(i64, String)>,
+}
+
+/// Replaces every sub-patch instance of the layout by the modules it is made of. Sub-patches are
+/// read from the `sub-patches` of the document and from the files listed in `include`, whose
+/// paths are relative to `base_dir`.
+pub(crate) fn flatten(
+    doc: &Value,
+    layout: &[Value],
+    base_dir: Option<&Path>,
+) -> Result<Vec<FlatModule>, YamlParsingError> {
+    let mut sub_patches = HashMap::new();
+    read_sub_patches(doc, "", base_dir, &mut sub_patches, &mut HashSet::new())?;
+
+    let mut next_id = layout
+        .iter()
+        .filter_map(|item| item["module"]["id"].as_i64())
+        .max()
+        .unwrap_or(-1)
+        + 1;
+
+    let mut flat = Vec::new();
+    for (index, item) in layout.iter().enumerate() {
+        let module = FlatModule {
+            path: format!("layout[{}].module", index),
+            module: item["module"].clone(),
+            forwarded: HashMap::new(),
+        };
+
+        match (
+            sub_patch_of(&module.module, &sub_patches),
+            module.module["id"].as_i64(),
+        ) {
+            (Some(sub_patch), Some(id)) => expand(
+                &module,
+                id,
+                sub_patch,
+                &sub_patches,
+                &mut next_id,
+                &mut flat,
+                0,
+            )?,
+            _ => flat.push(module),
+        }
+    }
+
+    Ok(flat)
+}
+
+fn sub_patch_of<'a>(
+    module: &Value,
+    sub_patches: &'a HashMap<String, SubPatch>,
+) -> Option<&'a SubPatch> {
+    module["type"]
+        .as_str()
+        .and_then(|name| sub_patches.get(name))
+}
+
+/// Reads the sub-patches defined in a document and in the files it includes.
+/// # Arguments
+/// * `prefix` - Added to the paths of the sub-patches, naming the file they come from.
+/// * `visited` - Files already included, so a file is never read twice.
+fn read_sub_patches(
+    doc: &Value,
+    prefix: &str,
+    base_dir: Option<&Path>,
+    sub_patches: &mut HashMap<String, SubPatch>,
+    visited: &mut HashSet<String>,
+) -> Result<(), YamlParsingError> {
+    let fail = |path: String, error| YamlParsingError::in_field(None, path, error);
+
+    let includes = match &doc["include"] {
+        Value::List(includes) => includes.as_slice(),
+        Value::Missing => &[],
+        _ => {
+            return Err(fail(
+                format!("{}include", prefix),
+                YamlParsingError::WrongFormat {
+                    field_name: String::from("include"),
+                    supported_format: String::from("list of files"),
+                },
+            ))
+        }
+    };
+
+    for (index, include) in includes.iter().enumerate() {
+        let include_path = format!("{}include[{}]", prefix, index);
+        let file = match include.as_str() {
+            Some(file) => resolve_path(base_dir, Path::new(file)),
+            None => {
+                return Err(fail(
+                    include_path,
+                    YamlParsingError::WrongFormat {
+                        field_name: String::from("include"),
+                        supported_format: String::from("str"),
+                    },
+                ))
+            }
+        };
+        if !visited.insert(file.display().to_string()) {
+            continue;
+        }
+
+        let text = fs::read_to_string(&file).map_err(|source| {
+            fail(
+                include_path.clone(),
+                YamlParsingError::Io {
+                    path: file.display().to_string(),
+                    source,
+                },
+            )
+        })?;
+        // Included files may be written in any format, whatever the format of the layout
+        let format = Format::from_path(&file).unwrap_or_default();
+        let included = match format.parse(&text) {
+            Ok(included) => included,
+            Err(YamlParsingError::EmptyDocument) => Value::Null,
+            Err(YamlParsingError::Syntax { message, location }) => {
+                return Err(fail(
+                    include_path,
+                    YamlParsingError::Syntax {
+                        message: format!("{}: {}", file.display(), message),
+                        location,
+                    },
+                ))
+            }
+            Err(err) => return Err(fail(include_path, err)),
+        };
+
+        let prefix = format!("{}:", file.display());
+        let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty());
+        read_sub_patches(&included, &prefix, dir, sub_patches, visited)?;
+    }
+
+    let definitions = match &doc["sub-patches"] {
+        Value::Map(definitions) => definitions,
+        _ => return Ok(()),
+    };
+
+    for (name, definition) in definitions.iter() {
+        let name = name.to_string();
+        let path = format!("{}sub-patches.{}", prefix, name);
+
+        if sub_patches.contains_key(&name) {
+            return Err(fail(
+                path,
+                invalid(&name, String::from("it is defined more than once")),
+            ));
+        }
+
+        let sub_patch = read_sub_patch(&name, path, definition)?;
+        sub_patches.insert(name, sub_patch);
+    }
+
+    Ok(())
+}
+
+/// Reads the definition of a sub-patch. Errors are returned with the field they were found in.
+fn read_sub_patch(
+    name: &str,
+    path: String,
+    definition: &Value,
+) -> Result<SubPatch, YamlParsingError> {
+    let fail = |field: String, error| {
+        YamlParsingError::in_field(None, format!("{}.{}", path, field), error)
+    };
+
+    let layout = match &definition["layout"] {
+        Value::List(layout) => layout.clone(),
+        _ => {
+            return Err(fail(
+                String::from("layout"),
+                invalid(name, String::from("it needs a 'layout' list of modules")),
+            ))
+        }
+    };
+    let ids: HashSet<i64> = layout
+        .iter()
+        .filter_map(|item| item["module"]["id"].as_i64())
+        .collect();
+    let check_id = |field: &str, id: Option<i64>| match id {
+        Some(id) if ids.contains(&id) => Ok(id),
+        _ => Err(fail(
+            field.to_string(),
+            invalid(
+                name,
+                format!("'{}' must be the ID of one of its modules", field),
+            ),
+        )),
+    };
+
+    let output = check_id("output", definition["output"].as_i64())?;
+    let input = match &definition["input"] {
+        Value::Missing => None,
+        input => Some(check_id("input", input.as_i64())?),
+    };
+
+    let mut inputs = HashMap::new();
+    if let Value::Map(definitions) = &definition["inputs"] {
+        for (input_name, input) in definitions.iter() {
+            let field = format!("inputs.{}", input_name);
+            let module = check_id(&format!("{}.module", field), input["module"].as_i64())?;
+            let tag = match input["linked-with"].as_str() {
+                Some(tag) => tag.to_string(),
+                None => {
+                    return Err(fail(
+                        format!("{}.linked-with", field),
+                        YamlParsingError::MissingAuxTag,
+                    ))
+                }
+            };
+            inputs.insert(input_name.to_string(), (module, tag));
+        }
+    }
+
+    let mut parameters = Vec::new();
+    if let Value::Map(definitions) = &definition["parameters"] {
+        for (parameter_name, parameter) in definitions.iter() {
+            let field = format!("parameters.{}.targets", parameter_name);
+            let targets = match &parameter["targets"] {
+                Value::List(targets) => targets,
+                _ => {
+                    return Err(fail(
+                        field,
+                        YamlParsingError::MissingField(String::from("targets")),
+                    ))
+                }
+            };
+
+            let mut parsed_targets = Vec::new();
+            for target in targets {
+                let target = match target {
+                    Value::String(target) => target.clone(),
+                    // `1.frequency` is read as a string, but `1.5` would be a number
+                    Value::Real(target) => target.to_string(),
+                    _ => String::new(),
+                };
+                let parsed = target
+                    .split_once('.')
+                    .and_then(|(id, key)| Some((id.parse::<i64>().ok()?, key.to_string())));
+
+                match parsed {
+                    Some((id, key)) if ids.contains(&id) => parsed_targets.push((id, key)),
+                    _ => {
+                        return Err(fail(
+                            field,
+                            invalid(
+                                name,
+                                format!(
+                                    "'{}' is not a valid target, such as '1.frequency'",
+                                    target
+                                ),
+                            ),
+                        ))
+                    }
+                }
+            }
+
+            parameters.push(ExposedParameter {
+                name: parameter_name.to_string(),
+                default: match &parameter["default"] {
+                    Value::Missing => None,
+                    default => Some(default.clone()),
+                },
+                targets: parsed_targets,
+            });
+        }
+    }
+
+    Ok(SubPatch {
+        path,
+        input,
+        output,
+        inputs,
+        parameters,
+        layout,
+    })
+}
+
+/// Adds the modules of a sub-patch instance to the flattened layout.
+/// # Arguments
+/// * `instance` - The module using the sub-patch.
+/// * `id` - The ID given to the output of the sub-patch.
+/// * `next_id` - The first ID not used yet by any module.
+fn expand(
+    instance: &FlatModule,
+    id: i64,
+    sub_patch: &SubPatch,
+    sub_patches: &HashMap<String, SubPatch>,
+    next_id: &mut i64,
+    flat: &mut Vec<FlatModule>,
+    depth: usize,
+) -> Result<(), YamlParsingError> {
+    let fail = |path: String, error| YamlParsingError::in_field(Some(id), path, error);
+    let instance_path = instance.path.as_str();
+    let instance_forwarded = &instance.forwarded;
+    let instance = &instance.module;
+    let name = instance["type"].as_str().unwrap_or_default();
+
+    if depth >= MAX_DEPTH {
+        return Err(fail(
+            format!("{}.type", instance_path),
+            invalid(
+                name,
+                String::from("sub-patches are nested too deep, is it using itself?"),
+            ),
+        ));
+    }
+
+    // Every config field of the instance must be an exposed parameter
+    if let Value::Map(config) = &instance["config"] {
+        for key in config.keys() {
+            if !sub_patch.parameters.iter().any(|p| p.name == *key) {
+                return Err(fail(
+                    format!("{}.config.{}", instance_path, key),
+                    YamlParsingError::UnknownParameter(key.to_string()),
+                ));
+            }
+        }
+    }
+
+    // Same as a regular module, whose input would go nowhere
+    if sub_patch.input.is_none() && instance_field(instance, "input-from").is_some() {
+        return Err(fail(
+            format!("{}.input-from", instance_path),
+            invalid(name, String::from("it has no input")),
+        ));
+    }
+
+    let mut ids: HashMap<i64, i64> = HashMap::new();
+    for local_id in sub_patch
+        .layout
+        .iter()
+        .filter_map(|item| item["module"]["id"].as_i64())
+    {
+        if local_id == sub_patch.output {
+            ids.insert(local_id, id);
+        } else {
+            ids.insert(local_id, *next_id);
+            *next_id += 1;
+        }
+    }
+
+    for (index, item) in sub_patch.layout.iter().enumerate() {
+        let path = format!("{}.layout[{}].module", sub_patch.path, index);
+        let module = &item["module"];
+        let (local_id, mut node) = match (module["id"].as_i64(), module) {
+            (Some(local_id), Value::Map(node)) => (local_id, node.clone()),
+            _ => {
+                // Reported as a regular module
+                flat.push(FlatModule {
+                    path,
+                    module: module.clone(),
+                    forwarded: HashMap::new(),
+                });
+                continue;
+            }
+        };
+        let module_id = ids[&local_id];
+        node.insert("id", Value::Integer(module_id));
+
+        // ROUTING
+        if node.remove("os-out").is_some() {
+            return Err(fail(
+                format!("{}.os-out", path),
+                invalid(
+                    name,
+                    String::from("its modules can not be linked to the Operating System"),
+                ),
+            ));
+        }
+        if local_id == sub_patch.output {
+            if let Some(os_out) = instance_field(instance, "os-out") {
+                node.insert("os-out", os_out);
+            }
+        }
+
+        if let Some(from) = node.get("input-from").and_then(Value::as_i64) {
+            let from = map_id(&ids, from, name)
+                .map_err(|err| fail(format!("{}.input-from", path), err))?;
+            node.insert("input-from", Value::Integer(from));
+        }
+        if Some(local_id) == sub_patch.input {
+            match instance_field(instance, "input-from") {
+                Some(from) => node.insert("input-from", from),
+                None => node.remove("input-from"),
+            };
+        }
+
+        let mut forwarded = HashMap::new();
+        let mut auxiliaries = match node.remove("auxiliaries") {
+            Some(Value::List(auxiliaries)) => auxiliaries,
+            _ => Vec::new(),
+        };
+        for (aux_index, aux) in auxiliaries.iter_mut().enumerate() {
+            if let Value::Map(aux) = aux {
+                if let Some(Value::Map(aux)) = aux.get_mut("aux") {
+                    // Read as the loader does, which truncates reals
+                    let from = match aux.get("from-id") {
+                        Some(Value::Integer(from)) => Some(*from),
+                        Some(Value::Real(from)) => Some(*from as i64),
+                        _ => None,
+                    };
+                    if let Some(from) = from {
+                        let from = map_id(&ids, from, name).map_err(|err| {
+                            let field = format!("{}.auxiliaries[{}].aux.from-id", path, aux_index);
+                            fail(field, err)
+                        })?;
+                        aux.insert("from-id", Value::Integer(from));
+                    }
+                }
+            }
+        }
+        if let Value::List(instance_auxiliaries) = &instance["auxiliaries"] {
+            for (aux_index, aux) in instance_auxiliaries.iter().enumerate() {
+                let tag = aux["aux"]["linked-with"].as_str().unwrap_or_default();
+                let target = sub_patch
+                    .inputs
+                    .get(tag)
+                    .map(|(target, inner_tag)| (*target, inner_tag.as_str()));
+
+                match target {
+                    Some((target, inner_tag)) if target == local_id => {
+                        let mut aux = aux.clone();
+                        if let Value::Map(item) = &mut aux {
+                            if let Some(Value::Map(aux)) = item.get_mut("aux") {
+                                aux.insert("linked-with", Value::String(inner_tag.into()));
+                            }
+                        }
+                        auxiliaries.push(aux);
+                    }
+                    // Not an input of the sub-patch, so linked straight to its output
+                    None if local_id == sub_patch.output => {
+                        // Reported where it was written, even if handed over several times
+                        let origin =
+                            instance_forwarded
+                                .get(&aux_index)
+                                .cloned()
+                                .unwrap_or_else(|| ForwardedAux {
+                                    path: format!(
+                                        "{}.auxiliaries[{}].aux",
+                                        instance_path, aux_index
+                                    ),
+                                    sub_patch: name.to_string(),
+                                });
+                        forwarded.insert(auxiliaries.len(), origin);
+                        auxiliaries.push(aux.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if !auxiliaries.is_empty() {
+            node.insert("auxiliaries", Value::List(auxiliaries));
+        }
+
+        // PARAMETERS
+        for parameter in &sub_patch.parameters {
+            let value = match &instance["config"][parameter.name.as_str()] {
+                Value::Missing => parameter.default.clone(),
+                value => Some(value.clone()),
+            };
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+
+            for (_, field) in parameter.targets.iter().filter(|(t, _)| *t == local_id) {
+                if !matches!(node.get("config"), Some(Value::Map(_))) {
+                    node.insert("config", Value::Map(Map::new()));
+                }
+                if let Some(Value::Map(config)) = node.get_mut("config") {
+                    config.insert(field.as_str(), value.clone());
+                }
+            }
+        }
+
+        let node = FlatModule {
+            path,
+            module: Value::Map(node),
+            forwarded,
+        };
+        match sub_patch_of(&node.module, sub_patches) {
+            Some(inner) => expand(
+                &node,
+                module_id,
+                inner,
+                sub_patches,
+                next_id,
+                flat,
+                depth + 1,
+            )?,
+            None => flat.push(node),
+        }
+    }
+
+    Ok(())
+}
+
+fn instance_field(instance: &Value, name: &str) -> Option<Value> {
+    match &instance[name] {
+        Value::Missing => None,
+        value => Some(value.clone()),
+    }
+}
+
+fn map_id(ids: &HashMap<i64, i64>, local_id: i64, name: &str) -> Result<i64, YamlParsingError> {
+    ids.get(&local_id).copied().ok_or_else(|| {
+        invalid(
+            name,
+            format!("module {} is not one of its modules", local_id),
+        )
+    })
+}
+
+fn invalid(name: &str, reason: String) -> YamlParsingError {
+    YamlParsingError::InvalidSubPatch {
+        name: name.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: &str = "\
+sub-patches:
+  gain-stage:
+    input: 1
+    output: 0
+    inputs:
+      level:
+        module: 0
+        linked-with: out_gain
+    parameters:
+      gain:
+        default: 0.5
+        targets: [0.out-gain]
+    layout:
+      - module:
+          id: 0
+          type: sum
+          input-from: 1
+          config:
+            input-amount: 2
+          auxiliaries:
+            - aux:
+                from-id: 2
+                linked-with: in2
+      - module:
+          id: 1
+          type: pass_through
+      - module:
+          id: 2
+          type: osc_debug
+  double-stage:
+    input: 1
+    output: 0
+    layout:
+      - module:
+          id: 0
+          type: gain-stage
+          input-from: 1
+      - module:
+          id: 1
+          type: gain-stage
+layout:
+  - module:
+      id: 0
+      type: gain-stage
+      os-out: true
+      input-from: 1
+      config:
+        gain: 0.8
+      auxiliaries:
+        - aux:
+            from-id: 2
+            linked-with: level
+  - module:
+      id: 1
+      type: oscillator
+  - module:
+      id: 2
+      type: double-stage
+";
+
+    fn flatten_str(yaml: &str) -> Result<Vec<FlatModule>, YamlParsingError> {
+        let doc = Format::Yaml.parse(yaml).unwrap();
+        let layout = doc["layout"].as_list().cloned().unwrap_or_default();
+        flatten(&doc, &layout, None)
+    }
+
+    fn find(flat: &[FlatModule], id: i64) -> &Value {
+        &flat
+            .iter()
+            .find(|entry| entry.module["id"].as_i64() == Some(id))
+            .unwrap()
+            .module
+    }
+
+    #[test]
+    fn test_flatten() {
+        let flat = flatten_str(LAYOUT).unwrap_or_else(|err| panic!("{}", err));
+
+        // 3 modules per gain stage, one of them used twice by the double stage, plus the oscillator
+        assert_eq!(flat.len(), 10);
+        let mut ids: Vec<i64> = flat
+            .iter()
+            .map(|e| e.module["id"].as_i64().unwrap())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (0..10).collect::<Vec<_>>());
+
+        // The output of the sub-patch keeps the ID and the routing of the instance
+        let output = find(&flat, 0);
+        assert_eq!(output["type"].as_str(), Some("sum"));
+        assert_eq!(output["os-out"].as_bool(), Some(true));
+        assert_eq!(output["config"]["out-gain"].as_f64(), Some(0.8));
+        let auxiliaries = output["auxiliaries"].as_list().unwrap();
+        assert_eq!(auxiliaries.len(), 2);
+        assert_eq!(auxiliaries[1]["aux"]["from-id"].as_i64(), Some(2));
+        assert_eq!(
+            auxiliaries[1]["aux"]["linked-with"].as_str(),
+            Some("out_gain")
+        );
+
+        // The input of the sub-patch is fed by the input of the instance
+        let input = find(&flat, output["input-from"].as_i64().unwrap());
+        assert_eq!(input["type"].as_str(), Some("pass_through"));
+        assert_eq!(input["input-from"].as_i64(), Some(1));
+
+        // Nested sub-patches take the default values
+        let nested = find(&flat, 2);
+        assert_eq!(nested["type"].as_str(), Some("sum"));
+        assert_eq!(nested["config"]["out-gain"].as_f64(), Some(0.5));
+        assert_eq!(flat[0].path, "sub-patches.gain-stage.layout[0].module");
+    }
+
+    #[test]
+    fn test_errors() {
+        let kind = |yaml: &str| flatten_str(yaml).err().unwrap();
+
+        let err = kind(&LAYOUT.replace("gain: 0.8", "volume: 0.8"));
+        assert!(matches!(err.kind(), YamlParsingError::UnknownParameter(_)));
+        assert_eq!(err.module_id(), Some(0));
+        assert_eq!(err.field(), Some("layout[0].module.config.volume"));
+
+        let err = kind(&LAYOUT.replace("type: pass_through", "type: double-stage"));
+        assert!(matches!(
+            err.kind(),
+            YamlParsingError::InvalidSubPatch { .. }
+        ));
+
+        let err = kind(&LAYOUT.replace("    output: 0\n    inputs", "    output: 7\n    inputs"));
+        assert!(matches!(
+            err.kind(),
+            YamlParsingError::InvalidSubPatch { .. }
+        ));
+        assert_eq!(err.field(), Some("sub-patches.gain-stage.output"));
+
+        let err =
+            kind(&LAYOUT.replace("type: osc_debug", "type: osc_debug\n          os-out: true"));
+        assert!(matches!(
+            err.kind(),
+            YamlParsingError::InvalidSubPatch { .. }
+        ));
+
+        // Reals are IDs as well
+        let err = kind(&LAYOUT.replace(
+            "from-id: 2\n                linked-with: in2",
+            "from-id: 7.0\n                linked-with: in2",
+        ));
+        assert!(matches!(
+            err.kind(),
+            YamlParsingError::InvalidSubPatch { .. }
+        ));
+        assert_eq!(
+            err.field(),
+            Some("sub-patches.gain-stage.layout[0].module.auxiliaries[0].aux.from-id")
+        );
+        let flat = flatten_str(&LAYOUT.replace(
+            "from-id: 2\n                linked-with: in2",
+            "from-id: 2.0\n                linked-with: in2",
+        ))
+        .unwrap();
+        let output = find(&flat, 0);
+        let inner = output["auxiliaries"][0]["aux"]["from-id"].as_i64().unwrap();
+        assert_eq!(find(&flat, inner)["type"].as_str(), Some("osc_debug"));
+
+        let err = kind(&format!("include:\n  - missing.yaml\n{}", LAYOUT));
+        assert!(matches!(err.kind(), YamlParsingError::Io { .. }));
+        assert_eq!(err.field(), Some("include[0]"));
+    }
+}
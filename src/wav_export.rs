@@ -0,0 +1,819 @@
+//! Writes signals to wav files.
+//!
+//! A [WavExport] tells how the file is written: the [sample format](WavFormat), the amount of
+//! channels, an optional [normalisation](Normalization), dithering and what to do with the
+//! samples out of range ([ClipPolicy]). The signals of the synthesizer are mono, they are copied
+//! to every channel of the file unless given already interleaved.
+//! # Example
+//! ```no_run
+//! use lion_synth::{buffer_from_yaml, Normalization, WavExport, WavFormat};
+//!
+//! let buffer = buffer_from_yaml("fm.yaml", 44100, 44100).unwrap();
+//! let report = WavExport::new()
+//!     .with_format(WavFormat::Int24)
+//!     .with_channels(2)
+//!     .with_normalization(Normalization::Loudness(-14.0))
+//!     .with_dither(true)
+//!     .write(&buffer, "/tmp/fm.wav", 44100)
+//!     .unwrap();
+//! println!("Gain applied: {} dB", 20.0 * report.gain.log10());
+//! ```
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use thiserror::Error;
+
+/// Seed of the dither noise when none is given, so the same signal is always written the same.
+pub const DEFAULT_DITHER_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Samples above this level are bent by [ClipPolicy::SoftClip].
+const SOFT_CLIP_KNEE: f32 = 0.8;
+
+/// Loudness of the blocks ignored by the absolute gate of the loudness measurement, in LUFS.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks quieter than the ungated loudness minus this amount of LU are ignored.
+const RELATIVE_GATE: f64 = 10.0;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ExportError {
+    #[error("Could not write the wav file: {0}")]
+    Wav(#[from] hound::Error),
+    #[error("Could not create the output directory: {0}")]
+    Io(#[from] io::Error),
+    #[error("A wav file needs at least one channel")]
+    NoChannels,
+    #[error("{len} samples cannot be split into frames of {channels} channels")]
+    IncompleteFrame { len: usize, channels: u16 },
+    #[error("Sample {position} is out of range ({value}) and clipping is not allowed")]
+    Clipped { position: u64, value: f32 },
+}
+
+/// Format of the samples written to the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavFormat {
+    #[default]
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl WavFormat {
+    fn bits_per_sample(self) -> u16 {
+        match self {
+            Self::Int16 => 16,
+            Self::Int24 => 24,
+            Self::Int32 | Self::Float32 => 32,
+        }
+    }
+
+    fn is_float(self) -> bool {
+        self == Self::Float32
+    }
+}
+
+/// Gain applied to the whole signal before writing it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// Brings the highest sample to the given level, in dBFS.
+    Peak(f32),
+    /// Brings the integrated loudness to the given level, in LUFS, as measured by ITU-R BS.1770.
+    /// Every channel has the same weight.
+    Loudness(f32),
+}
+
+/// What happens to the samples outside of `[-1.0, 1.0]`, once normalised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipPolicy {
+    /// Cuts the samples at full scale.
+    #[default]
+    Clamp,
+    /// Bends the samples above 0.8 smoothly towards full scale.
+    SoftClip,
+    /// Stops the export with [ExportError::Clipped].
+    Fail,
+}
+
+/// How a signal is written to a wav file. The default writes mono, 16 bits integers, clamped and
+/// neither normalised nor dithered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WavExport {
+    format: WavFormat,
+    channels: u16,
+    normalization: Option<Normalization>,
+    dither: bool,
+    clip_policy: ClipPolicy,
+    seed: u64,
+}
+
+impl Default for WavExport {
+    fn default() -> Self {
+        Self {
+            format: WavFormat::default(),
+            channels: 1,
+            normalization: None,
+            dither: false,
+            clip_policy: ClipPolicy::default(),
+            seed: DEFAULT_DITHER_SEED,
+        }
+    }
+}
+
+/// What was done to the signal while writing it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportReport {
+    pub frames: u64,
+    /// Linear gain applied by the normalisation.
+    pub gain: f32,
+    /// Highest absolute sample after the gain, before clipping.
+    pub peak: f32,
+    /// Amount of samples changed by the clip policy.
+    pub clipped: u64,
+}
+
+impl WavExport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_format(mut self, format: WavFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the amount of channels of the file.
+    pub fn with_channels(mut self, channels: u16) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = Some(normalization);
+        self
+    }
+
+    /// Adds triangular (TPDF) noise of one least significant bit before quantizing the samples to
+    /// integers, trading the quantization distortion for a low noise floor. Float files are not
+    /// dithered.
+    pub fn with_dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    pub fn with_clip_policy(mut self, clip_policy: ClipPolicy) -> Self {
+        self.clip_policy = clip_policy;
+        self
+    }
+
+    /// Sets the seed of the dither noise.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn get_format(&self) -> WavFormat {
+        self.format
+    }
+
+    pub fn get_channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn get_normalization(&self) -> Option<Normalization> {
+        self.normalization
+    }
+
+    /// Writes a mono signal, copied to every channel. The directories of the path are created if
+    /// missing.
+    pub fn write(
+        &self,
+        buffer: &[f32],
+        path: impl AsRef<Path>,
+        sample_rate: i32,
+    ) -> Result<ExportReport, ExportError> {
+        let mut meter = self.meter(1, sample_rate);
+        meter.push(buffer);
+        let gain = meter.gain();
+        self.check_clipping(buffer, 1, gain)?;
+
+        let mut encoder = self.encoder(path, sample_rate, gain)?;
+        encoder.write_mono(buffer)?;
+        encoder.finalize()
+    }
+
+    /// Writes a signal whose frames hold a sample for each channel of the file.
+    pub fn write_interleaved(
+        &self,
+        samples: &[f32],
+        path: impl AsRef<Path>,
+        sample_rate: i32,
+    ) -> Result<ExportReport, ExportError> {
+        if self.channels == 0 {
+            return Err(ExportError::NoChannels);
+        }
+        if !samples.len().is_multiple_of(self.channels as usize) {
+            return Err(ExportError::IncompleteFrame {
+                len: samples.len(),
+                channels: self.channels,
+            });
+        }
+
+        let mut meter = self.meter(self.channels, sample_rate);
+        meter.push(samples);
+        let gain = meter.gain();
+        self.check_clipping(samples, self.channels, gain)?;
+
+        let mut encoder = self.encoder(path, sample_rate, gain)?;
+        encoder.write_interleaved(samples)?;
+        encoder.finalize()
+    }
+
+    /// Opens a file to write a signal piece by piece, applying the given gain. The normalisation
+    /// needs the whole signal and is left to the caller.
+    pub fn encoder(
+        &self,
+        path: impl AsRef<Path>,
+        sample_rate: i32,
+        gain: f32,
+    ) -> Result<WavEncoder, ExportError> {
+        if self.channels == 0 {
+            return Err(ExportError::NoChannels);
+        }
+
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let spec = hound::WavSpec {
+            channels: self.channels,
+            sample_rate: sample_rate as u32,
+            bits_per_sample: self.format.bits_per_sample(),
+            sample_format: match self.format.is_float() {
+                true => hound::SampleFormat::Float,
+                false => hound::SampleFormat::Int,
+            },
+        };
+
+        Ok(WavEncoder {
+            writer: hound::WavWriter::create(path, spec)?,
+            format: self.format,
+            channels: self.channels,
+            gain,
+            clip_policy: self.clip_policy,
+            dither: (self.dither && !self.format.is_float()).then(|| Dither::new(self.seed)),
+            frames: 0,
+            peak: 0.0,
+            clipped: 0,
+        })
+    }
+
+    /// Measures a signal of the given amount of channels for the normalisation. A mono signal is
+    /// measured as copied to every channel of the file.
+    pub(crate) fn meter(&self, channels: u16, sample_rate: i32) -> SignalMeter {
+        SignalMeter {
+            normalization: self.normalization,
+            peak: 0.0,
+            loudness: LoudnessMeter::new(channels, sample_rate),
+            copies: match channels {
+                1 => self.channels.max(1),
+                _ => 1,
+            },
+        }
+    }
+
+    /// Fails before anything is written if a sample would be clipped when not allowed.
+    fn check_clipping(&self, samples: &[f32], channels: u16, gain: f32) -> Result<(), ExportError> {
+        if self.clip_policy != ClipPolicy::Fail {
+            return Ok(());
+        }
+
+        match samples
+            .iter()
+            .position(|sample| (sample * gain).abs() > 1.0)
+        {
+            Some(index) => Err(ExportError::Clipped {
+                position: (index / channels as usize) as u64,
+                value: samples[index] * gain,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes a signal to a wav file piece by piece, as configured by the [WavExport] it was
+/// obtained from. The file is complete once [finalized](WavEncoder::finalize).
+pub struct WavEncoder {
+    writer: hound::WavWriter<BufWriter<File>>,
+    format: WavFormat,
+    channels: u16,
+    gain: f32,
+    clip_policy: ClipPolicy,
+    dither: Option<Dither>,
+    frames: u64,
+    peak: f32,
+    clipped: u64,
+}
+
+impl WavEncoder {
+    /// Writes mono samples, copying each one to every channel.
+    pub fn write_mono(&mut self, samples: &[f32]) -> Result<(), ExportError> {
+        for sample in samples {
+            for _ in 0..self.channels {
+                self.write_sample(*sample)?;
+            }
+            self.frames += 1;
+        }
+        Ok(())
+    }
+
+    /// Writes frames holding a sample for each channel. Frames may not be split between calls.
+    pub fn write_interleaved(&mut self, samples: &[f32]) -> Result<(), ExportError> {
+        if !samples.len().is_multiple_of(self.channels as usize) {
+            return Err(ExportError::IncompleteFrame {
+                len: samples.len(),
+                channels: self.channels,
+            });
+        }
+
+        for frame in samples.chunks(self.channels as usize) {
+            for sample in frame {
+                self.write_sample(*sample)?;
+            }
+            self.frames += 1;
+        }
+        Ok(())
+    }
+
+    /// Amount of frames written so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Completes the header of the file.
+    pub fn finalize(self) -> Result<ExportReport, ExportError> {
+        self.writer.finalize()?;
+        Ok(ExportReport {
+            frames: self.frames,
+            gain: self.gain,
+            peak: self.peak,
+            clipped: self.clipped,
+        })
+    }
+
+    fn write_sample(&mut self, sample: f32) -> Result<(), ExportError> {
+        let sample = sample * self.gain;
+        self.peak = self.peak.max(sample.abs());
+
+        let sample = if sample.abs() <= 1.0 {
+            sample
+        } else {
+            self.clipped += 1;
+            match self.clip_policy {
+                ClipPolicy::Clamp => sample.clamp(-1.0, 1.0),
+                ClipPolicy::SoftClip => sample,
+                ClipPolicy::Fail => {
+                    return Err(ExportError::Clipped {
+                        position: self.frames,
+                        value: sample,
+                    })
+                }
+            }
+        };
+        let sample = match self.clip_policy {
+            ClipPolicy::SoftClip => soft_clip(sample),
+            _ => sample,
+        };
+
+        if self.format.is_float() {
+            self.writer.write_sample(sample)?;
+            return Ok(());
+        }
+
+        let max = ((1_i64 << (self.format.bits_per_sample() - 1)) - 1) as f64;
+        let noise = match &mut self.dither {
+            Some(dither) => dither.next(),
+            None => 0.0,
+        };
+        let value = (sample as f64 * max + noise).round().clamp(-max - 1.0, max);
+        self.writer.write_sample(value as i32)?;
+
+        Ok(())
+    }
+}
+
+/// Highest absolute value of a signal.
+pub fn peak(samples: &[f32]) -> f32 {
+    samples
+        .iter()
+        .fold(0.0, |peak: f32, sample| peak.max(sample.abs()))
+}
+
+/// Integrated loudness of a signal in LUFS, following ITU-R BS.1770-4: the K-weighted signal is
+/// measured in blocks of 400 milliseconds overlapping by 75%, ignoring the silent and the quiet
+/// blocks. The samples of the frames are interleaved and every channel has the same weight.
+/// Signals shorter than a block are measured as a single block, silence as negative infinity.
+pub fn integrated_loudness(samples: &[f32], channels: u16, sample_rate: i32) -> f32 {
+    let mut meter = LoudnessMeter::new(channels, sample_rate);
+    meter.push(samples);
+    meter.loudness()
+}
+
+/// Measures a signal piece by piece to find the gain of the normalisation, giving the same gain
+/// whether the signal is measured at once or not.
+pub(crate) struct SignalMeter {
+    normalization: Option<Normalization>,
+    peak: f32,
+    loudness: LoudnessMeter,
+    /// Times a mono signal is copied in the file.
+    copies: u16,
+}
+
+impl SignalMeter {
+    pub(crate) fn push(&mut self, samples: &[f32]) {
+        match self.normalization {
+            Some(Normalization::Peak(_)) => self.peak = self.peak.max(peak(samples)),
+            Some(Normalization::Loudness(_)) => self.loudness.push(samples),
+            None => (),
+        }
+    }
+
+    /// Linear gain bringing the signal measured to the level of the normalisation.
+    pub(crate) fn gain(&self) -> f32 {
+        match self.normalization {
+            Some(Normalization::Peak(level)) => peak_gain(self.peak, level),
+            // The channels hold the same signal, each one adding up to the loudness
+            Some(Normalization::Loudness(level)) => {
+                let copies = 10.0 * (self.copies as f32).log10();
+                loudness_gain(self.loudness.loudness() + copies, level)
+            }
+            None => 1.0,
+        }
+    }
+}
+
+/// The loudness measurement of [integrated_loudness], fed piece by piece. Only the energy of
+/// every 100 milliseconds is kept, a block being made of four of them.
+struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    step: usize,
+    /// Energy of the K-weighted signal of every step, all channels added up.
+    steps: Vec<f64>,
+    current: f64,
+    /// Frames of the current step.
+    frames: usize,
+    /// Incomplete frame left by the last samples pushed.
+    pending: Vec<f32>,
+}
+
+impl LoudnessMeter {
+    fn new(channels: u16, sample_rate: i32) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            channels,
+            filters: (0..channels)
+                .map(|_| k_weighting(sample_rate as f64))
+                .collect(),
+            step: ((0.1 * sample_rate as f64) as usize).max(1),
+            steps: Vec::new(),
+            current: 0.0,
+            frames: 0,
+            pending: Vec::with_capacity(channels),
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        for sample in samples {
+            self.pending.push(*sample);
+            if self.pending.len() < self.channels {
+                continue;
+            }
+
+            for (channel, filters) in self.filters.iter_mut().enumerate() {
+                let mut value = self.pending[channel] as f64;
+                for filter in filters.iter_mut() {
+                    value = filter.process(value);
+                }
+                self.current += value * value;
+            }
+            self.pending.clear();
+
+            self.frames += 1;
+            if self.frames == self.step {
+                self.steps.push(self.current);
+                self.current = 0.0;
+                self.frames = 0;
+            }
+        }
+    }
+
+    fn loudness(&self) -> f32 {
+        let block = 4 * self.step;
+        let powers: Vec<f64> = if self.steps.len() >= 4 {
+            self.steps
+                .windows(4)
+                .map(|steps| steps.iter().sum::<f64>() / block as f64)
+                .collect()
+        } else {
+            let frames = self.steps.len() * self.step + self.frames;
+            if frames == 0 {
+                return f32::NEG_INFINITY;
+            }
+            vec![(self.steps.iter().sum::<f64>() + self.current) / frames as f64]
+        };
+
+        let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+        let mean = |powers: &[f64]| powers.iter().sum::<f64>() / powers.len() as f64;
+
+        let powers: Vec<f64> = powers
+            .into_iter()
+            .filter(|power| loudness(*power) > ABSOLUTE_GATE)
+            .collect();
+        if powers.is_empty() {
+            return f32::NEG_INFINITY;
+        }
+
+        let gate = loudness(mean(&powers)) - RELATIVE_GATE;
+        let powers: Vec<f64> = powers
+            .into_iter()
+            .filter(|power| loudness(*power) > gate)
+            .collect();
+
+        loudness(mean(&powers)) as f32
+    }
+}
+
+fn peak_gain(peak: f32, level: f32) -> f32 {
+    match peak > 0.0 {
+        true => 10_f32.powf(level / 20.0) / peak,
+        false => 1.0,
+    }
+}
+
+fn loudness_gain(loudness: f32, level: f32) -> f32 {
+    match loudness.is_finite() {
+        true => 10_f32.powf((level - loudness) / 20.0),
+        false => 1.0,
+    }
+}
+
+/// Bends the samples above the knee towards full scale, leaving the ones below untouched.
+fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= SOFT_CLIP_KNEE {
+        return sample;
+    }
+
+    let range = 1.0 - SOFT_CLIP_KNEE;
+    let bent = SOFT_CLIP_KNEE + range * ((level - SOFT_CLIP_KNEE) / range).tanh();
+    bent.copysign(sample)
+}
+
+/// Triangular noise between -1 and 1, from a xorshift generator.
+struct Dither {
+    state: u64,
+}
+
+impl Dither {
+    fn new(seed: u64) -> Self {
+        // The generator is stuck at 0
+        Self { state: seed.max(1) }
+    }
+
+    fn uniform(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    fn next(&mut self) -> f64 {
+        self.uniform() - self.uniform()
+    }
+}
+
+/// A second order filter, in direct form I.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    inputs: [f64; 2],
+    outputs: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]],
+            inputs: [0.0; 2],
+            outputs: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.inputs[0] + self.b[2] * self.inputs[1]
+            - self.a[0] * self.outputs[0]
+            - self.a[1] * self.outputs[1];
+        self.inputs = [input, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+/// The K-weighting filters of BS.1770 for any sample rate: a high shelf modelling the head,
+/// followed by a high pass. The analog prototypes are the ones of libebur128, which give back the
+/// coefficients of the standard at 48 kHz.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let shelf = {
+        let (gain, q, frequency) = (
+            3.999_843_853_973_347,
+            0.707_175_236_955_419_6,
+            1_681.974_450_955_533,
+        );
+        let k = (std::f64::consts::PI * frequency / sample_rate).tan();
+        let high = 10_f64.powf(gain / 20.0);
+        let band = high.powf(0.499_666_774_154_541_6);
+        Biquad::new(
+            [
+                high + band * k / q + k * k,
+                2.0 * (k * k - high),
+                high - band * k / q + k * k,
+            ],
+            [
+                1.0 + k / q + k * k,
+                2.0 * (k * k - 1.0),
+                1.0 - k / q + k * k,
+            ],
+        )
+    };
+    let high_pass = {
+        let (q, frequency) = (0.500_327_037_323_877_3, 38.135_470_876_024_44);
+        let k = (std::f64::consts::PI * frequency / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::new(
+            [a0, -2.0 * a0, a0],
+            [a0, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k],
+        )
+    };
+
+    [shelf, high_pass]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("lion_synth_{}_{}.wav", name, std::process::id()))
+    }
+
+    fn sine(amplitude: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 48000.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_formats() {
+        let buffer = vec![0.0, 0.5, -0.5, 1.0, -1.0];
+        let path = temp_path("formats");
+
+        WavExport::new()
+            .with_format(WavFormat::Int24)
+            .write(&buffer, &path, 44100)
+            .unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, 24);
+        let samples: Vec<i32> = reader.samples::<i32>().map(Result::unwrap).collect();
+        assert_eq!(samples, [0, 4194304, -4194304, 8388607, -8388607]);
+
+        WavExport::new()
+            .with_format(WavFormat::Float32)
+            .with_channels(2)
+            .write(&buffer, &path, 44100)
+            .unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.spec().sample_format, hound::SampleFormat::Float);
+        let samples: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+        assert_eq!(
+            samples,
+            [0.0, 0.0, 0.5, 0.5, -0.5, -0.5, 1.0, 1.0, -1.0, -1.0]
+        );
+
+        // Frames must be complete
+        let export = WavExport::new().with_channels(2);
+        assert!(matches!(
+            export.write_interleaved(&buffer, &path, 44100),
+            Err(ExportError::IncompleteFrame {
+                len: 5,
+                channels: 2
+            })
+        ));
+        assert!(matches!(
+            WavExport::new()
+                .with_channels(0)
+                .write(&buffer, &path, 44100),
+            Err(ExportError::NoChannels)
+        ));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_clip_policy() {
+        let buffer = vec![0.5, 2.0, -3.0];
+        let path = temp_path("clip");
+
+        let report = WavExport::new().write(&buffer, &path, 44100).unwrap();
+        assert_eq!(report.clipped, 2);
+        assert_eq!(report.peak, 3.0);
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let samples: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+        assert_eq!(samples, [16384, i16::MAX, -i16::MAX]);
+
+        WavExport::new()
+            .with_format(WavFormat::Float32)
+            .with_clip_policy(ClipPolicy::SoftClip)
+            .write(&buffer, &path, 44100)
+            .unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let samples: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+        assert_eq!(samples[0], 0.5);
+        assert!(samples[1] > 0.99 && samples[1] < 1.0);
+        // Far above full scale the curve is flat
+        assert!(samples[2] < -0.99 && samples[2] >= -1.0);
+
+        fs::remove_file(&path).unwrap();
+
+        // Nothing is written when clipping is not allowed
+        let result = WavExport::new()
+            .with_clip_policy(ClipPolicy::Fail)
+            .write(&buffer, &path, 44100);
+        assert!(matches!(
+            result,
+            Err(ExportError::Clipped { position: 1, .. })
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_normalization() {
+        let buffer = sine(0.25, 48000);
+        let path = temp_path("normalization");
+
+        let report = WavExport::new()
+            .with_format(WavFormat::Float32)
+            .with_normalization(Normalization::Peak(-6.0))
+            .write(&buffer, &path, 48000)
+            .unwrap();
+        assert!((report.peak - 10_f32.powf(-6.0 / 20.0)).abs() < 1e-4);
+
+        // A full scale sine of 1 kHz in a single channel is at -3 LUFS
+        assert!((integrated_loudness(&sine(1.0, 48000), 1, 48000) + 3.01).abs() < 0.05);
+        assert_eq!(
+            integrated_loudness(&[0.0; 100], 1, 48000),
+            f32::NEG_INFINITY
+        );
+
+        let report = WavExport::new()
+            .with_format(WavFormat::Float32)
+            .with_channels(2)
+            .with_normalization(Normalization::Loudness(-14.0))
+            .write(&buffer, &path, 48000)
+            .unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let samples: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+        assert!((integrated_loudness(&samples, 2, 48000) + 14.0).abs() < 0.05);
+        // Both channels add up, so the sine is louder than -14 LUFS in stereo
+        assert!(report.gain < 1.0);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_dither() {
+        let buffer = vec![0.0; 1000];
+        let path = temp_path("dither");
+
+        let export = WavExport::new().with_dither(true);
+        export.write(&buffer, &path, 44100).unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let first: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+        // Silence turns into noise of one bit at most
+        assert!(first.iter().all(|sample| sample.abs() <= 1));
+        assert!(first.iter().any(|sample| *sample != 0));
+
+        // The noise is the same for the same seed
+        export.write(&buffer, &path, 44100).unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let second: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+        assert_eq!(first, second);
+
+        fs::remove_file(&path).unwrap();
+    }
+}